@@ -28,12 +28,24 @@ pub enum Sort {
 
 impl Default for Sort { fn default() -> Self { Self::NoSort } }
 
+/// The format in which to output contact information.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// Aligned columns of fields.
+    Table,
+    /// One JSON object per line.
+    Ndjson,
+}
+
+impl Default for OutputFormat { fn default() -> Self { Self::Table } }
+
 #[derive(Debug)]
 pub enum Command {
     Search,
     Alias(String),
     New(String),
     Edit(Either<String, PathBuf>),
+    Count,
 }
 
 impl Default for Command { fn default() -> Self { Self::Search } }
@@ -53,7 +65,20 @@ pub struct Options {
     pub filter: Option<Query>,
     // Maximum number of records to list
     pub limit: Option<u32>,
+    // Drop contacts with the same value for this field as an earlier contact.
+    pub dedup: Option<String>,
+    // Print the contacts in groups that share the same value for this field.
+    pub group_by: Option<String>,
+    // The fields to set on a contact created by the `new` command.
+    pub fields: Vec<(String, String)>,
     pub sort: Sort,
+    pub format: OutputFormat,
+    // Overrides the field_separator configuration value.
+    pub separator: Option<String>,
+    // Write the results to this file rather than stdout.
+    pub output: Option<PathBuf>,
+    // List each file that could not be read, rather than only a count.
+    pub verbose: bool,
 }
 
 impl Options {
@@ -98,14 +123,60 @@ impl Options {
                 "--filter" => {
                     enforce_len(&args, 2, "No query filter provided")?;
 
-                    let filter = match opts.filter {
-                        Some(f) => {
-                            let new_filter = Query::from_str(&args[1])?;
-                            Some(f.merge_with(new_filter))
-                        },
-                        None => Some(Query::from_str(&args[1])?)
+                    opts.add_filter(Query::from_str(&args[1])?);
+                    args = &mut args[2..];
+                },
+                "--filter-file" => {
+                    use std::fs;
+
+                    enforce_len(args, 2, "No query filter file provided")?;
+
+                    let text = fs::read_to_string(&args[1]).map_err(|e| {
+                        anyhow!("Cannot read filter file {}: {}", args[1], e)
+                    })?;
+
+                    opts.add_filter(Query::from_str(&text)?);
+                    args = &mut args[2..];
+                },
+                "--format" => {
+                    enforce_len(args, 2, "Missing output format")?;
+
+                    opts.format = match args[1].as_ref() {
+                        "table" => OutputFormat::Table,
+                        "ndjson" => OutputFormat::Ndjson,
+                        f => return Err(
+                            anyhow!("Unknown output format: {}", f)
+                        ),
                     };
-                    opts.filter = filter;
+                    args = &mut args[2..];
+                },
+                "--separator" => {
+                    enforce_len(args, 2, "Missing the field separator")?;
+                    opts.separator = Some(parse_separator(&args[1])?);
+                    args = &mut args[2..];
+                },
+                "--output" => {
+                    enforce_len(args, 2, "Missing the output path")?;
+                    opts.output = Some(PathBuf::from(&args[1]));
+                    args = &mut args[2..];
+                },
+                "--verbose" => {
+                    opts.verbose = true;
+                    args = &mut args[1..];
+                },
+                "--set" => {
+                    enforce_len(args, 2, "Missing the field to set")?;
+                    opts.fields.push(parse_field_assignment(&args[1])?);
+                    args = &mut args[2..];
+                },
+                "--group-by" => {
+                    enforce_len(args, 2, "Missing the grouping field")?;
+                    opts.group_by = Some(args[1].to_owned());
+                    args = &mut args[2..];
+                },
+                "--dedup" => {
+                    enforce_len(args, 2, "Missing the deduplication field")?;
+                    opts.dedup = Some(args[1].to_owned());
                     args = &mut args[2..];
                 },
                 "--limit" => {
@@ -139,6 +210,9 @@ impl Options {
 
                         opts.cmd_or_alias = Command::New(args[1].to_owned());
                         args = &mut args[2..];
+                    } else if args[0] == "count" {
+                        opts.cmd_or_alias = Command::Count;
+                        args = &mut args[1..];
                     } else if args[0] == "edit" {
                         enforce_len(&args, 2,
                             concat!("Expected a contact name or path for the ",
@@ -192,10 +266,33 @@ impl Options {
             }
         }
 
+        if ! opts.fields.is_empty()
+            && ! matches!(opts.cmd_or_alias, Command::New(_))
+        {
+            return Err(anyhow!("--set can only be used with the new command"));
+        }
+
         Ok(opts)
     }
 }
 
+/// Parse a `--set` option's "Field=value" argument.
+fn parse_field_assignment(arg: &str) -> anyhow::Result<(String, String)> {
+    use upim_note::Note;
+
+    let (field, value) = arg.split_once('=')
+        .ok_or_else(|| anyhow!("Expected Field=value: {}", arg))?;
+    let (field, value) = (field.trim(), value.trim());
+
+    if ! Note::is_valid_attribute_key(field) {
+        Err(anyhow!("Invalid field name: {}", field))
+    } else if ! Note::is_valid_attribute_value(value) {
+        Err(anyhow!("Invalid value for {}: {}", field, value))
+    } else {
+        Ok((field.into(), value.into()))
+    }
+}
+
 /// Errors that can be returned when attempting to perform parameter
 /// substitution in aliases.
 #[derive(Debug)]
@@ -227,6 +324,90 @@ impl From<ParseIntError> for AliasSubstitutionError {
     }
 }
 
+/// Retrieve the filter of the named alias, expanding any aliases it refers
+/// to.
+///
+/// `lookup` retrieves an alias' definition by name; a leading `--filter` in a
+/// definition is ignored. If the first word of a definition is the name of an
+/// alias, that word is replaced by the other alias' filter, with its condition
+/// in parenthesis -- so with `friends = @friend OR @family` and
+/// `local = friends AND City = '$0'`, the filter for `local` is
+/// `(@friend OR @family) AND City = '$0'`. Parameters are not substituted;
+/// see [substitute_alias].
+///
+/// Returns an error if the alias is unknown or refers to itself, directly or
+/// through other aliases.
+pub fn resolve_alias<'a, F>(name: &str, lookup: F) -> anyhow::Result<String>
+    where F: Fn(&str) -> Option<&'a String>,
+{
+    let mut chain = vec![name];
+    let mut tails = vec![];
+    let mut alias = lookup(name)
+        .ok_or_else(|| anyhow!("Unknown alias: {}", name))?;
+
+    let mut filter = loop {
+        let filter = alias.strip_prefix("--filter").unwrap_or(alias).trim();
+        let (first, tail) = filter.split_once(char::is_whitespace)
+            .unwrap_or((filter, ""));
+
+        let next = match lookup(first) {
+            Some(next) => next,
+            None => break filter.to_owned(),
+        };
+
+        if chain.contains(&first) {
+            chain.push(first);
+            return Err(anyhow!("Cyclic alias: {}", chain.join(" -> ")));
+        }
+
+        chain.push(first);
+        tails.push(tail.trim_start());
+        alias = next;
+    };
+
+    for tail in tails.into_iter().rev().filter(|t| ! t.is_empty()) {
+        filter = join_words(&parenthesize_condition(&filter), tail);
+    }
+
+    Ok(filter)
+}
+
+/// Wrap the condition of a filter in parenthesis.
+///
+/// If the filter has no `WHERE` clause, the entire filter is taken to be the
+/// condition.
+fn parenthesize_condition(filter: &str) -> String {
+    use crate::filter::is_within_quotes;
+
+    let upper = filter.to_ascii_uppercase();
+    let clause = upper.match_indices("WHERE")
+        .map(|(i, _)| i)
+        .find(|i| {
+            let end = i + "WHERE".len();
+            let is_word = filter[..*i].ends_with(char::is_whitespace)
+                && filter[end..].starts_with(char::is_whitespace);
+
+            is_word && ! is_within_quotes(filter, *i)
+        });
+
+    match clause {
+        Some(i) => {
+            let (fields, cond) = filter.split_at(i + "WHERE".len());
+            format!("{} ({})", fields, cond.trim())
+        },
+        None => format!("({})", filter),
+    }
+}
+
+/// Join two strings with a space, unless either is empty.
+fn join_words(a: &str, b: &str) -> String {
+    match (a.is_empty(), b.is_empty()) {
+        (_, true) => a.to_owned(),
+        (true, false) => b.to_owned(),
+        (false, false) => format!("{} {}", a, b),
+    }
+}
+
 /// Substitute strings from command-line arguments into the given alias.
 ///
 /// The name of the alias itself should not be passed in `args`.
@@ -278,6 +459,34 @@ impl Options {
         ! matches!(self.cmd_or_alias, Command::Search)
             || self.filter.is_some()
     }
+
+    /// Add a filter, intersecting it with any filter already provided.
+    fn add_filter(&mut self, filter: Query) {
+        self.filter = Some(match self.filter.take() {
+            Some(f) => f.merge_with(filter),
+            None => filter,
+        });
+    }
+}
+
+/// Interpret a field separator given on the command line.
+///
+/// The separator is read like the `field_separator` configuration value, but
+/// quoting it is optional.
+fn parse_separator(sep: &str) -> anyhow::Result<String> {
+    use crate::{config::validate_field_separator, filter::is_quoted};
+
+    if sep.chars().count() < 2 {
+        return Ok(sep.to_owned());
+    }
+
+    let sep = if is_quoted(sep) {
+        sep.to_owned()
+    } else {
+        format!("'{}'", sep)
+    };
+
+    validate_field_separator(&sep).map_err(|e| anyhow!("{}", e))
 }
 
 #[inline]
@@ -413,6 +622,169 @@ mod tests {
         );
     }
 
+    #[test]
+    fn args_filter_file() {
+        use std::{env, fs};
+
+        let path = env::temp_dir().join("upim_contact_filter_file.txt");
+        fs::write(&path, "\
+            # Everyone at work.\n\
+            Name,Phone\n\
+            WHERE 'Employer:Name' = 'Some Company'\n\
+            ").unwrap();
+
+        let args = [
+            "upim-contact",
+            "--filter-file", path.to_str().unwrap(),
+            "--filter", "Name WHERE Name = 'Somebody'",
+        ];
+        let opts = Options::new(args.iter().map(|s| s.to_string()));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(opts.unwrap().filter,
+            Some(Query {
+                select: vec!["Name".into()],
+                condition: Condition::And(Box::new((
+                    Condition::Filter(
+                        "Employer:Name".into(),
+                        FilterOp::EqualTo,
+                        "Some Company".into()
+                    ),
+                    Condition::Filter(
+                        "Name".into(),
+                        FilterOp::EqualTo,
+                        "Somebody".into()
+                    )
+                ))),
+            })
+        );
+
+        let args = ["upim-contact", "--filter-file", "/nonexistent/filter"];
+        assert!(Options::new(args.iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn args_format() {
+        let args = ["upim-contact", "--filter", "Name", "--format", "ndjson"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(opts.format, OutputFormat::Ndjson);
+
+        let args = ["upim-contact", "--filter", "Name"];
+        let args = args.iter().map(|s| s.to_string());
+        assert_eq!(Options::new(args).unwrap().format, OutputFormat::Table);
+
+        let args = ["upim-contact", "--format", "xml"];
+        let args = args.iter().map(|s| s.to_string());
+        assert!(Options::new(args).is_err());
+    }
+
+    #[test]
+    fn args_count() {
+        let args = ["upim-contact", "count", "--filter", "Name WHERE Num > 1"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert!(matches!(opts.cmd_or_alias, Command::Count));
+        assert!(opts.filter.is_some());
+    }
+
+    #[test]
+    fn args_separator() {
+        let sep = |s: &str| {
+            let args = ["upim-contact", "--separator", s, "--filter", "Name"];
+            Options::new(args.iter().map(|s| s.to_string()))
+                .map(|opts| opts.separator.unwrap())
+        };
+
+        assert_eq!(sep("{TAB}").unwrap(), "\t");
+        assert_eq!(sep("'{SPACE}::{SPACE}'").unwrap(), " :: ");
+        assert_eq!(sep(r"\u2713").unwrap(), "✓");
+        assert_eq!(sep(",").unwrap(), ",");
+        assert_eq!(sep("'").unwrap(), "'");
+        assert!(sep(r"\uZZZZ").is_err());
+
+        let args = ["upim-contact", "--filter", "Name"];
+        let args = args.iter().map(|s| s.to_string());
+        assert!(Options::new(args).unwrap().separator.is_none());
+    }
+
+    #[test]
+    fn args_output() {
+        let args = ["upim-contact", "--filter", "Name", "--output", "out.txt"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(opts.output.unwrap(), Path::new("out.txt"));
+
+        let args = ["upim-contact", "--filter", "Name", "--output"];
+        assert!(Options::new(args.iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn args_verbose() {
+        let args = ["upim-contact", "--verbose", "--filter", "Name"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert!(opts.verbose);
+        assert!(opts.filter.is_some());
+    }
+
+    #[test]
+    fn args_dedup() {
+        let args = ["upim-contact", "--dedup", "Name"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+
+        assert_eq!(opts.dedup.as_deref(), Some("Name"));
+    }
+
+    #[test]
+    fn args_new_with_fields() {
+        let args = [
+            "upim-contact", "new", "Jane", "--set", "Phone=123",
+            "--set", "Email = x@y", "--set", "Phone=456",
+        ];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert!(
+            matches!(opts.cmd_or_alias, Command::New(ref n) if n == "Jane")
+        );
+        assert_eq!(opts.fields, [
+            ("Phone".to_string(), "123".to_string()),
+            ("Email".into(), "x@y".into()),
+            ("Phone".into(), "456".into()),
+        ]);
+
+        let invalid = [
+            &["new", "Jane", "--set", "Phone"][..],
+            &["new", "Jane", "--set", "=123"],
+            &["new", "Jane", "--set", "Phone=[123]"],
+            &["new", "Jane", "--set"],
+            &["--set", "Phone=123", "--filter", "Name"],
+        ];
+        for args in invalid.iter() {
+            let args = args.iter().map(|s| s.to_string());
+            assert!(Options::new_from_arguments(args).is_err());
+        }
+    }
+
+    #[test]
+    fn args_group_by() {
+        let args = ["upim-contact", "--group-by", "Employer:Name"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(opts.group_by.as_deref(), Some("Employer:Name"));
+
+        let args = ["upim-contact", "--group-by"];
+        assert!(Options::new(args.iter().map(|s| s.to_string())).is_err());
+    }
+
     #[test]
     fn args_limit() {
         let args = vec!["upim-contact", "--limit", "2"];
@@ -472,6 +844,88 @@ mod tests {
         assert!(Options::new(args).is_err());
     }
 
+    #[test]
+    fn resolve_chained_aliases() {
+        use std::collections::HashMap;
+
+        let aliases = [
+            ("friends", "--filter 'Name,Phone' WHERE @friend"),
+            ("local", "friends AND City = '$0'"),
+            ("close", "--filter local AND @close"),
+        ].iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<String, String>>();
+        let lookup = |name: &str| aliases.get(name);
+
+        assert_eq!(
+            resolve_alias("friends", lookup).unwrap(),
+            "'Name,Phone' WHERE @friend"
+        );
+        assert_eq!(
+            resolve_alias("close", lookup).unwrap(),
+            "'Name,Phone' WHERE ((@friend) AND City = '$0') AND @close"
+        );
+
+        let alias = resolve_alias("local", lookup).unwrap();
+        let args = vec!["Springfield".to_string()];
+        let (len, alias) = substitute_alias(&args, &alias).unwrap();
+
+        assert_eq!(len, 1);
+        assert_eq!(
+            alias,
+            "'Name,Phone' WHERE (@friend) AND City = 'Springfield'"
+        );
+
+        assert!(resolve_alias("nothing", lookup).is_err());
+    }
+
+    #[test]
+    fn resolve_alias_keeps_condition_precedence() {
+        use std::collections::HashMap;
+        use crate::filter::Query;
+
+        let aliases = [
+            ("friends", "Name WHERE @a OR @b"),
+            ("local", "friends AND City = 'x'"),
+        ].iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<String, String>>();
+        let lookup = |name: &str| aliases.get(name);
+
+        let alias = resolve_alias("local", lookup).unwrap();
+        let query = Query::from_str(&alias).unwrap();
+
+        assert_eq!(alias, "Name WHERE (@a OR @b) AND City = 'x'");
+        assert_eq!(query.condition,
+            Condition::And(Box::new((
+                Condition::Or(Box::new((
+                    Condition::Tag("@a".into()),
+                    Condition::Tag("@b".into()),
+                ))),
+                Condition::Filter("City".into(), FilterOp::EqualTo, "x".into()),
+            )))
+        );
+    }
+
+    #[test]
+    fn resolve_cyclic_alias_is_err() {
+        use std::collections::HashMap;
+
+        let aliases = [
+            ("a", "b AND @a"),
+            ("b", "--filter c AND @b"),
+            ("c", "a"),
+            ("d", "d"),
+        ].iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<String, String>>();
+        let lookup = |name: &str| aliases.get(name);
+
+        let err = resolve_alias("a", lookup).unwrap_err();
+        assert_eq!(err.to_string(), "Cyclic alias: a -> b -> c -> a");
+        assert!(resolve_alias("d", lookup).is_err());
+    }
+
     #[test]
     fn substitute_alias_no_substitution() {
         let args = vec!["--limit", "1"];
@@ -1,4 +1,5 @@
 use std::{
+    io::{BufRead, Write},
     path::PathBuf,
     str::FromStr as _,
     fmt,
@@ -57,20 +58,13 @@ pub fn read_config(path: Option<PathBuf>)
         return Err(errors);
     };
 
-    if conf.get_default("default_collection").is_none() {
-        errors.push(
-            ConfigurationError::MissingOption("default_collection".into())
-        );
-    }
-
     match validate_field_separator(conf.get_default("field_separator").unwrap())
     {
         Ok(ref v) => conf = conf.set_default("field_separator", v),
         Err(e) => errors.push(e),
     }
 
-    if let Err(mut errs) = validate_aliases(conf.group_as_dict("Aliases"))
-    {
+    if let Err(mut errs) = validate_aliases(&conf) {
         errors.append(&mut errs);
     }
 
@@ -81,6 +75,63 @@ pub fn read_config(path: Option<PathBuf>)
     }
 }
 
+/// Determine the collection to use: `requested` (from the `-C` option) if
+/// provided, or else the `default_collection`.
+///
+/// If neither is set and `interactive` is true, the configured collections are
+/// listed on `output` and the user chooses one by number or name from `input`.
+/// Otherwise the missing `default_collection` is an error.
+pub fn select_collection<R, W>(
+    requested: Option<&str>,
+    conf: &Config,
+    interactive: bool,
+    input: R,
+    output: &mut W
+) -> anyhow::Result<String>
+    where R: BufRead,
+          W: Write,
+{
+    use anyhow::anyhow;
+
+    if let Some(coll) = requested.or_else(|| {
+        conf.get_default("default_collection").map(|c| c.as_str())
+    }) {
+        return Ok(coll.to_owned());
+    }
+
+    let missing = ConfigurationError::MissingOption(
+        "default_collection".into()
+    );
+
+    let mut collections = conf.variables_in_group("Collections")
+        .collect::<Vec<_>>();
+    collections.sort();
+
+    if ! interactive || collections.is_empty() {
+        return Err(missing.into());
+    }
+
+    writeln!(output, "No collection was selected. Choose a collection:")?;
+    for (i, coll) in collections.iter().enumerate() {
+        writeln!(output, "  {}) {}", i + 1, coll)?;
+    }
+    write!(output, "Collection: ")?;
+    output.flush()?;
+
+    let mut choice = String::new();
+    let mut input = input;
+    input.read_line(&mut choice)?;
+    let choice = choice.trim();
+
+    let selected = match choice.parse::<usize>() {
+        Ok(n) if n > 0 => collections.get(n - 1),
+        _ => collections.iter().find(|c| c.as_str() == choice),
+    };
+
+    selected.map(|c| c.to_string())
+        .ok_or_else(|| anyhow!("Invalid collection: {}", choice))
+}
+
 /// Errors that can occur while reading information from our exteral
 /// environment.
 #[derive(Debug, Clone)]
@@ -117,7 +168,7 @@ impl From<FileError> for ConfigurationError {
 /// - Replaces "{SPACE}" or "{TAB}" with a space or tab, respectively.
 /// - Replaces a Unicode character code in the form \uXXXX with the character
 ///   code itself.
-fn validate_field_separator(val: &str)
+pub(crate) fn validate_field_separator(val: &str)
 -> std::result::Result<String, ConfigurationError> {
     use crate::filter::is_quoted;
 
@@ -149,13 +200,34 @@ fn validate_field_separator(val: &str)
     Ok(val)
 }
 
-fn validate_aliases<'a, I>(aliases: I)
--> std::result::Result::<(), Vec<ConfigurationError>>
-    where I: Iterator<Item = (String, &'a String)>
-{
+/// Ensure every alias resolves to a valid filter.
+///
+/// Aliases that begin with another alias are checked after expanding it.
+fn validate_aliases(conf: &Config)
+-> std::result::Result::<(), Vec<ConfigurationError>> {
+    use crate::args::resolve_alias;
+
     let mut errors = vec![];
 
-    for (alias, commands) in aliases {
+    for alias in conf.variables_in_group("Aliases") {
+        let definition = &conf[("Aliases", alias.as_str())];
+        let own_filter = definition.strip_prefix("--filter")
+            .unwrap_or(definition)
+            .trim();
+
+        let commands = match resolve_alias(alias, |n| conf.get("Aliases", n)) {
+            Ok(filter) if filter != own_filter =>
+                format!("--filter {}", filter),
+            Ok(_) => definition.to_owned(),
+            Err(e) => {
+                errors.push(ConfigurationError::InvalidValue {
+                    data: format!("In alias {}: {}", alias, e),
+                    rules: "Invalid alias".into(),
+                });
+                continue;
+            },
+        };
+
         for part in commands.split("--") {
             // TODO: Validate all possible options
             if let Some(filter) = part.strip_prefix("filter") {
@@ -234,40 +306,101 @@ fn unescape_unicode(s: &str) -> std::result::Result<String, UnescapeError> {
 mod tests {
     use super::*;
 
+    /// Create a configuration with the given aliases.
+    fn alias_config(aliases: &[(&str, &str)]) -> Config {
+        aliases.iter()
+            .fold(Config::default(), |conf, (k, v)| conf.set("Aliases", k, v))
+    }
+
+    #[test]
+    fn select_requested_or_default_collection() {
+        let conf = Config::default()
+            .set("Collections", "work", "/tmp/work")
+            .set_default("default_collection", "personal");
+
+        let select = |requested| select_collection(
+            requested, &conf, true, &b""[..], &mut vec![]
+        ).unwrap();
+
+        assert_eq!(select(Some("work")), "work");
+        assert_eq!(select(None), "personal");
+    }
+
+    #[test]
+    fn prompt_for_collection() {
+        let conf = Config::default()
+            .set("Collections", "work", "/tmp/work")
+            .set("Collections", "personal", "/tmp/personal");
+
+        let mut output = vec![];
+        let by_number =
+            select_collection(None, &conf, true, &b"2\n"[..], &mut output);
+        let by_name = select_collection(
+            None, &conf, true, &b"personal\n"[..], &mut vec![]
+        );
+        let invalid =
+            select_collection(None, &conf, true, &b"3\n"[..], &mut vec![]);
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("  1) personal\n  2) work\n"));
+        assert!(output.ends_with("Collection: "));
+
+        assert_eq!(by_number.unwrap(), "work");
+        assert_eq!(by_name.unwrap(), "personal");
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn missing_collection_is_error_without_terminal() {
+        let conf = Config::default()
+            .set("Collections", "work", "/tmp/work");
+
+        let mut output = vec![];
+        let res =
+            select_collection(None, &conf, false, &b"1\n"[..], &mut output);
+
+        assert!(output.is_empty());
+        assert!(res.unwrap_err().to_string().contains("default_collection"));
+    }
+
     #[test]
     fn validate_default_aliases() {
-        let aliases = vec![
+        let conf = alias_config(&[
             ("first",
-             "--filter 'Name,Phone,Employer:Name' WHERE Name = '$0' --limit 1"
-                .into()),
-            ("second", "--filter 'Name,Phone,Employer:Name' WHERE Name = '$0'"
-                .into()),
-        ];
-        let aliases = aliases.iter()
-            .map(move |(a, b)| (a.to_string(), b));
-
-        assert!(validate_aliases(aliases).is_ok())
+             "--filter 'Name,Phone,Employer:Name' WHERE Name = '$0' --limit 1"),
+            ("second", "--filter 'Name,Phone,Employer:Name' WHERE Name = '$0'"),
+            ("limit", "--limit 5"),
+        ]);
+
+        assert!(validate_aliases(&conf).is_ok())
     }
 
     #[test]
     fn error_when_validating_bad_filter() {
-        let aliases = vec![
+        let conf = alias_config(&[
             ("first",
-             "--filter Name,Phone,Employer:Name' WHERE Name = '$0' --limit 1"
-                .into()),
-            ("second", "--filter Name,Phone,Employer:Name' WHERE Name > '$0'"
-                .into()),
-            ("third", "--filter 'Name,Phone,Employer:Name' WHERE".into()),
-        ];
-
-        let aliases = aliases.iter()
-            .map(|(a, b)| (a.to_string(), b));
+             "--filter Name,Phone,Employer:Name' WHERE Name = '$0' --limit 1"),
+            ("second", "--filter Name,Phone,Employer:Name' WHERE Name > '$0'"),
+            ("third", "--filter 'Name,Phone,Employer:Name' WHERE"),
+        ]);
 
-        let res = validate_aliases(aliases);
+        let res = validate_aliases(&conf);
         assert!(res.is_err());
         assert_eq!(res.unwrap_err().len(), 3);
     }
 
+    #[test]
+    fn validate_chained_aliases() {
+        let conf = alias_config(&[
+            ("friends", "--filter 'Name,Phone' WHERE @friend"),
+            ("local", "friends AND City = '$0'"),
+        ]);
+        assert!(validate_aliases(&conf).is_ok());
+
+        let conf = alias_config(&[("a", "b AND @a"), ("b", "a AND @b")]);
+        assert_eq!(validate_aliases(&conf).unwrap_err().len(), 2);
+    }
+
     #[test]
     fn unescape_unicode_valid_input() {
         let text = r"This is a \u2764!";
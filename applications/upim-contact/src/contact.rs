@@ -1,16 +1,29 @@
 use std::{
-    collections::hash_map::Keys as Groups,
-    path::Path,
+    collections::{HashMap, hash_map::Keys as Groups},
+    io::Write,
+    path::{Path, PathBuf},
     str::FromStr as _,
+    time::SystemTime,
 };
 
 use anyhow::anyhow;
 use multimap::MultiMap;
 use walkdir::WalkDir;
 
+use upim_core::{error::FileError, paths::is_hidden};
 use upim_note::Note;
 
-use crate::filter::{Condition, FilterOp};
+use crate::args::Sort;
+use crate::filter::{
+    Condition,
+    FilterOp,
+    Function,
+    Query,
+    SelectFunction,
+    ANY_FIELD,
+    parse_regex_fields,
+    parse_select_function,
+};
 
 /// Data structure to store the contact information for a person or group.
 ///
@@ -35,6 +48,10 @@ use crate::filter::{Condition, FilterOp};
 pub struct Contact {
     tags: Vec<String>,
     info: MultiMap<String, Note>,
+    /// The group of each note in `info`, in the order they were read.
+    order: Vec<String>,
+    /// The modification time of the file the contact was read from.
+    modified: Option<SystemTime>,
 }
 
 impl Contact {
@@ -73,6 +90,7 @@ impl Contact {
         }
 
         let mut info = MultiMap::new();
+        let mut order = vec![];
         let mut last_group = String::from("default"); // Key for the first note.
 
         for note in notes.iter() {
@@ -81,9 +99,15 @@ impl Contact {
                 last_group = tag[1..].to_lowercase();
             }
             info.insert(last_group.clone(), note.clone());
+            order.push(last_group.clone());
         }
 
-        let contact = Self { tags: tags.to_vec(), info };
+        let contact = Self {
+            tags: tags.to_vec(),
+            info,
+            order,
+            modified: None,
+        };
 
         if contact.name().is_some() {
             Ok(contact)
@@ -92,9 +116,62 @@ impl Contact {
         }
     }
 
+    /// Rebuild the [Note] this contact was created from.
+    ///
+    /// Each group's note is stored as the content of the note before it.
+    /// Attributes keep their order, except that the values of a duplicated
+    /// field are written together.
+    // With the field setters, this lets library code edit and save contacts;
+    // upim-contact itself does not edit contacts yet.
+    #[allow(dead_code)]
+    pub fn to_note(&self) -> Note {
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+
+        let mut notes = self.order.iter()
+            .filter_map(|group| {
+                let idx = seen.entry(group.as_str()).or_default();
+                *idx += 1;
+                self.info.get_vec(group).and_then(|v| v.get(*idx - 1))
+            })
+            .cloned()
+            .collect::<Vec<Note>>();
+
+        // Contact::new reads an empty content as an empty note; we drop it so
+        // the content remains empty.
+        while notes.len() > 1
+            && matches!(notes.last(), Some(n) if is_empty_note(n))
+        {
+            notes.pop();
+        }
+
+        let mut note = notes.pop().unwrap_or_default();
+
+        while let Some(mut parent) = notes.pop() {
+            parent.set_content(&note.to_string());
+            note = parent;
+        }
+
+        for tag in &self.tags {
+            note.insert_tag(tag);
+        }
+        note
+    }
+
     /// Load the file at the given path as a Contact.
+    ///
+    /// The file's modification time is kept; see [Contact::modified].
     pub fn new_from_file(path: &Path) -> anyhow::Result<Self> {
-        Self::new(Note::read_from_file(path)?)
+        let mut contact = Self::new(Note::read_from_file(path)?)?;
+        contact.modified = path.metadata().and_then(|m| m.modified()).ok();
+        Ok(contact)
+    }
+
+    /// Get the modification time of the file the contact was read from.
+    ///
+    /// Returns `None` if the contact was not read from a file, or if the
+    /// platform does not record modification times.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
     }
 
     /// Get the name of this contact.
@@ -109,21 +186,21 @@ impl Contact {
     pub fn name(&self) -> Option<String> {
         let def = self.info.get("default").unwrap();
 
-        match def.get_attribute("Name")
-            .or_else(|| def.get_attribute("Full Name"))
+        match def.get_str("Name")
+            .or_else(|| def.get_str("Full Name"))
         {
             Some(v) => Some(v.into()),
             None => {
-                let given = def.get_attribute("Given Name")
-                    .or_else(|| def.get_attribute("First Name"));
-                let family = def.get_attribute("Family Name")
-                    .or_else(|| def.get_attribute("Last Name"));
+                let given = def.get_str("Given Name")
+                    .or_else(|| def.get_str("First Name"));
+                let family = def.get_str("Family Name")
+                    .or_else(|| def.get_str("Last Name"));
 
                 if given.or(family).is_some() {
                     Some(format!(
                         "{} {}",
-                        given.cloned().unwrap_or_default(),
-                        family.cloned().unwrap_or_default()
+                        given.unwrap_or_default(),
+                        family.unwrap_or_default()
                     ).trim().into())
                 } else {
                     None
@@ -143,6 +220,46 @@ impl Contact {
             .and_then(|g| g.get_attribute(name))
     }
 
+    /// Set the value of a field in the default information group.
+    // Unused by upim-contact; see `to_note`.
+    #[allow(dead_code)]
+    pub fn set_field(&mut self, name: &str, value: &str) {
+        self.set_field_in("default", name, value);
+    }
+
+    /// Set the value of a field in the specified information group.
+    ///
+    /// If the group does not exist, it is created after all other groups.
+    // Unused by upim-contact; see `to_note`.
+    #[allow(dead_code)]
+    pub fn set_field_in(&mut self, group: &str, name: &str, value: &str) {
+        let key = group.to_lowercase();
+
+        if ! self.info.contains_key(&key) {
+            let mut note = Note::default();
+            note.insert_tag(group);
+
+            // The text following the contact's groups belongs to the last
+            // group, so it moves to the new one.
+            let info = &mut self.info;
+            let last = self.order.last()
+                .and_then(|g| info.get_vec_mut(g))
+                .and_then(|notes| notes.last_mut());
+
+            if let Some(last) = last {
+                note.append_content(last.content());
+                last.clear_content();
+            }
+
+            self.info.insert(key.clone(), note);
+            self.order.push(key.clone());
+        }
+
+        if let Some(note) = self.info.get_mut(&key) {
+            note.set_attribute(name, value);
+        }
+    }
+
     /// Return an iterator of the groups defined by the Contact.
     pub fn groups(&self) -> Groups<String, Vec<Note>> {
         self.info.keys()
@@ -160,15 +277,50 @@ impl Contact {
             .unwrap()
     }
 
+    /// Iterate over each group with its fields, as (name, value) pairs.
+    ///
+    /// Groups are listed in the order they appear in the contact, by their
+    /// lowercase names as in [Contact::groups]. If a group appears more than
+    /// once, it is listed once with the fields of every occurrence.
+    // For rendering a full contact; upim-contact prints only selected fields.
+    #[allow(dead_code)]
+    pub fn group_entries(&self)
+    -> impl Iterator<Item = (&str, impl Iterator<Item = (&String, &String)>)> {
+        use upim_core::uniq::Uniq as _;
+
+        self.order.iter()
+            .uniq()
+            .map(move |group| {
+                let fields = self.info.get_vec(group).into_iter()
+                    .flatten()
+                    .flat_map(|note| note.attributes());
+
+                (group.as_str(), fields)
+            })
+    }
+
     pub fn matches(&self, condition: &Condition) -> bool {
         match condition {
             Condition::All => true,
+            Condition::Filter(field, ref op, value) if field == ANY_FIELD => {
+                let mut values = self.info.iter_all()
+                    .flat_map(|(_, notes)| notes.iter())
+                    .flat_map(|note| note.attributes().map(|(_, v)| v));
+
+                // `* NOT value` means that no field holds the value; every
+                // other operator is satisfied by any single field.
+                if *op == FilterOp::Not {
+                    values.all(|v| v != value)
+                } else {
+                    values.any(|v| compare(v, op, value))
+                }
+            },
             Condition::Filter(field, ref op, value) => {
                 let (group, field) = field.split_once(':')
                     .unwrap_or(("default", field));
 
                 if let Some(info) = self.info.get(&group.to_lowercase()) {
-                    let attr = if let Some(f) = info.get_attribute(field) {
+                    let attr = if let Some(f) = info.get_str(field) {
                         f
                     } else {
                         // If the operator is `Not` and the field doesn't exist,
@@ -176,61 +328,47 @@ impl Contact {
                         return *op == FilterOp::Not;
                     };
 
-                    // TODO: On parse errors, return an error instead of false?
-                    match op {
-                        FilterOp::EqualTo => attr == value,
-                        FilterOp::LessThan => {
-                            let attr = if let Ok(a) = attr.parse::<f32>() {
-                                a
-                            } else {
-                                return false;
-                            };
-
-                            value.parse::<f32>()
-                                .map(|v| attr < v)
-                                .unwrap_or(false)
-                        },
-                        FilterOp::LessEq => {
-                            let attr = if let Ok(a) = attr.parse::<f32>() {
-                                a
-                            } else {
-                                return false;
-                            };
-
-                            value.parse::<f32>()
-                                .map(|v| attr <= v)
-                                .unwrap_or(false)
-                        },
-                        FilterOp::GreaterThan => {
-                            let attr = if let Ok(a) = attr.parse::<f32>() {
-                                a
-                            } else {
-                                return false;
-                            };
-
-                            value.parse::<f32>()
-                                .map(|v| attr > v)
-                                .unwrap_or(false)
-                        },
-                        FilterOp::GreaterEq => {
-                            let attr = if let Ok(a) = attr.parse::<f32>() {
-                                a
-                            } else {
-                                return false;
-                            };
-
-                            value.parse::<f32>()
-                                .map(|v| attr >= v)
-                                .unwrap_or(false)
-                        },
-                        FilterOp::Not => attr != value,
-                    }
+                    compare(attr, op, value)
                 } else {
                     false
                 }
             },
+            Condition::Between(field, low, high) if field == ANY_FIELD => {
+                self.info.iter_all()
+                    .flat_map(|(_, notes)| notes.iter())
+                    .flat_map(|note| note.attributes().map(|(_, v)| v))
+                    .any(|v| in_range(v, low, high))
+            },
+            Condition::Between(field, low, high) => {
+                let (group, field) = field.split_once(':')
+                    .unwrap_or(("default", field));
+
+                matches!(
+                    self.get_field_from(group, field),
+                    Some(v) if in_range(v, low, high)
+                )
+            },
+            Condition::Tag(tag) => self.tags.contains(tag),
+            Condition::NotTag(tag) => ! self.tags.contains(tag),
+            Condition::Exists(group) =>
+                self.info.contains_key(&group.to_lowercase()),
+            Condition::NotExists(group) =>
+                ! self.info.contains_key(&group.to_lowercase()),
             Condition::Function(ref func) => {
-                todo!();
+                match func {
+                    // Variable assignments bind a subcontact for the rest of
+                    // the query; they don't filter anything.
+                    Function::Ref(_, _) | Function::Split(_, _, _) => true,
+                    Function::Regex(field, re) => {
+                        let (group, field) = field.split_once(':')
+                            .unwrap_or(("default", field));
+
+                        matches!(
+                            self.get_field_from(group, field),
+                            Some(val) if re.is_match(val)
+                        )
+                    },
+                }
             },
             Condition::And(inner) => {
                 let (lhs, rhs): &(Condition, Condition) = &**inner;
@@ -244,15 +382,89 @@ impl Contact {
     }
 }
 
+fn is_empty_note(note: &Note) -> bool {
+    note.tags().is_empty()
+        && note.attribute_keys().next().is_none()
+        && note.content().is_empty()
+}
+
+/// Compare a field's value against the value in a filter.
+///
+/// All operators other than = and NOT compare numerically; if either side is
+/// not a number, the comparison is false.
+fn compare(attr: &str, op: &FilterOp, value: &str) -> bool {
+    // TODO: On parse errors, return an error instead of false?
+    let numbers = || match (attr.parse::<f32>(), value.parse::<f32>()) {
+        (Ok(a), Ok(v)) => Some((a, v)),
+        _ => None,
+    };
+
+    match op {
+        FilterOp::EqualTo => attr == value,
+        FilterOp::Not => attr != value,
+        FilterOp::LessThan => matches!(numbers(), Some((a, v)) if a < v),
+        FilterOp::LessEq => matches!(numbers(), Some((a, v)) if a <= v),
+        FilterOp::GreaterThan => matches!(numbers(), Some((a, v)) if a > v),
+        FilterOp::GreaterEq => matches!(numbers(), Some((a, v)) if a >= v),
+    }
+}
+
+/// Determine whether a field's value lies within an inclusive range.
+///
+/// The value and bounds must all be numbers or all be ISO 8601 dates
+/// (YYYY-MM-DD); otherwise the value is not within the range.
+fn in_range(attr: &str, low: &str, high: &str) -> bool {
+    let numbers = (
+        attr.parse::<f32>(),
+        low.parse::<f32>(),
+        high.parse::<f32>()
+    );
+
+    if let (Ok(a), Ok(l), Ok(h)) = numbers {
+        l <= a && a <= h
+    } else if is_iso_date(attr) && is_iso_date(low) && is_iso_date(high) {
+        low <= attr && attr <= high
+    } else {
+        false
+    }
+}
+
+/// Determine whether the string is a date in the form YYYY-MM-DD.
+fn is_iso_date(s: &str) -> bool {
+    s.len() == 10 && s.bytes().enumerate().all(|(i, b)| match i {
+        4 | 7 => b == b'-',
+        _ => b.is_ascii_digit(),
+    })
+}
+
+/// A file in a collection that could not be read as a contact.
+#[derive(Debug)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    /// Why the file could not be read.
+    pub reason: String,
+}
+
+/// Read the contacts in the collection at the given path that match the
+/// condition.
+///
+/// Hidden files and directories, such as upim-edit's lock files, are ignored.
+/// Files that cannot be read as contacts are skipped and returned alongside
+/// the contacts; errors walking the collection itself are returned as errors.
 pub fn read_contacts(path: &Path, condition: Condition)
--> anyhow::Result<Vec<Contact>> {
+-> anyhow::Result<(Vec<Contact>, Vec<SkippedFile>)> {
     if ! path.is_dir() {
         return Err(anyhow!("The contacts collection must be a directory"));
     }
 
     let mut contacts = vec![];
+    let mut skipped = vec![];
 
-    for entry in WalkDir::new(path).min_depth(1).follow_links(true) {
+    let entries = WalkDir::new(path).min_depth(1).follow_links(true)
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || ! is_hidden(e.path()));
+
+    for entry in entries {
         match entry {
             Err(e) => {
                 if e.loop_ancestor().is_some() {
@@ -263,17 +475,211 @@ pub fn read_contacts(path: &Path, condition: Condition)
             },
             Ok(entry) => {
                 if entry.file_type().is_file() {
-                    let contact = Contact::new_from_file(entry.path())?;
-
-                    if contact.matches(&condition) {
-                        contacts.push(contact)
+                    match Contact::new_from_file(entry.path()) {
+                        Ok(contact) => if contact.matches(&condition) {
+                            contacts.push(contact)
+                        },
+                        Err(e) => skipped.push(SkippedFile {
+                            path: entry.path().to_owned(),
+                            reason: e.to_string(),
+                        }),
                     }
                 }
             }
         }
     }
 
-    Ok(contacts)
+    Ok((contacts, skipped))
+}
+
+/// Summarize the files skipped by [read_contacts].
+///
+/// If `verbose` is true, each file and the reason it was skipped is listed.
+/// Returns `None` if no files were skipped.
+pub fn skipped_summary(skipped: &[SkippedFile], verbose: bool)
+-> Option<String> {
+    let mut summary = match skipped.len() {
+        0 => return None,
+        1 => String::from("1 file skipped due to errors"),
+        n => format!("{} files skipped due to errors", n),
+    };
+
+    if verbose {
+        summary.push(':');
+        for file in skipped {
+            summary.push_str(
+                &format!("\n\t{}: {}", file.path.display(), file.reason)
+            );
+        }
+    } else {
+        summary.push_str(" (use --verbose to list them)");
+    }
+
+    Some(summary)
+}
+
+/// Remove contacts with the same value for `field` as an earlier contact.
+///
+/// The field may be given as "Group:Field". Contacts without the field are
+/// kept.
+pub fn dedup_contacts(contacts: Vec<Contact>, field: &str) -> Vec<Contact> {
+    use upim_core::uniq::UniqByKey as _;
+
+    let key = |c: &Contact| match field.split_once(':') {
+        Some((group, field)) => c.get_field_from(group, field).cloned(),
+        None => c.get_field(field).cloned(),
+    };
+
+    contacts.into_iter()
+        .uniq_by_key(key)
+        .collect()
+}
+
+/// Write a new contact file at `path` with the given name and fields.
+///
+/// The fields are added in order; a field given more than once has each of
+/// its values. Any existing file is replaced.
+pub fn write_new_contact(path: &Path, name: &str, fields: &[(String, String)])
+-> anyhow::Result<()> {
+    let mut note = Note::default();
+    note.set_attribute("Name", name);
+
+    for (field, value) in fields {
+        note.add_attribute(field, value);
+    }
+
+    note.write_to_file(path)
+        .map_err(|e| anyhow!("Cannot write {}: {}", path.display(), e))
+}
+
+/// The field name that sorts contacts by the modification time of their
+/// files, rather than by a field's value.
+pub const MTIME_FIELD: &str = "@mtime";
+
+/// Sort the contacts by the field named in `sort`, which may be given as
+/// "Group:Field", or by file modification time for [MTIME_FIELD].
+///
+/// Values are compared as text. Contacts without the field (or without a
+/// modification time) are placed last in either order; otherwise, contacts
+/// with equal values keep their order.
+pub fn sort_contacts(contacts: &mut [Contact], sort: &Sort) {
+    use std::cmp::Ordering;
+
+    let (field, descending) = match sort {
+        Sort::NoSort => return,
+        Sort::Ascending(f) => (f.as_str(), false),
+        Sort::Descending(f) => (f.as_str(), true),
+    };
+
+    fn order<T: Ord>(a: Option<T>, b: Option<T>, descending: bool)
+    -> Ordering {
+        match (a, b) {
+            (Some(a), Some(b)) if descending => b.cmp(&a),
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    }
+
+    if field == MTIME_FIELD {
+        contacts.sort_by(|a, b| order(a.modified(), b.modified(), descending));
+    } else {
+        let (group, field) = field.split_once(':')
+            .unwrap_or(("default", field));
+
+        contacts.sort_by(|a, b| order(
+            a.get_field_from(group, field),
+            b.get_field_from(group, field),
+            descending
+        ));
+    }
+}
+
+/// Partition the contacts by their values for `field`, which may be given
+/// as "Group:Field".
+///
+/// The groups are sorted by value, followed by a group with no value for the
+/// contacts without the field. Within each group, contacts keep their order.
+pub fn group_contacts(contacts: Vec<Contact>, field: &str)
+-> Vec<(Option<String>, Vec<Contact>)> {
+    use std::collections::BTreeMap;
+
+    let (group, field) = field.split_once(':').unwrap_or(("default", field));
+    let mut groups: BTreeMap<String, Vec<Contact>> = BTreeMap::new();
+    let mut missing = vec![];
+
+    for contact in contacts {
+        match contact.get_field_from(group, field).cloned() {
+            Some(value) => groups.entry(value).or_default().push(contact),
+            None => missing.push(contact),
+        }
+    }
+
+    let mut groups = groups.into_iter()
+        .map(|(k, v)| (Some(k), v))
+        .collect::<Vec<_>>();
+
+    if ! missing.is_empty() {
+        groups.push((None, missing));
+    }
+    groups
+}
+
+/// Count the contacts in the collection at the given path that match the
+/// condition.
+///
+/// Contacts are read and tested one at a time, so only one contact is held in
+/// memory at once. As with [read_contacts], files that cannot be read as
+/// contacts are skipped and returned with the count.
+pub fn count_matching(path: &Path, condition: &Condition)
+-> Result<(usize, Vec<SkippedFile>), FileError> {
+    use std::io::ErrorKind;
+
+    if ! path.is_dir() {
+        return Err(FileError::IO((path.to_owned(), ErrorKind::NotFound)));
+    }
+
+    let mut count = 0;
+    let mut skipped = vec![];
+
+    let entries = WalkDir::new(path).min_depth(1).follow_links(true)
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || ! is_hidden(e.path()));
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                if e.loop_ancestor().is_some() { continue; }
+
+                let file = e.path().map(Path::to_path_buf).unwrap_or_default();
+                let kind = e.io_error()
+                    .map(|e| e.kind())
+                    .unwrap_or(ErrorKind::Other);
+
+                return Err(FileError::IO((file, kind)));
+            },
+        };
+
+        if ! entry.file_type().is_file() { continue; }
+
+        let contact = Note::read_from_file(entry.path())
+            .map_err(anyhow::Error::from)
+            .and_then(Contact::new);
+
+        match contact {
+            Ok(contact) => if contact.matches(condition) {
+                count += 1;
+            },
+            Err(e) => skipped.push(SkippedFile {
+                path: entry.path().to_owned(),
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    Ok((count, skipped))
 }
 
 /// Retrieve a list of fields containing every attribute used by every contact
@@ -296,71 +702,460 @@ pub fn get_all_fields(contacts: &[Contact]) -> Vec<(&str, &str)> {
     known_fields.drain().map(|(g, f)| (g.as_str(), f.as_str())).collect()
 }
 
-/// Print the specified fields in the list of contacts, using the provided
-/// separator.
-pub fn print_contacts(contacts: &[Contact], fields: &[String], sep: &str) {
-    use std::cmp::max;
+/// A column of output, resolved from the field list.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Column<'a> {
+    /// A (group, field) pair.
+    Field(&'a str, &'a str),
+    /// A computed field, and the text in the field list that selected it.
+    Function(&'a str, SelectFunction),
+}
 
-    let fields = if fields.len() == 1 && fields[0] == "*" {
-        get_all_fields(contacts)
-    } else {
-        fields.iter()
-            .map(|f| f.split_once(':').unwrap_or(("default", f)))
-            .collect()
-    };
+impl Column<'_> {
+    /// The column's heading.
+    fn header(&self) -> String {
+        match self {
+            Column::Field("default", field) => (*field).to_owned(),
+            Column::Field(group, field) => format!("{}:{}", group, field),
+            Column::Function(text, _) => text.trim().to_owned(),
+        }
+    }
 
-    let mut table = vec![];
-    let mut header = vec![];
+    /// Retrieve the column's values for the given contact.
+    ///
+    /// A split field has a value for each segment; all other columns have a
+    /// single value. References are looked up by name in `references`; each
+    /// lookup is stored in `resolved` so that the contact's other columns
+    /// referring to the same field can reuse it.
+    fn values<'c>(
+        &'c self,
+        contact: &Contact,
+        references: &'c [Contact],
+        resolved: &mut HashMap<&'c str, Option<&'c Contact>>
+    ) -> Vec<String> {
+        let field = |contact: &Contact, name: &str| {
+            let (group, name) = name.split_once(':')
+                .unwrap_or(("default", name));
+
+            contact.get_field_from(group, name).cloned().unwrap_or_default()
+        };
+
+        match self {
+            Column::Field(group, name) => vec![
+                contact.get_field_from(group, name)
+                    .cloned()
+                    .unwrap_or_default()
+            ],
+            Column::Function(_, SelectFunction::Split(name, sep)) => {
+                field(contact, name)
+                    .split(*sep)
+                    .map(|s| s.trim().to_owned())
+                    .collect()
+            },
+            Column::Function(_, SelectFunction::Ref(name, target)) => {
+                let refd = *resolved.entry(name).or_insert_with(|| {
+                    let name = field(contact, name);
 
-    let mut lengths = vec![];
-    lengths.resize(fields.len(), 0);
+                    references.iter()
+                        .find(|c| c.name().as_deref() == Some(name.as_str()))
+                });
 
-    for i in 0..fields.len() {
-        let field_len = fields[i].0.len() + fields[i].1.len() + 1;
-        lengths[i] = max(lengths[i], field_len);
+                vec![refd.map(|c| field(c, target)).unwrap_or_default()]
+            },
+        }
+    }
+}
 
-        if fields[i].0 == "default" {
-            header.push(fields[i].1.to_owned());
+/// Resolve the selected fields into a list of columns.
+///
+/// A lone `*` selects every field used by any of the contacts, and a
+/// `REGEX_FIELDS` selector expands to every field whose name matches its
+/// regular expression, in sorted order.
+///
+/// `variables` are the (variable, field) pairs bound by `variable = REF(field)`
+/// in the query; a `variable.Field` selector is read from the referenced
+/// contact.
+pub fn expand_fields<'a>(
+    contacts: &'a [Contact],
+    fields: &'a [String],
+    variables: &[(&'a str, &'a str)]
+) -> anyhow::Result<Vec<Column<'a>>> {
+    if fields.len() == 1 && fields[0] == "*" {
+        return Ok(get_all_fields(contacts).into_iter()
+            .map(|(g, f)| Column::Field(g, f))
+            .collect());
+    }
+
+    let mut selected = vec![];
+
+    for field in fields {
+        if let Some(re) = parse_regex_fields(field) {
+            let re = re?;
+
+            let mut matches = get_all_fields(contacts).into_iter()
+                .filter(|(group, field)| {
+                    if *group == "default" {
+                        re.is_match(field)
+                    } else {
+                        re.is_match(&format!("{}:{}", group, field))
+                    }
+                })
+                .collect::<Vec<(&str, &str)>>();
+
+            matches.sort_unstable();
+            selected.extend(
+                matches.into_iter().map(|(g, f)| Column::Field(g, f))
+            );
+        } else if let Some(func) = parse_select_function(field) {
+            selected.push(Column::Function(field, func?));
+        } else if let Some((name, target)) = field.split_once('.')
+            .and_then(|(var, target)| {
+                variables.iter()
+                    .find(|(v, _)| *v == var)
+                    .map(|(_, name)| (name, target))
+            })
+        {
+            selected.push(Column::Function(
+                field,
+                SelectFunction::Ref((*name).into(), target.into())
+            ));
         } else {
-            let mut h = fields[i].0.to_owned();
-            h.push(':');
-            h.push_str(fields[i].1);
+            let (group, field) = field.split_once(':')
+                .unwrap_or(("default", field));
 
-            header.push(h);
+            selected.push(Column::Field(group, field));
         }
     }
 
-    table.push(header);
+    Ok(selected)
+}
+
+/// Build the table of the query's fields for the list of contacts.
+///
+/// The first row is the header. A contact with a split field has one row for
+/// each segment; its other fields are repeated on each row. Referenced contacts
+/// are looked up by name in `references`, once per contact for each field
+/// referenced.
+pub fn build_table(
+    contacts: &[Contact],
+    references: &[Contact],
+    query: &Query
+) -> anyhow::Result<Vec<Vec<String>>> {
+    let variables = query.condition.ref_variables();
+    let columns = expand_fields(contacts, &query.select, &variables)?;
+    let mut table = vec![columns.iter().map(Column::header).collect()];
 
     for contact in contacts {
-        let mut row = vec![];
+        table.append(&mut contact_rows(contact, &columns, references));
+    }
 
-        for (i, field) in fields.iter().enumerate() {
-            let (group, field) = field;
+    Ok(table)
+}
 
-            let field_data = contact.get_field_from(group, field)
-                .cloned()
-                .unwrap_or_else(String::default);
+/// Build the rows of the table for a single contact.
+///
+/// See [build_table].
+fn contact_rows(contact: &Contact, columns: &[Column], references: &[Contact])
+-> Vec<Vec<String>> {
+    let mut resolved = HashMap::new();
+
+    let values = columns.iter()
+        .map(|c| c.values(contact, references, &mut resolved))
+        .collect::<Vec<Vec<String>>>();
+
+    let rows = values.iter().map(Vec::len).max().unwrap_or(0).max(1);
+
+    (0..rows)
+        .map(|i| {
+            values.iter()
+                .map(|v| {
+                    if v.len() == 1 {
+                        v[0].clone()
+                    } else {
+                        v.get(i).cloned().unwrap_or_default()
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Write the query's fields in the list of contacts as newline-delimited JSON.
+///
+/// Each row of the table (see [build_table]) is written as a single-line JSON
+/// object mapping the column headers to their values, and the output is
+/// flushed after each contact so consumers can process records as they
+/// arrive.
+pub fn write_ndjson<W: Write>(
+    out: &mut W,
+    contacts: &[Contact],
+    references: &[Contact],
+    query: &Query
+) -> anyhow::Result<()> {
+    use serde_json::{Map, Value};
+
+    let variables = query.condition.ref_variables();
+    let columns = expand_fields(contacts, &query.select, &variables)?;
+    let headers = columns.iter().map(Column::header).collect::<Vec<String>>();
+
+    for contact in contacts {
+        for row in contact_rows(contact, &columns, references) {
+            let record = headers.iter().cloned()
+                .zip(row.into_iter().map(Value::String))
+                .collect::<Map<String, Value>>();
 
-            lengths[i] = max(lengths[i], field_data.len());
-            row.push(field_data);
+            writeln!(out, "{}", Value::Object(record))?;
+        }
+        out.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Pass `f` a writer for the file at `path`, or for stdout if there is no
+/// path.
+///
+/// The file is replaced atomically: the output is written to a temporary
+/// file beside it, which is renamed over the target only once `f` succeeds.
+pub fn write_output<F>(path: Option<&Path>, f: F) -> anyhow::Result<()>
+    where F: FnOnce(&mut dyn Write) -> anyhow::Result<()>,
+{
+    use std::{
+        fs::{self, File},
+        io::{self, BufWriter},
+    };
+    use upim_core::paths::temp_sibling;
+
+    let path = match path {
+        Some(p) => p,
+        None => return f(&mut io::stdout().lock()),
+    };
+
+    let temp = temp_sibling(path);
+    let res = File::create(&temp)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            f(&mut out)?;
+            out.into_inner()?.sync_all()?;
+            Ok(())
+        })
+        .and_then(|_| Ok(fs::rename(&temp, path)?));
+
+    if res.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    res.map_err(|e| e.context(format!("Cannot write {}", path.display())))
+}
+
+/// Print the query's fields in the list of contacts, using the provided
+/// separator.
+///
+/// See [build_table] for the meaning of `references`.
+pub fn print_contacts<W: Write>(
+    out: &mut W,
+    contacts: &[Contact],
+    references: &[Contact],
+    query: &Query,
+    sep: &str
+) -> anyhow::Result<()> {
+    let table = build_table(contacts, references, query)?;
+
+    let mut lengths = vec![0; table.first().map(Vec::len).unwrap_or(0)];
+
+    for row in table.iter() {
+        for (i, column) in row.iter().enumerate() {
+            lengths[i] = lengths[i].max(column.len());
         }
-        table.push(row);
     }
 
     for row in table {
         for (i, column) in row.iter().enumerate() {
-            print!("{1:0$}", lengths[i], column);
-            print!("{}", sep);
+            write!(out, "{1:0$}", lengths[i], column)?;
+            write!(out, "{}", sep)?;
         }
-        println!();
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+/// Print each group of contacts created by [group_contacts] under a header
+/// naming the field and the group's value, or "(none)" for the contacts
+/// without the field.
+///
+/// Each group is printed as by [print_contacts], with a blank line between
+/// groups.
+pub fn print_grouped<W: Write>(
+    out: &mut W,
+    groups: &[(Option<String>, Vec<Contact>)],
+    field: &str,
+    references: &[Contact],
+    query: &Query,
+    sep: &str
+) -> anyhow::Result<()> {
+    for (i, (value, contacts)) in groups.iter().enumerate() {
+        if i > 0 { writeln!(out)?; }
+
+        writeln!(out, "{} = {}", field, value.as_deref().unwrap_or("(none)"))?;
+        print_contacts(out, contacts, references, query, sep)?;
     }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn new_contact_with_fields() {
+        use std::{env, fs};
+
+        let path = env::temp_dir().join("upim_contact_new.contact");
+        let fields = [
+            ("Phone".to_string(), "123".to_string()),
+            ("Email".into(), "x@y".into()),
+            ("Phone".into(), "456".into()),
+        ];
+
+        let res = write_new_contact(&path, "Jane", &fields);
+        let note = Note::read_from_file(&path);
+        let _ = fs::remove_file(&path);
+
+        res.unwrap();
+        let contact = Contact::new(note.unwrap()).unwrap();
+        let note = &contact.info["default"];
+
+        assert_eq!(contact.name().unwrap(), "Jane");
+        assert_eq!(contact.get_field("Email").unwrap(), "x@y");
+        assert_eq!(note.attributes_sorted().iter()
+            .filter(|(k, _)| *k == "Phone")
+            .map(|(_, v)| v.as_str())
+            .collect::<Vec<_>>(), ["123", "456"]);
+    }
+
+    #[test]
+    fn print_contacts_with_parsed_separator() {
+        use crate::args::Options;
+
+        let args = [
+            "upim-contact",
+            "--separator", "{TAB}",
+            "--filter", "Name,Phone",
+        ];
+        let opts = Options::new(args.iter().map(|s| s.to_string())).unwrap();
+
+        let contacts = [
+            "[Name: Jane]\n[Phone: 123]\n",
+            "[Name: Bob]\n[Phone: 4567]\n",
+        ].iter()
+            .map(|s| Contact::new(Note::from_str(s).unwrap()).unwrap())
+            .collect::<Vec<_>>();
+
+        let mut out = vec![];
+        print_contacts(
+            &mut out,
+            &contacts,
+            &[],
+            opts.filter.as_ref().unwrap(),
+            opts.separator.as_deref().unwrap()
+        ).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(out, "\
+            Name\tPhone\t\n\
+            Jane\t123  \t\n\
+            Bob \t4567 \t\n\
+        ");
+        assert!(out.lines().all(|l| l.matches('\t').count() == 2));
+    }
+
+    #[test]
+    fn print_contacts_grouped() {
+        let contacts = [
+            "[Name: One]\n\n@Employer\n[Name: Widgets]\n",
+            "[Name: Two]\n",
+            "[Name: Three]\n\n@Employer\n[Name: Acme]\n",
+            "[Name: Four]\n\n@Employer\n[Name: Widgets]\n",
+        ].iter()
+            .map(|s| Contact::new(Note::from_str(s).unwrap()).unwrap())
+            .collect::<Vec<_>>();
+
+        let groups = group_contacts(contacts, "Employer:Name");
+        let names = groups.iter()
+            .map(|(k, v)| {
+                let names = v.iter().map(|c| c.name().unwrap());
+                (k.as_deref(), names.collect::<Vec<_>>().join(","))
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, [
+            (Some("Acme"), "Three".to_string()),
+            (Some("Widgets"), "One,Four".into()),
+            (None, "Two".into()),
+        ]);
+
+        let query = Query::from_str("Name").unwrap();
+        let mut out = vec![];
+        print_grouped(&mut out, &groups, "Employer:Name", &[], &query, " ")
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Employer:Name = Acme\n\
+            Name  \n\
+            Three \n\
+            \n\
+            Employer:Name = Widgets\n\
+            Name \n\
+            One  \n\
+            Four \n\
+            \n\
+            Employer:Name = (none)\n\
+            Name \n\
+            Two  \n"
+        );
+    }
+
+    #[test]
+    fn dedup_contacts_by_field() {
+        let contacts = [
+            "[Name: Favorite Person]\n[Phone: 123-456]\n",
+            "[Name: Favorite Person]\n[Phone: 555-555]\n",
+            "[Name: Other Person]\n[Phone: 987-654]\n",
+        ].iter()
+            .map(|s| Contact::new(Note::from_str(s).unwrap()).unwrap())
+            .collect::<Vec<_>>();
+
+        let contacts = dedup_contacts(contacts, "Name");
+        let phones = contacts.iter()
+            .map(|c| c.get_field("Phone").unwrap().as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(phones, ["123-456", "987-654"]);
+    }
+
+    #[test]
+    fn dedup_contacts_by_group_field() {
+        let contacts = [
+            "[Name: One]\n\n@Employer\n[Name: Widgets]\n",
+            "[Name: Two]\n",
+            "[Name: Three]\n\n@Employer\n[Name: Widgets]\n",
+            "[Name: Four]\n\n@Employer\n[Name: Acme]\n",
+            "[Name: Five]\n",
+        ].iter()
+            .map(|s| Contact::new(Note::from_str(s).unwrap()).unwrap())
+            .collect::<Vec<_>>();
+
+        let contacts = dedup_contacts(contacts, "Employer:Name");
+        let names = contacts.iter()
+            .map(|c| c.name().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, ["One", "Two", "Four", "Five"]);
+    }
+
     #[test]
     fn simple_contact_name() {
         let text = "\
@@ -416,6 +1211,35 @@ mod tests {
         assert!(Contact::new(Note::from_str(text).unwrap()).is_err());
     }
 
+    #[test]
+    fn contact_to_note_round_trip() {
+        let text = "\
+        @contact\n\
+        [Name: Favorite Person]\n\
+        \n\
+        @Employer\n\
+        [Name: Some Company]\n\
+        \n\
+        @Spouse\n\
+        [Name: Other Person]\n\
+        \n\
+        Some notes.\n\
+        ";
+
+        let note = Note::from_str(text).unwrap();
+        let contact = Contact::new(note.clone()).unwrap();
+        assert_eq!(contact.to_note(), note);
+
+        let text = "\
+        [Name: Favorite Person]\n\
+        [Phone: 123-456]\n\
+        ";
+
+        let note = Note::from_str(text).unwrap();
+        let contact = Contact::new(note.clone()).unwrap();
+        assert_eq!(contact.to_note(), note);
+    }
+
     #[test]
     fn get_field() {
         let text = "\
@@ -448,6 +1272,85 @@ mod tests {
         );
     }
 
+    #[test]
+    fn set_fields() {
+        let text = "\
+        [Name: Favorite Person]\n\
+        [Phone: 123-456]\n\
+        \n\
+        @Employer\n\
+        [Name: Some Company]\n\
+        \n\
+        Some notes.\n\
+        ";
+
+        let mut contact = Contact::new(Note::from_str(text).unwrap()).unwrap();
+
+        contact.set_field("Phone", "234-567");
+        contact.set_field("Email", "person@example.com");
+        contact.set_field_in("Employer", "Phone", "345-678");
+        contact.set_field_in("Spouse", "Name", "Other Person");
+
+        assert_eq!(contact.get_field("Phone").unwrap(), "234-567");
+        assert_eq!(contact.get_field("Email").unwrap(), "person@example.com");
+        assert_eq!(
+            contact.get_field_from("employer", "Name").unwrap(),
+            "Some Company"
+        );
+        assert_eq!(
+            contact.get_field_from("employer", "Phone").unwrap(),
+            "345-678"
+        );
+        assert_eq!(
+            contact.get_field_from("spouse", "Name").unwrap(),
+            "Other Person"
+        );
+
+        let contact = Contact::new(contact.to_note()).unwrap();
+        assert_eq!(
+            contact.get_field_from("spouse", "Name").unwrap(),
+            "Other Person"
+        );
+        assert_eq!(contact.info["spouse"].content(), "Some notes.\n");
+    }
+
+    #[test]
+    fn group_entries_list_every_field() {
+        let text = "\
+        @friend\n\
+        [Name: Favorite Person]\n\
+        [Phone: 123-456]\n\
+        \n\
+        @Employer\n\
+        [Name: Some Company]\n\
+        [Phone: 555-5555]\n\
+        \n\
+        @Spouse\n\
+        [Name: Other Person]\n\
+        ";
+
+        let contact = Contact::new(Note::from_str(text).unwrap()).unwrap();
+
+        let entries = contact.group_entries()
+            .map(|(group, fields)| {
+                let mut fields = fields
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .collect::<Vec<_>>();
+                fields.sort_unstable();
+                (group, fields)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(entries, [
+            (
+                "default",
+                vec![("Name", "Favorite Person"), ("Phone", "123-456")]
+            ),
+            ("employer", vec![("Name", "Some Company"), ("Phone", "555-5555")]),
+            ("spouse", vec![("Name", "Other Person")]),
+        ]);
+    }
+
     #[test]
     fn group_list() {
         let text = "\
@@ -465,6 +1368,402 @@ mod tests {
         assert_eq!(groups.len(), 2);
     }
 
+    #[test]
+    fn expand_regex_fields() {
+        let text = "\
+        [Name: Favorite Person]\n\
+        [Home Phone: 123-456]\n\
+        [Cell_Phone: 234-567]\n\
+        [Phone Notes: Call after noon]\n\
+        [Email: person@example.com]\n\
+        \n\
+        @Employer\n\
+        [Phone: 345-678]\n\
+        ";
+
+        let contacts = vec![
+            Contact::new(Note::from_str(text).unwrap()).unwrap()
+        ];
+
+        let fields = vec!["Name".into(), "REGEX_FIELDS('.*Phone')".into()];
+        assert_eq!(
+            expand_fields(&contacts, &fields, &[]).unwrap(),
+            [
+                Column::Field("default", "Name"),
+                Column::Field("default", "Cell_Phone"),
+                Column::Field("default", "Home Phone"),
+                Column::Field("employer", "Phone"),
+            ]
+        );
+
+        let fields = vec!["REGEX_FIELDS('[A-Z].*_Phone')".into()];
+        assert_eq!(
+            expand_fields(&contacts, &fields, &[]).unwrap(),
+            [Column::Field("default", "Cell_Phone")]
+        );
+
+        let fields = vec!["REGEX_FIELDS('Fax')".into()];
+        assert!(expand_fields(&contacts, &fields, &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn print_table_with_separator() {
+        let contacts = [
+            "[Name: Favorite Person]\n[Phone: 123-456]\n",
+            "[Name: Other]\n[Phone: 555-5555]\n",
+        ].iter()
+            .map(|s| Contact::new(Note::from_str(s).unwrap()).unwrap())
+            .collect::<Vec<_>>();
+
+        let query = Query::from_str("Name,Phone").unwrap();
+        let mut out = vec![];
+        print_contacts(&mut out, &contacts, &[], &query, "\t").unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Name           \tPhone   \t\n\
+            Favorite Person\t123-456 \t\n\
+            Other          \t555-5555\t\n"
+        );
+    }
+
+    #[test]
+    fn export_to_file() {
+        use std::{env, fs};
+
+        let contacts = [
+            Contact::new(Note::from_str("[Name: Person]\n").unwrap()).unwrap()
+        ];
+        let query = Query::from_str("Name").unwrap();
+
+        let path = env::temp_dir().join("upim_contact_export.ndjson");
+        fs::write(&path, "old\n").unwrap();
+
+        let res = write_output(Some(&path), |mut out| {
+            write_ndjson(&mut out, &contacts, &[], &query)
+        });
+        let written = fs::read_to_string(&path).unwrap();
+
+        let failed = write_output(Some(&path), |out| {
+            writeln!(out, "partial")?;
+            Err(anyhow!("Failed"))
+        });
+        let kept = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(res.is_ok());
+        assert_eq!(written, "{\"Name\":\"Person\"}\n");
+        assert!(failed.is_err());
+        assert_eq!(kept, written);
+        assert!(fs::read_dir(env::temp_dir()).unwrap()
+            .filter_map(|e| e.ok())
+            .all(|e| ! e.file_name().to_string_lossy()
+                .starts_with(".upim_contact_export.ndjson.")));
+    }
+
+    #[test]
+    fn split_select_expands_rows() {
+        let text = "\
+        [Name: Favorite Person]\n\
+        [Phone: 123-456]\n\
+        [Children: Child One, Child Two,Child Three]\n\
+        ";
+        let contacts = [Contact::new(Note::from_str(text).unwrap()).unwrap()];
+
+        let query = Query::from_str("\"Name,SPLIT(Children, ',')\"").unwrap();
+        let table = build_table(&contacts, &[], &query).unwrap();
+
+        assert_eq!(table, [
+            ["Name", "SPLIT(Children, ',')"],
+            ["Favorite Person", "Child One"],
+            ["Favorite Person", "Child Two"],
+            ["Favorite Person", "Child Three"],
+        ]);
+    }
+
+    #[test]
+    fn ref_select_resolves_contact() {
+        let person = "\
+        [Name: Favorite Person]\n\
+        [Spouse: Other Person]\n\
+        ";
+        let spouse = "\
+        [Name: Other Person]\n\
+        [Phone: 234-567]\n\
+        ";
+
+        let contacts = [Contact::new(Note::from_str(person).unwrap()).unwrap()];
+        let references = [
+            Contact::new(Note::from_str(person).unwrap()).unwrap(),
+            Contact::new(Note::from_str(spouse).unwrap()).unwrap(),
+        ];
+
+        let query = Query::from_str("Name,REF(Spouse).Phone").unwrap();
+        let table = build_table(&contacts, &references, &query).unwrap();
+
+        assert_eq!(table, [
+            ["Name", "REF(Spouse).Phone"],
+            ["Favorite Person", "234-567"],
+        ]);
+    }
+
+    #[test]
+    fn select_multiple_fields_from_ref_variable() {
+        let person = "\
+        [Name: Favorite Person]\n\
+        [Spouse: Other Person]\n\
+        ";
+        let spouse = "\
+        [Name: Other Person]\n\
+        [Phone: 234-567]\n\
+        [Email: other@example.com]\n\
+        ";
+
+        let references = [
+            Contact::new(Note::from_str(person).unwrap()).unwrap(),
+            Contact::new(Note::from_str(spouse).unwrap()).unwrap(),
+        ];
+
+        let query = Query::from_str(
+            "'Name,s.Name,s.Phone,s.Email' WHERE Name = 'Favorite Person' \
+            AND s = REF(Spouse)"
+        ).unwrap();
+        assert!(query.selects_references());
+
+        let contacts = [Contact::new(Note::from_str(person).unwrap()).unwrap()];
+        assert!(contacts[0].matches(&query.condition));
+        assert!(! references[1].matches(&query.condition));
+
+        let table = build_table(&contacts, &references, &query).unwrap();
+
+        assert_eq!(table, [
+            ["Name", "s.Name", "s.Phone", "s.Email"],
+            [
+                "Favorite Person",
+                "Other Person",
+                "234-567",
+                "other@example.com"
+            ],
+        ]);
+    }
+
+    #[test]
+    fn write_contacts_as_ndjson() {
+        use serde_json::Value;
+
+        let texts = [
+            "[Name: Favorite Person]\n[Phone: 123-456]\n",
+            "[Name: \"Quoted\" Person]\n[Children: A, B]\n",
+        ];
+        let contacts = texts.iter()
+            .map(|t| Contact::new(Note::from_str(t).unwrap()).unwrap())
+            .collect::<Vec<Contact>>();
+
+        let query = Query::from_str("\"Name,Phone,SPLIT(Children, ',')\"")
+            .unwrap();
+
+        let mut out = vec![];
+        write_ndjson(&mut out, &contacts, &[], &query).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let records = out.lines()
+            .map(|l| serde_json::from_str::<Value>(l).unwrap())
+            .collect::<Vec<Value>>();
+
+        assert!(out.ends_with('\n'));
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0]["Name"], "Favorite Person");
+        assert_eq!(records[0]["Phone"], "123-456");
+        assert_eq!(records[1]["Name"], "\"Quoted\" Person");
+        assert_eq!(records[1]["SPLIT(Children, ',')"], "A");
+        assert_eq!(records[2]["SPLIT(Children, ',')"], "B");
+        assert_eq!(records[2]["Phone"], "");
+    }
+
+    #[test]
+    fn count_matching_contacts() {
+        use std::{env, fs};
+
+        let dir = env::temp_dir().join("upim_contact_count_matching");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+
+        fs::write(dir.join("a.contact"), "[Name: A]\n[Num: 1]\n").unwrap();
+        fs::write(dir.join("b.contact"), "[Name: B]\n[Num: 5]\n").unwrap();
+        fs::write(dir.join("sub/c.contact"), "[Name: C]\n[Num: 9]\n")
+            .unwrap();
+
+        let all = count_matching(&dir, &Condition::All);
+        let some = count_matching(&dir, &Condition::Filter(
+            "Num".into(),
+            FilterOp::GreaterThan,
+            "2".into()
+        ));
+        let none = count_matching(&dir, &Condition::Filter(
+            "Name".into(),
+            FilterOp::EqualTo,
+            "D".into()
+        ));
+
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(all.unwrap().0, 3);
+        assert_eq!(some.unwrap().0, 2);
+        assert_eq!(none.unwrap().0, 0);
+    }
+
+    #[test]
+    fn count_matching_skips_invalid_files() {
+        use std::{env, fs};
+
+        let dir = env::temp_dir().join("upim_contact_count_skipped");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(dir.join("a.contact"), "[Name: A]\n").unwrap();
+        fs::write(dir.join("b.contact"), "[Name: B]\n").unwrap();
+        fs::write(dir.join("bad.contact"), "Not a contact\n").unwrap();
+
+        let res = count_matching(&dir, &Condition::All);
+        let _ = fs::remove_dir_all(&dir);
+
+        let (count, skipped) = res.unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].path, dir.join("bad.contact"));
+    }
+
+    #[test]
+    fn read_contacts_ignores_hidden_files() {
+        use std::{env, fs};
+
+        let dir = env::temp_dir().join("upim_contact_hidden_files");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join(".git")).unwrap();
+
+        fs::write(dir.join("a.contact"), "[Name: A]\n").unwrap();
+        fs::write(dir.join(".a.contact.lock"), "").unwrap();
+        fs::write(dir.join(".git/config"), "Not a contact\n").unwrap();
+
+        let res = read_contacts(&dir, Condition::All);
+        let count = count_matching(&dir, &Condition::All);
+        let _ = fs::remove_dir_all(&dir);
+
+        let (contacts, skipped) = res.unwrap();
+        assert_eq!(contacts.len(), 1);
+        assert!(skipped.is_empty());
+
+        let (count, skipped) = count.unwrap();
+        assert_eq!(count, 1);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn summarize_skipped_files() {
+        use std::{env, fs};
+
+        let dir = env::temp_dir().join("upim_contact_skipped_files");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(dir.join("a.contact"), "[Name: A]\n").unwrap();
+        fs::write(dir.join("b.contact"), "Not a header\n").unwrap();
+        fs::write(dir.join("c.contact"), "[Phone: 123]\n").unwrap();
+
+        let res = read_contacts(&dir, Condition::All);
+        let _ = fs::remove_dir_all(&dir);
+
+        let (contacts, mut skipped) = res.unwrap();
+        skipped.sort_by(|a, b| a.path.cmp(&b.path));
+
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(skipped.len(), 2);
+
+        assert_eq!(
+            skipped_summary(&skipped, false).unwrap(),
+            "2 files skipped due to errors (use --verbose to list them)"
+        );
+
+        let verbose = skipped_summary(&skipped, true).unwrap();
+        assert!(verbose.starts_with("2 files skipped due to errors:\n"));
+
+        for name in ["b.contact", "c.contact"] {
+            let entry = format!("\n\t{}: ", dir.join(name).display());
+            assert!(verbose.contains(&entry));
+        }
+        assert!(verbose.contains("No name provided in contact"));
+
+        assert!(skipped_summary(&[], true).is_none());
+    }
+
+    #[test]
+    fn sort_contacts_by_mtime() {
+        use std::{env, fs, time::Duration};
+        use filetime::FileTime;
+
+        let dir = env::temp_dir().join("upim_contact_sort_mtime");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let epoch = SystemTime::UNIX_EPOCH;
+        for (name, days) in [("Old", 1), ("Newest", 30), ("Newer", 20)] {
+            let path = dir.join(format!("{}.contact", name));
+            fs::write(&path, format!("[Name: {}]\n", name)).unwrap();
+
+            let mtime = epoch + Duration::from_secs(days * 86_400);
+            filetime::set_file_mtime(&path, FileTime::from_system_time(mtime))
+                .unwrap();
+        }
+
+        let res = read_contacts(&dir, Condition::All);
+        let _ = fs::remove_dir_all(&dir);
+        let (mut contacts, _) = res.unwrap();
+
+        let names = |contacts: &[Contact]| contacts.iter()
+            .map(|c| c.name().unwrap())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        sort_contacts(&mut contacts, &Sort::Descending(MTIME_FIELD.into()));
+        assert_eq!(names(&contacts), "Newest,Newer,Old");
+
+        sort_contacts(&mut contacts, &Sort::Ascending(MTIME_FIELD.into()));
+        assert_eq!(names(&contacts), "Old,Newer,Newest");
+
+        sort_contacts(&mut contacts, &Sort::Ascending("Name".into()));
+        assert_eq!(names(&contacts), "Newer,Newest,Old");
+    }
+
+    #[test]
+    fn sort_contacts_without_field_last() {
+        let mut contacts = ["[Name: A]\n", "[Name: B]\n[Age: 30]\n",
+            "[Name: C]\n[Age: 40]\n"].iter()
+            .map(|s| Contact::new(Note::from_str(s).unwrap()).unwrap())
+            .collect::<Vec<_>>();
+
+        let names = |contacts: &[Contact]| contacts.iter()
+            .map(|c| c.name().unwrap())
+            .collect::<Vec<_>>()
+            .join(",");
+
+        sort_contacts(&mut contacts, &Sort::Descending("Age".into()));
+        assert_eq!(names(&contacts), "C,B,A");
+
+        sort_contacts(&mut contacts, &Sort::Ascending("Age".into()));
+        assert_eq!(names(&contacts), "B,C,A");
+
+        // Contacts not read from a file have no modification time.
+        sort_contacts(&mut contacts, &Sort::Ascending(MTIME_FIELD.into()));
+        assert_eq!(names(&contacts), "B,C,A");
+        assert!(contacts[0].modified().is_none());
+    }
+
+    #[test]
+    fn count_matching_requires_directory() {
+        let path = Path::new("nopath/not-a-collection");
+        assert!(count_matching(path, &Condition::All).is_err());
+    }
+
     #[test]
     fn filter_equal() {
         let text = "\
@@ -831,4 +2130,116 @@ mod tests {
         assert!(contact.matches(&cond_true));
         assert!(! contact.matches(&cond_false));
     }
+
+    #[test]
+    fn filter_between() {
+        let text = "\
+        [Name: Favorite Person]\n\
+        [Age: 30]\n\
+        [Birthday: 1991-06-15]\n\
+        ";
+        let contact = Contact::new(Note::from_str(text).unwrap()).unwrap();
+
+        let between = |field: &str, low: &str, high: &str| {
+            Condition::Between(field.into(), low.into(), high.into())
+        };
+
+        assert!(contact.matches(&between("Age", "18", "30")));
+        assert!(contact.matches(&between("Age", "30", "40")));
+        assert!(contact.matches(&between("Age", "29.5", "30.5")));
+        assert!(! contact.matches(&between("Age", "18", "29")));
+        assert!(! contact.matches(&between("Age", "31", "40")));
+        assert!(! contact.matches(&between("Age", "a", "z")));
+        assert!(! contact.matches(&between("Missing", "18", "30")));
+
+        let birthday = |low, high| between("Birthday", low, high);
+        assert!(contact.matches(&birthday("1991-06-15", "1992-01-01")));
+        assert!(contact.matches(&birthday("1991-01-01", "1991-06-15")));
+        assert!(! contact.matches(&birthday("1991-06-16", "1992-01-01")));
+
+        assert!(contact.matches(&between(ANY_FIELD, "25", "35")));
+        assert!(! contact.matches(&between(ANY_FIELD, "40", "50")));
+    }
+
+    #[test]
+    fn filter_by_tag() {
+        let archived = "@friend @archived\n[Name: Old Friend]\n";
+        let current = "@friend\n[Name: Favorite Person]\n";
+
+        let archived = Contact::new(Note::from_str(archived).unwrap()).unwrap();
+        let current = Contact::new(Note::from_str(current).unwrap()).unwrap();
+
+        let not_archived = Condition::NotTag("@archived".into());
+        let friend = Condition::Tag("@friend".into());
+
+        assert!(current.matches(&not_archived));
+        assert!(! archived.matches(&not_archived));
+        assert!(archived.matches(&friend));
+        assert!(current.matches(&friend));
+
+        let cond = Condition::Or(Box::new((
+            Condition::Filter(
+                "Name".into(),
+                FilterOp::EqualTo,
+                "Old Friend".into()
+            ),
+            not_archived,
+        )));
+
+        assert!(current.matches(&cond));
+        assert!(archived.matches(&cond));
+    }
+
+    #[test]
+    fn filter_by_group_presence() {
+        let employed = "\
+        [Name: Favorite Person]\n\
+        \n\
+        @Employer\n\
+        ";
+        let unemployed = "[Name: Other Person]\n";
+
+        let employed = Contact::new(Note::from_str(employed).unwrap()).unwrap();
+        let unemployed =
+            Contact::new(Note::from_str(unemployed).unwrap()).unwrap();
+
+        let exists = Condition::Exists("employer".into());
+        let not_exists = Condition::NotExists("Employer".into());
+
+        assert!(employed.matches(&exists));
+        assert!(! unemployed.matches(&exists));
+        assert!(! employed.matches(&not_exists));
+        assert!(unemployed.matches(&not_exists));
+    }
+
+    #[test]
+    fn filter_any_field() {
+        let text = "\
+        [Name: Favorite Person]\n\
+        [Num: 123]\n\
+        \n\
+        @Employer\n\
+        [Name: Some Company]\n\
+        [Phone: 555-1234]\n\
+        ";
+
+        let contact = Contact::new(Note::from_str(text).unwrap()).unwrap();
+
+        let any = |op, value: &str| Condition::Filter(
+            ANY_FIELD.into(),
+            op,
+            value.into()
+        );
+
+        assert!(contact.matches(&any(FilterOp::EqualTo, "555-1234")));
+        assert!(contact.matches(&any(FilterOp::EqualTo, "Some Company")));
+        assert!(! contact.matches(&any(FilterOp::EqualTo, "Nobody")));
+
+        assert!(contact.matches(&any(FilterOp::GreaterThan, "100")));
+        assert!(contact.matches(&any(FilterOp::LessEq, "123")));
+        assert!(! contact.matches(&any(FilterOp::LessThan, "123")));
+
+        assert!(contact.matches(&any(FilterOp::Not, "Nobody")));
+        assert!(! contact.matches(&any(FilterOp::Not, "555-1234")));
+    }
 }
@@ -55,8 +55,36 @@
 //!     Filter the result set to only include contacts in which the values of
 //!     the given field match the regular expression.
 //! </td></tr>
+//! <tr><td><Code>REGEX_FIELDS(regex)</code></td>
+//! <td>
+//!     Only valid in the field list. Select every field whose name matches the
+//!     regular expression; the expression must match the entire name, and
+//!     fields outside the default group are matched as
+//!     <code>Group:Field</code>. For example,
+//!     <code>"REGEX_FIELDS('.*Phone'),Name"</code> selects every field ending
+//!     in "Phone" and the Name field. Because the regular expression is quoted,
+//!     a quoted field list must use the other quotation mark.
+//! </td></tr>
 //! </table>
 //!
+//! SPLIT and REF may also be used in the field list. <code>SPLIT(Children,
+//! ',')</code> prints one row per child, repeating the contact's other fields
+//! on each row, and <code>REF(Spouse).Phone</code> prints the Phone field of
+//! the contact named in the Spouse field.
+//!
+//!
+//! ## Whitespace and Comments
+//!
+//! Queries may span multiple lines. Outside of quoted strings, any run of
+//! whitespace (including newlines) is treated as a single space, and `#`
+//! begins a comment that continues to the end of the line:
+//!
+//! ```text
+//! 'Name,Phone'
+//! WHERE 'Employer:Name' = 'My Company' # Current employees only.
+//!     AND Phone NOT EMPTY
+//! ```
+//!
 //!
 //! ## Formal Grammar
 //!
@@ -66,8 +94,11 @@
 //! Query ::= FieldList ( 'WHERE' Condition )?
 //!
 //! Condition ::=
-//!     FieldName Op StringLiteral
+//!     FieldName Op Value
+//!     | FieldName 'BETWEEN' Value 'AND' Value
 //!     | FunctionClause
+//!     | TagClause
+//!     | 'NOT'? 'EXISTS' ( GroupName | QuotedGroupName )
 //!     | '(' Condition ')'
 //!     | Condition 'AND' Condition
 //!     | Condition 'OR' Condition
@@ -77,6 +108,10 @@
 //!     | Variable '=' SplitFunction
 //!     | RegexFunction
 //!
+//! TagClause ::=
+//!     'NOT'? 'TAG' Value
+//!     | '!'? '@' AnyWord
+//!
 //! RefFunction ::= 'REF' '(' ( FieldName | SplitFunction ) ')'
 //!
 //! SplitFunction ::= 'SPLIT' '(' FieldName ',' Char ')'
@@ -87,7 +122,15 @@
 //!
 //! FieldList ::= UnquotedFieldList | QuotedFieldList
 //!
-//! UnquotedFieldList ::= UnquotedFieldName ( ',' UnquotedFieldName )*
+//! UnquotedFieldList ::= FieldSelector ( ',' FieldSelector )*
+//!
+//! FieldSelector ::=
+//!     UnquotedFieldName
+//!     | RegexFieldsFunction
+//!     | SplitFunction
+//!     | RefFunction '.' AnyWord
+//!
+//! RegexFieldsFunction ::= 'REGEX_FIELDS' '(' StringLiteral ')'
 //!
 //! QuotedFieldList ::=
 //!     '\'' UnquotedFieldList '\''
@@ -95,6 +138,10 @@
 //!
 //! GroupName ::= AnyWord
 //!
+//! QuotedGroupName ::=
+//!     '\'' GroupName '\''
+//!     | '"' GroupName '"'
+//!
 //! FieldName ::= UnquotedFieldName | QuotedFieldName
 //!
 //! UnquotedFieldName ::=
@@ -113,10 +160,16 @@
 //!     | '>='
 //!     | 'NOT'
 //!
+//! Value ::= StringLiteral | Bareword
+//!
 //! StringLiteral ::=
 //!     '\'' [:printable:] '\''
 //!     | '"' [:printable:] '"'
 //!
+//! Bareword ::=
+//!     ( [:printable:] - [:whitespace:] - '\'' - '"' - '(' - ')' )+
+//!     - Reserved - 'NOT'
+//!
 //! AnyText ::= ( [:printable:] - ',' )* - Reserved
 //!
 //! AnyWord ::= ( AnyText - [:whitespace:] - [:punctuation:] )*
@@ -133,6 +186,7 @@
 use std::str::FromStr;
 
 use anyhow::Context as _;
+use regex::Regex;
 
 use upim_core::uniq::Uniq as _;
 
@@ -197,9 +251,39 @@ pub enum Function {
     // variable, field, separator
     Split(String, String, char),
     /// Match the given field's value against the provided regular expression.
-    Regex(String, String),
+    Regex(String, FieldRegex),
+}
+
+/// A regular expression compiled when its query is parsed.
+///
+/// Two expressions are equal if their patterns are the same text.
+#[derive(Clone, Debug)]
+pub struct FieldRegex(Regex);
+
+impl FieldRegex {
+    /// Compile the pattern, returning an error if it is not a valid regular
+    /// expression.
+    pub fn new(pattern: &str)
+    -> std::result::Result<Self, FunctionParseError> {
+        Regex::new(pattern)
+            .map(Self)
+            .map_err(|e| FunctionParseError::InvalidArguments(e.to_string()))
+    }
+
+    /// Check whether the expression matches any part of `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.0.is_match(text)
+    }
+}
+
+impl PartialEq for FieldRegex {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
 }
 
+impl Eq for FieldRegex {}
+
 impl FromStr for Function {
     type Err = FunctionParseError;
 
@@ -217,7 +301,7 @@ impl FromStr for Function {
                 } else {
                     Ok(Function::Regex(
                         val.trim_end().into(),
-                        expr[1..expr.len()-1].into()
+                        FieldRegex::new(&expr[1..expr.len()-1])?
                     ))
                 }
             } else {
@@ -225,6 +309,11 @@ impl FromStr for Function {
             };
         }
 
+        // Variables are never quoted; this is a quoted field name.
+        if s.starts_with('\'') || s.starts_with('"') {
+            return Err(FunctionParseError::UnknownFunction(s.into()));
+        }
+
         let mut s = s;
 
         let (len, var) = read_variable(s)
@@ -243,7 +332,7 @@ impl FromStr for Function {
         s = &s[len..s.len()].trim_start();
 
         if s.len() >= 6 {
-            let end_idx = s[7..s.len()].find(')')
+            let end_idx = s.get(7..).and_then(|rest| rest.find(')'))
                 .map(|v| v + 7);
 
             if s[0..=3].to_ascii_uppercase() == "REF(" {
@@ -278,6 +367,13 @@ impl FromStr for Function {
 
 fn parse_split_function(s: &str, var: &str)
 -> std::result::Result<Function, FunctionParseError> {
+    parse_split_args(s)
+        .map(|(field, sep)| Function::Split(var.to_owned(), field, sep))
+}
+
+/// Parse the field name and separator given to a SPLIT function.
+fn parse_split_args(s: &str)
+-> std::result::Result<(String, char), FunctionParseError> {
     if let Some((field, sp)) = s.split_once(',') {
         if ! field_name_is_valid(field) {
             return Err(FunctionParseError::InvalidArguments(field.into()));
@@ -296,11 +392,7 @@ fn parse_split_function(s: &str, var: &str)
             ));
         }
 
-        Ok(Function::Split(
-            var.to_owned(),
-            field.into(),
-            split_str.chars().nth(1).unwrap()
-        ))
+        Ok((field.into(), split_str.chars().nth(1).unwrap()))
     } else {
         Err(FunctionParseError::InvalidArguments(
             "Invalid arguments to SPLIT function".into()
@@ -308,12 +400,28 @@ fn parse_split_function(s: &str, var: &str)
     }
 }
 
+/// The field name used in a [Condition::Filter] to compare against every
+/// field of a contact.
+///
+/// In a filter string, this is written as `*` or `ANY`.
+pub const ANY_FIELD: &str = "*";
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Condition {
     All, // Unfiltered.
     // Field, op, value
     Filter(String, FilterOp, String),
+    // Field, low, high. The range is inclusive.
+    Between(String, String, String),
     Function(Function),
+    // The contact has the tag. Tags include the '@' prefix.
+    Tag(String),
+    // The contact does not have the tag.
+    NotTag(String),
+    // The contact has the group, whatever its fields.
+    Exists(String),
+    // The contact does not have the group.
+    NotExists(String),
     // Logical and with the contained conditions.
     And(Box<(Condition, Condition)>),
     // Logical or with the contained conditions.
@@ -324,6 +432,23 @@ impl Default for Condition {
     fn default() -> Self { Self::All }
 }
 
+impl Condition {
+    /// Retrieve the variables bound to a referenced contact by
+    /// `variable = REF(field)`, as (variable, field) pairs.
+    pub fn ref_variables(&self) -> Vec<(&str, &str)> {
+        match self {
+            Condition::Function(Function::Ref(var, Either::Left(field))) =>
+                vec![(var.as_str(), field.as_str())],
+            Condition::And(inner) | Condition::Or(inner) => {
+                let mut vars = inner.0.ref_variables();
+                vars.append(&mut inner.1.ref_variables());
+                vars
+            },
+            _ => vec![],
+        }
+    }
+}
+
 impl FromStr for Condition {
     type Err = ConditionConversionError;
 
@@ -345,9 +470,7 @@ impl FromStr for Condition {
         }
         s = &s[len..s.len()].trim_start();
 
-        let ops = ["AND ", "OR "];
-
-        if let Some((i, op)) = find_any_str(&s.to_ascii_uppercase(), &ops) {
+        if let Some((i, op)) = find_conjunction(s) {
             let lhs = &s[0..i].trim_end();
             let rhs = &s[i + op.len() .. s.len()].trim_start();
 
@@ -364,6 +487,12 @@ impl FromStr for Condition {
                 "OR " => Ok(Condition::Or(Box::new((cond1, cond2)))),
                 _ => Err(Self::Err::UnknownOperator(op.to_owned())),
             }
+        } else if let Some(cond) = read_tag_condition(s) {
+            Ok(cond)
+        } else if let Some(cond) = read_exists_condition(s) {
+            Ok(cond)
+        } else if let Some(cond) = read_between(s) {
+            cond
         } else {
             match Function::from_str(s) {
                 Ok(f) => {
@@ -374,14 +503,14 @@ impl FromStr for Condition {
                     // If it doesn't look like an attempt to call a function, we
                     // assume its matching a field.
 
-                    let (len, field) = read_field(s)?;
+                    let (len, field) = read_condition_field(s)?;
                     s = &s[len..s.len()].trim_start();
 
                     let (len, op) = read_op(s)?;
                     s = &s[len..s.len()].trim();
 
                     // The rest of the string should either be EMPTY, a string,
-                    // or a number.
+                    // a single unquoted word, or a number.
                     // EMPTY or strings require the = or NOT operators.
 
                     let s = match s {
@@ -389,22 +518,22 @@ impl FromStr for Condition {
                         _ => s,
                     };
 
-                    if is_quoted(s) {
-                        if !(op == FilterOp::EqualTo || op == FilterOp::Not) {
-                            Err(Self::Err::BadComparison(
-                                "Cannot make comparison with string".to_owned()
-                            ))
-                        } else {
-                            Ok(Condition::Filter(
-                                field,
-                                op,
-                                s[1..s.len()-1].into()
-                            ))
-                        }
-                    } else if s.parse::<f64>().is_ok() {
-                        Ok(Condition::Filter(field, op, s.into()))
+                    let value = if s.parse::<f64>().is_ok() {
+                        return Ok(Condition::Filter(field, op, s.into()));
+                    } else if is_quoted(s) {
+                        &s[1..s.len()-1]
+                    } else if is_bareword(s) {
+                        s
                     } else {
-                        Err(Self::Err::UnquotedString(s.to_owned()))
+                        return Err(Self::Err::UnquotedString(s.to_owned()));
+                    };
+
+                    if !(op == FilterOp::EqualTo || op == FilterOp::Not) {
+                        Err(Self::Err::BadComparison(
+                            "Cannot make comparison with string".to_owned()
+                        ))
+                    } else {
+                        Ok(Condition::Filter(field, op, value.into()))
                     }
                 },
                 Err(e) => {
@@ -427,7 +556,8 @@ impl FromStr for Query {
     type Err = QueryConversionError;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        let mut s = s;
+        let s = normalize_whitespace(s);
+        let mut s = s.as_str();
         let mut f = Self::default();
 
         let (idx, select) = read_fields(&s)?;
@@ -454,6 +584,23 @@ impl FromStr for Query {
 }
 
 impl Query {
+    /// Check whether any field in the field list refers to another contact,
+    /// either directly or through a variable bound by the condition.
+    pub fn selects_references(&self) -> bool {
+        let vars = self.condition.ref_variables();
+
+        self.select.iter().any(|f| {
+            let is_var = f.split_once('.')
+                .map(|(var, _)| vars.iter().any(|(v, _)| *v == var))
+                .unwrap_or(false);
+
+            is_var || matches!(
+                parse_select_function(f),
+                Some(Ok(SelectFunction::Ref(_, _)))
+            )
+        })
+    }
+
     pub fn merge_with(self, other: Query) -> Query {
         let condition = Condition::And(Box::new((
             self.condition,
@@ -474,6 +621,249 @@ impl Query {
     }
 }
 
+/// Remove comments from the query and replace each run of whitespace outside
+/// of quoted strings with a single space.
+fn normalize_whitespace(s: &str) -> String {
+    let mut normalized = String::with_capacity(s.len());
+    let mut quote = None;
+    let mut chars = s.trim().chars();
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) => {
+                if c == q { quote = None; }
+                normalized.push(c);
+            },
+            None if c == '#' || c.is_whitespace() => {
+                if c == '#' { chars.by_ref().find(|c| *c == '\n'); }
+
+                if ! normalized.is_empty() && ! normalized.ends_with(' ') {
+                    normalized.push(' ');
+                }
+            },
+            None => {
+                if c == '\'' || c == '"' { quote = Some(c); }
+                normalized.push(c);
+            },
+        }
+    }
+
+    normalized.trim_end().to_owned()
+}
+
+/// Build a [Query] without parsing a filter string.
+///
+/// Field names and values are used as given, so they need no quoting.
+///
+/// Conditions are joined via [QueryBuilder::and] and [QueryBuilder::or], and
+/// are grouped the same way the parser groups them: `a AND b OR c` is
+/// `a AND (b OR c)`. Conditions without a conjunction between them are joined
+/// by AND, and a trailing conjunction is ignored.
+// For applications that build queries from structured input; upim-contact
+// reads its queries from the command line and configuration.
+#[allow(dead_code)]
+#[derive(Clone, Debug, Default)]
+pub struct QueryBuilder {
+    select: Vec<String>,
+    conditions: Vec<Condition>,
+    /// `joins[i]` joins `conditions[i]` and `conditions[i+1]`; true for AND.
+    joins: Vec<bool>,
+    next_join: Option<bool>,
+}
+
+#[allow(dead_code)]
+impl QueryBuilder {
+    /// Create a builder with no fields or conditions.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add fields to the field list.
+    pub fn select(mut self, fields: &[&str]) -> Self {
+        self.select.extend(fields.iter().map(|f| f.to_string()));
+        self
+    }
+
+    /// Add a condition that the field is equal to the value.
+    pub fn where_eq(self, field: &str, value: &str) -> Self {
+        self.where_op(field, FilterOp::EqualTo, value)
+    }
+
+    /// Add a condition that the field is not equal to the value.
+    pub fn where_not(self, field: &str, value: &str) -> Self {
+        self.where_op(field, FilterOp::Not, value)
+    }
+
+    /// Add a comparison between the field and value.
+    pub fn where_op(self, field: &str, op: FilterOp, value: &str) -> Self {
+        self.where_condition(
+            Condition::Filter(field.into(), op, value.into())
+        )
+    }
+
+    /// Add an arbitrary condition, such as a function or a group of
+    /// conditions built separately.
+    pub fn where_condition(mut self, condition: Condition) -> Self {
+        if ! self.conditions.is_empty() {
+            self.joins.push(self.next_join.take().unwrap_or(true));
+        }
+        self.conditions.push(condition);
+        self
+    }
+
+    /// Join the previous and next conditions with AND.
+    pub fn and(mut self) -> Self {
+        self.next_join = Some(true);
+        self
+    }
+
+    /// Join the previous and next conditions with OR.
+    pub fn or(mut self) -> Self {
+        self.next_join = Some(false);
+        self
+    }
+
+    /// Create the query.
+    ///
+    /// Without any conditions, the query matches every contact.
+    pub fn build(self) -> Query {
+        let mut conditions = self.conditions;
+        let mut condition = conditions.pop().unwrap_or_default();
+
+        for (lhs, is_and) in conditions.into_iter().zip(self.joins).rev() {
+            let pair = Box::new((lhs, condition));
+
+            condition = if is_and {
+                Condition::And(pair)
+            } else {
+                Condition::Or(pair)
+            };
+        }
+
+        Query { select: self.select, condition }
+    }
+}
+
+/// Parse a `REGEX_FIELDS(regex)` field selector.
+///
+/// Returns `None` if the field is not a `REGEX_FIELDS` selector; otherwise
+/// returns the regular expression, anchored so that it must match an entire
+/// field name.
+pub fn parse_regex_fields(field: &str)
+-> Option<std::result::Result<Regex, FunctionParseError>> {
+    let name = "REGEX_FIELDS";
+    let field = field.trim();
+
+    if field.len() <= name.len()
+        || ! field.is_char_boundary(name.len())
+        || field[..name.len()].to_ascii_uppercase() != name
+    {
+        return None;
+    }
+
+    let args = &field[name.len()..];
+    if ! args.starts_with('(') { return None; }
+
+    Some(match get_inner_expression(args) {
+        Ok((len, pattern)) if len == args.len() => {
+            let pattern = pattern.trim();
+
+            if is_quoted(pattern) {
+                Regex::new(&format!("^(?:{})$", &pattern[1..pattern.len()-1]))
+                    .map_err(|e| FunctionParseError::InvalidArguments(
+                        e.to_string()
+                    ))
+            } else {
+                Err(FunctionParseError::InvalidArguments(
+                    "Expected string literal".into()
+                ))
+            }
+        },
+        Ok(_) => Err(FunctionParseError::InvalidArguments(field.into())),
+        Err(_) => Err(FunctionParseError::MissingClosingParenthesis),
+    })
+}
+
+/// A computed field in the field list.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SelectFunction {
+    /// Print each segment of the split field on its own row.
+    // field, separator
+    Split(String, char),
+    /// Print a field of the contact named by the value of another field.
+    // reference field, field of the referenced contact
+    Ref(String, String),
+}
+
+/// Parse a `SPLIT(field, separator)` or `REF(field).Field` field selector.
+///
+/// Returns `None` if the field is not a function call.
+pub fn parse_select_function(field: &str)
+-> Option<std::result::Result<SelectFunction, FunctionParseError>> {
+    let field = field.trim();
+    let upper = field.to_ascii_uppercase();
+
+    let name_len = if upper.starts_with("SPLIT(") {
+        "SPLIT".len()
+    } else if upper.starts_with("REF(") {
+        "REF".len()
+    } else {
+        return None;
+    };
+
+    let (len, args) = match get_inner_expression(&field[name_len..]) {
+        Ok(res) => res,
+        Err(_) => {
+            return Some(Err(FunctionParseError::MissingClosingParenthesis));
+        },
+    };
+    let rest = &field[name_len + len..];
+
+    Some(if name_len == "SPLIT".len() {
+        if rest.is_empty() {
+            parse_split_args(args)
+                .map(|(f, sep)| SelectFunction::Split(f.trim().into(), sep))
+        } else {
+            Err(FunctionParseError::InvalidArguments(field.into()))
+        }
+    } else {
+        let args = args.trim();
+
+        match rest.strip_prefix('.') {
+            Some(target) if ! args.is_empty() && ! target.is_empty()
+                && field_name_is_valid(args) && field_name_is_valid(target)
+                => Ok(SelectFunction::Ref(args.into(), target.into())),
+            _ => Err(FunctionParseError::InvalidArguments(field.into())),
+        }
+    })
+}
+
+/// Split a list of fields on each comma that is not within parenthesis or
+/// quotation marks.
+fn split_field_list(s: &str) -> Vec<&str> {
+    let mut fields = vec![];
+    let mut level = 0;
+    let mut quote = None;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        match (c, quote) {
+            ('\'', None) | ('"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('(', None) => level += 1,
+            (')', None) => level -= 1,
+            (',', None) if level == 0 => {
+                fields.push(&s[start..i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+
+    fields.push(&s[start..]);
+    fields
+}
+
 /// Return the (char) index of the leftmost of any element in `patterns` in the
 /// given string.
 fn find_any_str<'a>(s: &str, patterns: &'a [&'a str])
@@ -502,11 +892,11 @@ fn find_any(s: &str, patterns: &[char]) -> Option<(usize, char)> {
         .find(|c| patterns.contains(&c.1))
 }
 
-/// Check whether the specified field name is valid.
+/// Check whether the specified unquoted field name is valid.
 ///
 /// # Validation rules
 ///
-/// Fields may not contain the following strings:
+/// Unquoted fields may not contain the following strings:
 /// - " WHERE "
 /// - " AND "
 /// - " OR "
@@ -515,7 +905,7 @@ fn find_any(s: &str, patterns: &[char]) -> Option<(usize, char)> {
 /// # Notes
 ///
 /// These restrictions are due to limitations of the parser implementation and
-/// may be lifted in the future.
+/// may be lifted in the future. They do not apply to quoted field names.
 fn field_name_is_valid(field: &str) -> bool {
     let disallowed = [" WHERE ", " AND ", " OR ", "\"", "'"];
     find_any_str(&field.to_ascii_uppercase(), &disallowed).is_none()
@@ -537,6 +927,21 @@ pub(crate) fn is_quoted(s: &str) -> bool {
     }
 }
 
+/// Determine whether the provided string is a single word that may be used as
+/// a string value without quotation marks.
+///
+/// A bareword cannot contain whitespace, quotation marks, or parenthesis, and
+/// cannot be a reserved word or operator.
+fn is_bareword(s: &str) -> bool {
+    let reserved = ["AND", "OR", "WHERE", "NOT"];
+
+    ! s.is_empty()
+        && ! s.contains(|c: char| {
+            c.is_whitespace() || ['\'', '"', '(', ')'].contains(&c)
+        })
+        && ! reserved.contains(&s.to_ascii_uppercase().as_str())
+}
+
 /// Get the text within matching parenthesis
 ///
 /// Returns the text (excluding the parenthesis) and the number of characters
@@ -587,7 +992,8 @@ fn read_field(s: &str)
 
     match end_idx {
         Some(i) => {
-            if field_name_is_valid(&s[start_idx..i]) {
+            // A quoted name may contain anything but its closing quote.
+            if start_idx == 1 || field_name_is_valid(&s[start_idx..i]) {
             Ok((
                 i + start_idx, // Re-add the skipped quote if necessary.
                 s[start_idx..i].into()
@@ -635,11 +1041,18 @@ fn read_fields(s: &str)
             let mut is_valid = true;
             let res = (
                 i + start_idx, // Re-add the skipped quote if necessary.
-                s[start_idx..i]
-                    .split(',')
-                    .inspect(|s| if ! field_name_is_valid(s)
-                        { is_valid = false; }
-                    )
+                split_field_list(&s[start_idx..i])
+                    .into_iter()
+                    .inspect(|s| {
+                        let valid = if let Some(re) = parse_regex_fields(s) {
+                            re.is_ok()
+                        } else if let Some(f) = parse_select_function(s) {
+                            f.is_ok()
+                        } else {
+                            field_name_is_valid(s)
+                        };
+                        if ! valid { is_valid = false; }
+                    })
                     .map(|s| s.to_string())
                     .collect()
             );
@@ -666,6 +1079,170 @@ fn read_variable(s: &str) -> anyhow::Result<(usize, String)> {
     Ok((idx + 1, s[0..idx].trim().into()))
 }
 
+/// Read a tag condition: `TAG value`, `NOT TAG value`, `@tag`, or `!@tag`.
+///
+/// Returns `None` if the string is not a tag condition.
+fn read_tag_condition(s: &str) -> Option<Condition> {
+    let s = s.trim();
+    let upper = s.to_ascii_uppercase();
+
+    let (negated, tag) = if let Some(tag) = s.strip_prefix("!@") {
+        (true, tag)
+    } else if let Some(tag) = s.strip_prefix('@') {
+        (false, tag)
+    } else if upper.starts_with("NOT TAG ") {
+        (true, s["NOT TAG ".len()..].trim_start())
+    } else if upper.starts_with("TAG ") {
+        (false, s["TAG ".len()..].trim_start())
+    } else {
+        return None;
+    };
+
+    let tag = if is_quoted(tag) {
+        &tag[1..tag.len()-1]
+    } else if is_bareword(tag) && FilterOp::from_str(tag).is_err() {
+        tag
+    } else {
+        return None;
+    };
+
+    let tag = format!("@{}", tag.strip_prefix('@').unwrap_or(tag));
+
+    Some(if negated { Condition::NotTag(tag) } else { Condition::Tag(tag) })
+}
+
+/// Read a group condition: `EXISTS group` or `NOT EXISTS group`.
+fn read_exists_condition(s: &str) -> Option<Condition> {
+    let s = s.trim();
+    let upper = s.to_ascii_uppercase();
+
+    let (negated, group) = if upper.starts_with("NOT EXISTS ") {
+        (true, s["NOT EXISTS ".len()..].trim_start())
+    } else if upper.starts_with("EXISTS ") {
+        (false, s["EXISTS ".len()..].trim_start())
+    } else {
+        return None;
+    };
+
+    let group = if is_quoted(group) {
+        &group[1..group.len()-1]
+    } else if is_bareword(group) && FilterOp::from_str(group).is_err() {
+        group
+    } else {
+        return None;
+    };
+
+    Some(if negated {
+        Condition::NotExists(group.into())
+    } else {
+        Condition::Exists(group.into())
+    })
+}
+
+/// Find the first AND or OR that joins two conditions.
+///
+/// Only a whole word is a conjunction, so a bareword such as `Portland` is not
+/// split. The AND within `field BETWEEN low AND high` is part of its condition
+/// and is skipped, as is any AND or OR within a quoted string.
+///
+/// Returns the byte index and the conjunction, including its trailing space.
+fn find_conjunction(s: &str) -> Option<(usize, &'static str)> {
+    let upper = s.to_ascii_uppercase();
+    let mut start = 0;
+
+    loop {
+        let (i, op) = ["AND ", "OR "].iter()
+            .filter_map(|op| upper[start..].find(op).map(|i| (start + i, *op)))
+            .min()?;
+
+        // A bareword cannot contain a quotation mark or parenthesis, so
+        // either may end the word before the conjunction.
+        let starts_word = upper[..i].chars().next_back()
+            .map(|c| c.is_whitespace() || ['\'', '"', ')'].contains(&c))
+            .unwrap_or(true);
+
+        let mut words = upper[..i].split_whitespace().rev();
+        let ends_range = words.next().is_some()
+            && words.next() == Some("BETWEEN");
+
+        if ! starts_word
+            || (op == "AND " && ends_range)
+            || is_within_quotes(s, i)
+        {
+            start = i + op.len();
+        } else {
+            return Some((i, op));
+        }
+    }
+}
+
+/// Determine whether the byte index `idx` of `s` lies within a quoted string.
+pub(crate) fn is_within_quotes(s: &str, idx: usize) -> bool {
+    let mut quote = None;
+
+    for (_, c) in s.char_indices().take_while(|(i, _)| *i < idx) {
+        match (c, quote) {
+            ('\'', None) | ('"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            _ => {},
+        }
+    }
+
+    quote.is_some()
+}
+
+/// Read the field name of a condition, normalizing an unquoted `ANY` to
+/// [ANY_FIELD].
+///
+/// Returns the number of characters read and the field name.
+fn read_condition_field(s: &str)
+-> std::result::Result<(usize, String), ConditionConversionError> {
+    let (len, field) = read_field(s)?;
+
+    if len == field.len() && field.eq_ignore_ascii_case("ANY") {
+        Ok((len, ANY_FIELD.into()))
+    } else {
+        Ok((len, field))
+    }
+}
+
+/// Read a `field BETWEEN low AND high` condition.
+///
+/// Returns `None` if the string is not a BETWEEN condition.
+fn read_between(s: &str)
+-> Option<std::result::Result<Condition, ConditionConversionError>> {
+    let (len, field) = read_condition_field(s).ok()?;
+    let s = s[len..].trim_start();
+
+    let s = match s.get(..8) {
+        Some(w) if w.eq_ignore_ascii_case("BETWEEN ") => &s[8..],
+        _ => return None,
+    };
+
+    let i = match s.to_ascii_uppercase().find(" AND ") {
+        Some(i) => i,
+        None => return Some(Err(ConditionConversionError::Invalid(format!(
+            "Expected BETWEEN <value> AND <value>: {}", s
+        )))),
+    };
+
+    let bound = |b: &str| {
+        let b = b.trim();
+
+        if is_quoted(b) {
+            Ok(b[1..b.len()-1].to_owned())
+        } else if is_bareword(b) {
+            Ok(b.to_owned())
+        } else {
+            Err(ConditionConversionError::UnquotedString(b.to_owned()))
+        }
+    };
+
+    Some(bound(&s[..i]).and_then(|low| {
+        Ok(Condition::Between(field, low, bound(&s[i + 5..])?))
+    }))
+}
+
 /// Read a filter operator from the input string.
 ///
 /// # Returns
@@ -747,6 +1324,174 @@ mod tests {
         assert_eq!(fields[1], "B Field");
     }
 
+    #[test]
+    fn read_regex_fields_selector() {
+        let text = "\"REGEX_FIELDS('.*Phone'),Name\" WHERE Name = 'Somebody'";
+
+        let (len, fields) = read_fields(text).unwrap();
+        assert_eq!(len, 30);
+        assert_eq!(fields, ["REGEX_FIELDS('.*Phone')", "Name"]);
+
+        let text = "Name,REGEX_FIELDS('[a-z]{1,3}') WHERE Name = 'Somebody'";
+
+        let (_, fields) = read_fields(text).unwrap();
+        assert_eq!(fields, ["Name", "REGEX_FIELDS('[a-z]{1,3}')"]);
+    }
+
+    #[test]
+    fn parse_regex_fields_selector() {
+        let re = parse_regex_fields("REGEX_FIELDS('.*Phone')").unwrap()
+            .unwrap();
+
+        assert!(re.is_match("Home Phone"));
+        assert!(re.is_match("Phone"));
+        assert!(! re.is_match("Phone Notes"));
+
+        assert!(parse_regex_fields("Phone").is_none());
+        assert!(parse_regex_fields("REGEX_FIELDS").is_none());
+        assert!(parse_regex_fields("REGEX_FIELDS(.*)").unwrap().is_err());
+        assert!(parse_regex_fields("REGEX_FIELDS('(')").unwrap().is_err());
+        assert!(parse_regex_fields("REGEX_FIELDS('a'").unwrap().is_err());
+    }
+
+    #[test]
+    fn read_function_selectors() {
+        let text = "\"Name,SPLIT(Children, ','),REF(Spouse).Phone\" WHERE \
+            Name = 'Somebody'";
+
+        let (_, fields) = read_fields(text).unwrap();
+        assert_eq!(
+            fields,
+            ["Name", "SPLIT(Children, ',')", "REF(Spouse).Phone"]
+        );
+
+        let text = "\"SPLIT(Children)\" WHERE Name = 'Somebody'";
+        assert!(read_fields(text).is_err());
+    }
+
+    #[test]
+    fn parse_select_function_selector() {
+        assert_eq!(
+            parse_select_function("SPLIT(Children, ',')").unwrap().unwrap(),
+            SelectFunction::Split("Children".into(), ',')
+        );
+        assert_eq!(
+            parse_select_function("split(Children,';')").unwrap().unwrap(),
+            SelectFunction::Split("Children".into(), ';')
+        );
+        assert_eq!(
+            parse_select_function("REF(Spouse).Name").unwrap().unwrap(),
+            SelectFunction::Ref("Spouse".into(), "Name".into())
+        );
+
+        assert!(parse_select_function("Children").is_none());
+        assert!(parse_select_function("REF(Spouse)").unwrap().is_err());
+        assert!(parse_select_function("REF(Spouse).").unwrap().is_err());
+        assert!(parse_select_function("SPLIT(Children, ',').Name")
+            .unwrap().is_err());
+        assert!(parse_select_function("SPLIT(Children, ','")
+            .unwrap().is_err());
+    }
+
+    #[test]
+    fn parse_multi_line_query_with_comments() {
+        let multi_line = "\
+            # Employees\n\
+            'Name,Phone'  # The fields to print.\n\
+            WHERE\n\
+            \t'Employer:Name' = 'My # Company'\n\
+            # Only those we can call:\n\
+            AND Phone NOT EMPTY   \n\
+            ";
+        let single_line = "'Name,Phone' WHERE 'Employer:Name' = 'My # Company' \
+            AND Phone NOT EMPTY";
+
+        assert_eq!(
+            Query::from_str(multi_line).unwrap(),
+            Query::from_str(single_line).unwrap()
+        );
+        assert_eq!(
+            Query::from_str("Name WHERE Name = 'Two\n  Lines'").unwrap()
+                .condition,
+            Condition::Filter(
+                "Name".into(),
+                FilterOp::EqualTo,
+                "Two\n  Lines".into()
+            )
+        );
+    }
+
+    #[test]
+    fn build_query() {
+        let built = QueryBuilder::new()
+            .select(&["Name", "Phone"])
+            .where_eq("Name", "Some Person")
+            .and()
+            .where_eq("Employer:Name", "My Company")
+            .or()
+            .where_not("Phone", "")
+            .build();
+
+        let parsed = Query::from_str(
+            "'Name,Phone' WHERE Name = 'Some Person' \
+            AND 'Employer:Name' = 'My Company' OR Phone NOT EMPTY"
+        ).unwrap();
+
+        assert_eq!(built, parsed);
+
+        let built = QueryBuilder::new()
+            .select(&["Name"])
+            .where_op("Num", FilterOp::GreaterEq, "10")
+            .where_eq("Name", "Smith")
+            .build();
+
+        assert_eq!(
+            built,
+            Query::from_str("Name WHERE Num >= 10 AND Name = Smith").unwrap()
+        );
+
+        assert_eq!(
+            QueryBuilder::new().select(&["Name"]).build(),
+            Query::from_str("Name").unwrap()
+        );
+    }
+
+    #[test]
+    fn query_selects_references() {
+        let query = Query::from_str("Name,REF(Spouse).Name").unwrap();
+        assert!(query.selects_references());
+
+        let query = Query::from_str("Name,SPLIT(Children,',')").unwrap();
+        assert!(! query.selects_references());
+
+        let query = Query::from_str("Name,s.Name WHERE s = REF(Spouse)")
+            .unwrap();
+        assert!(query.selects_references());
+
+        let query = Query::from_str("Name,t.Name WHERE s = REF(Spouse)")
+            .unwrap();
+        assert!(! query.selects_references());
+    }
+
+    #[test]
+    fn condition_ref_variables() {
+        let cond = Condition::from_str(
+            "Name = 'Person' AND s = REF(Spouse) OR e = REF(Employer)"
+        ).unwrap();
+
+        assert_eq!(
+            cond.ref_variables(),
+            [("s", "Spouse"), ("e", "Employer")]
+        );
+        assert!(Condition::All.ref_variables().is_empty());
+    }
+
+    #[test]
+    fn error_on_read_of_invalid_regex_fields() {
+        let text = "\"REGEX_FIELDS('(')\" WHERE Name = 'Somebody'";
+        assert!(read_fields(text).is_err());
+    }
+
     #[test]
     fn error_on_read_of_invalid_field() {
         let text = "'Field, and other' more text";
@@ -809,6 +1554,226 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_condition_quoted_field_with_reserved_word() {
+        let filter = Condition::Filter(
+            "Cats OR Dogs".into(),
+            FilterOp::EqualTo,
+            "yes".into()
+        );
+
+        assert_eq!(
+            Condition::from_str("'Cats OR Dogs' = yes").unwrap(),
+            filter
+        );
+        assert_eq!(
+            Condition::from_str("\"Cats OR Dogs\" = yes OR Name = Smith")
+                .unwrap(),
+            Condition::Or(Box::new((
+                filter,
+                Condition::Filter(
+                    "Name".into(),
+                    FilterOp::EqualTo,
+                    "Smith".into()
+                ),
+            )))
+        );
+
+        assert_eq!(
+            Query::from_str("Name WHERE 'A WHERE B' = x").unwrap(),
+            Query {
+                select: vec!["Name".into()],
+                condition: Condition::Filter(
+                    "A WHERE B".into(),
+                    FilterOp::EqualTo,
+                    "x".into()
+                ),
+            }
+        );
+
+        // Unquoted, the OR still joins two conditions.
+        assert!(Condition::from_str("Cats OR Dogs = yes").is_err());
+    }
+
+    #[test]
+    fn parse_condition_quoted_field_with_space() {
+        assert_eq!(
+            Condition::from_str("'Home Phone' = 555-1234").unwrap(),
+            Condition::Filter(
+                "Home Phone".into(),
+                FilterOp::EqualTo,
+                "555-1234".into()
+            )
+        );
+    }
+
+    #[test]
+    fn parse_condition_quoted_value_with_reserved_word() {
+        assert_eq!(
+            Condition::from_str("Name = 'Smith OR Jones'").unwrap(),
+            Condition::Filter(
+                "Name".into(),
+                FilterOp::EqualTo,
+                "Smith OR Jones".into()
+            )
+        );
+    }
+
+    #[test]
+    fn parse_condition_by_unquoted_field_value() {
+        let text = "Name = Smith";
+
+        let cond = Condition::from_str(text).unwrap();
+        assert_eq!(cond,
+            Condition::Filter(
+                "Name".into(),
+                FilterOp::EqualTo,
+                "Smith".into()
+            )
+        );
+    }
+
+    #[test]
+    fn parse_condition_any_field() {
+        let expected = Condition::Filter(
+            ANY_FIELD.into(),
+            FilterOp::EqualTo,
+            "555-1234".into()
+        );
+
+        assert_eq!(Condition::from_str("* = 555-1234").unwrap(), expected);
+        assert_eq!(Condition::from_str("ANY = 555-1234").unwrap(), expected);
+        assert_eq!(Condition::from_str("any = 555-1234").unwrap(), expected);
+
+        assert_eq!(
+            Condition::from_str("* >= 10").unwrap(),
+            Condition::Filter(
+                ANY_FIELD.into(),
+                FilterOp::GreaterEq,
+                "10".into()
+            )
+        );
+
+        // A quoted 'ANY' is a field with that name.
+        assert_eq!(
+            Condition::from_str("'ANY' = 1").unwrap(),
+            Condition::Filter("ANY".into(), FilterOp::EqualTo, "1".into())
+        );
+    }
+
+    #[test]
+    fn parse_condition_exists() {
+        let exists = Condition::Exists("Employer".into());
+        let not_exists = Condition::NotExists("Employer".into());
+
+        assert_eq!(Condition::from_str("EXISTS Employer").unwrap(), exists);
+        assert_eq!(Condition::from_str("exists 'Employer'").unwrap(), exists);
+        assert_eq!(
+            Condition::from_str("NOT EXISTS Employer").unwrap(),
+            not_exists
+        );
+        assert_eq!(
+            Condition::from_str("EXISTS 'Old Employer'").unwrap(),
+            Condition::Exists("Old Employer".into())
+        );
+
+        assert_eq!(
+            Condition::from_str("@friend AND NOT EXISTS Employer").unwrap(),
+            Condition::And(Box::new((
+                Condition::Tag("@friend".into()),
+                not_exists
+            )))
+        );
+
+        assert!(Condition::from_str("EXISTS").is_err());
+    }
+
+    #[test]
+    fn parse_condition_tags() {
+        let tag = Condition::Tag("@friend".into());
+        let not_tag = Condition::NotTag("@archived".into());
+
+        assert_eq!(Condition::from_str("TAG friend").unwrap(), tag);
+        assert_eq!(Condition::from_str("tag '@friend'").unwrap(), tag);
+        assert_eq!(Condition::from_str("@friend").unwrap(), tag);
+        assert_eq!(Condition::from_str("NOT TAG 'archived'").unwrap(), not_tag);
+        assert_eq!(Condition::from_str("!@archived").unwrap(), not_tag);
+
+        assert_eq!(
+            Condition::from_str("Name = Smith AND !@archived").unwrap(),
+            Condition::And(Box::new((
+                Condition::Filter(
+                    "Name".into(),
+                    FilterOp::EqualTo,
+                    "Smith".into()
+                ),
+                not_tag
+            )))
+        );
+
+        // A field named "Tag" is still a field.
+        assert_eq!(
+            Condition::from_str("Tag = friend").unwrap(),
+            Condition::Filter("Tag".into(), FilterOp::EqualTo, "friend".into())
+        );
+    }
+
+    #[test]
+    fn parse_condition_between() {
+        let age = Condition::Between("Age".into(), "18".into(), "30".into());
+
+        assert_eq!(Condition::from_str("Age BETWEEN 18 AND 30").unwrap(), age);
+        assert_eq!(Condition::from_str("Age between 18 and 30").unwrap(), age);
+        assert_eq!(
+            Condition::from_str(
+                "Birthday BETWEEN '2000-01-01' AND 2000-12-31"
+            ).unwrap(),
+            Condition::Between(
+                "Birthday".into(),
+                "2000-01-01".into(),
+                "2000-12-31".into()
+            )
+        );
+
+        assert_eq!(
+            Condition::from_str("Age BETWEEN 18 AND 30 AND @friend").unwrap(),
+            Condition::And(Box::new((
+                age.clone(),
+                Condition::Tag("@friend".into())
+            )))
+        );
+        assert_eq!(
+            Condition::from_str("@friend OR Age BETWEEN 18 AND 30").unwrap(),
+            Condition::Or(Box::new((Condition::Tag("@friend".into()), age)))
+        );
+
+        assert!(Condition::from_str("Age BETWEEN 18").is_err());
+        assert!(Condition::from_str("Age BETWEEN 18 AND two words").is_err());
+    }
+
+    #[test]
+    fn parse_condition_multiple_words_must_be_quoted() {
+        assert!(matches!(
+            Condition::from_str("Name = Two Words"),
+            Err(ConditionConversionError::UnquotedString(_))
+        ));
+        assert!(Condition::from_str("Name = 'Two Words'").is_ok());
+    }
+
+    #[test]
+    fn parse_condition_unquoted_value_cannot_be_reserved() {
+        assert!(Condition::from_str("Name = WHERE").is_err());
+        assert!(Condition::from_str("Name = (Smith)").is_err());
+    }
+
+    #[test]
+    fn parse_condition_unquoted_value_is_a_string() {
+        assert!(matches!(
+            Condition::from_str("Name > Smith"),
+            Err(ConditionConversionError::BadComparison(_))
+        ));
+    }
+
     #[test]
     fn parse_condition_field_empty() {
         let text = "Phone = EMPTY";
@@ -889,11 +1854,28 @@ mod tests {
         let cond = Condition::from_str(text).unwrap();
         assert_eq!(cond,
             Condition::Function(
-                Function::Regex("SomeField".into(), ".*regex.*".into())
+                Function::Regex(
+                    "SomeField".into(),
+                    FieldRegex::new(".*regex.*").unwrap()
+                )
             )
         );
     }
 
+    #[test]
+    fn parse_regex_function_with_invalid_pattern_is_err() {
+        let text = "Name WHERE REGEX(SomeField, '(unclosed')";
+
+        assert!(matches!(
+            Query::from_str(text),
+            Err(QueryConversionError::Condition(
+                ConditionConversionError::Function(
+                    FunctionParseError::InvalidArguments(_)
+                )
+            ))
+        ));
+    }
+
     #[test]
     fn parse_filter_and_filter() {
         let text = "Name = 'Person' AND Phone > 1";
@@ -1009,6 +1991,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_conjunction_after_bareword_ending_in_conjunction() {
+        let filter = |field: &str, op, value: &str| {
+            Condition::Filter(field.into(), op, value.into())
+        };
+
+        let cond = Condition::from_str("City = Portland OR City = Salem")
+            .unwrap();
+        assert_eq!(cond,
+            Condition::Or(Box::new((
+                filter("City", FilterOp::EqualTo, "Portland"),
+                filter("City", FilterOp::EqualTo, "Salem"),
+            )))
+        );
+
+        let cond = Condition::from_str("Name = Thor AND Age > 3").unwrap();
+        assert_eq!(cond,
+            Condition::And(Box::new((
+                filter("Name", FilterOp::EqualTo, "Thor"),
+                filter("Age", FilterOp::GreaterThan, "3"),
+            )))
+        );
+    }
+
     #[test]
     fn parse_parens_inner_on_right() {
         let text = "(a = 'b' AND (b = 'c' AND c = 'd'))";
@@ -1073,6 +2079,16 @@ mod tests {
             });
     }
 
+    #[test]
+    fn determine_bareword() {
+        assert!(is_bareword("Smith"));
+        assert!(is_bareword("x@example.com"));
+        assert!(! is_bareword("Two Words"));
+        assert!(! is_bareword("'Smith'"));
+        assert!(! is_bareword("and"));
+        assert!(! is_bareword(""));
+    }
+
     #[test]
     fn determine_string_quote_presence() {
         assert!(is_quoted("'some text'"));
@@ -11,6 +11,7 @@ mod error;
 mod filter;
 
 use std::{
+    io,
     path::Path,
     str::FromStr as _,
     env,
@@ -20,16 +21,39 @@ use anyhow::{anyhow, Context};
 
 use upim_core::paths::collection_path;
 
-use args::{Command, Options, substitute_alias};
+use args::{
+    Command,
+    Options,
+    OutputFormat,
+    resolve_alias,
+    substitute_alias,
+};
 use config::*;
-use contact::{read_contacts, print_contacts};
-use filter::Query;
+use contact::{
+    count_matching,
+    dedup_contacts,
+    group_contacts,
+    print_grouped,
+    read_contacts,
+    print_contacts,
+    skipped_summary,
+    sort_contacts,
+    write_new_contact,
+    write_ndjson,
+    write_output,
+};
+use filter::{Condition, Query};
 
 
 fn main() -> anyhow::Result<()> {
     use std::process::Command as Proc;
 
     let opts = Options::new(env::args())?;
+    let format = opts.format;
+
+    for w in upim_core::config::configuration_warnings() {
+        eprintln!("Warning: {}", w);
+    }
 
     let conf = read_config(opts.conf_path)
         .map_err(|errs| {
@@ -39,73 +63,65 @@ fn main() -> anyhow::Result<()> {
             return anyhow!("Failed to read configuration file.");
         })?;
 
+    let collection = select_collection(
+        opts.collection.as_deref(),
+        &conf,
+        atty::is(atty::Stream::Stdout),
+        io::stdin().lock(),
+        &mut io::stdout()
+    )?;
+
     let search = match opts.cmd_or_alias {
         Command::Search => opts.filter,
         Command::Alias(ref name) => {
-            match conf.get("Aliases", name) {
-                Some(alias) => {
-                    let alias = alias.strip_prefix("--filter")
-                        .unwrap_or(alias)
-                        .trim_start();
-
-                    let alias = match opts.alias_params {
-                        Some(ref p) => {
-                            let (len, a) = substitute_alias(p, alias)?;
-
-                            if len != p.len() {
-                                return Err(anyhow!(
-                                    "Expected {} parameters, but received {}: \
-                                    {:?}",
-                                    len,
-                                    p.len(),
-                                    p
-                                ));
-                            }
-
-                            a
-                        },
-                        None => {
-                            // Check for missing values for parameter
-                            // substitutions. We get back a cloned alias, which
-                            // we'd need to do anyway.
-                            let (_, alias) = substitute_alias(&[], alias)?;
-                            alias
-                        },
-                    };
-
-                    let alias = Query::from_str(&alias)?;
-
-                    opts.filter.or(Some(alias))
+            let alias = resolve_alias(name, |n| conf.get("Aliases", n))?;
+
+            let alias = match opts.alias_params {
+                Some(ref p) => {
+                    let (len, a) = substitute_alias(p, &alias)?;
+
+                    if len != p.len() {
+                        return Err(anyhow!(
+                            "Expected {} parameters, but received {}: {:?}",
+                            len,
+                            p.len(),
+                            p
+                        ));
+                    }
+
+                    a
+                },
+                None => {
+                    // Check for missing values for parameter substitutions. We
+                    // get back a cloned alias, which we'd need to do anyway.
+                    let (_, alias) = substitute_alias(&[], &alias)?;
+                    alias
                 },
-                None => return Err(anyhow!("Unknown alias: {}", name)),
-            }
-        },
-        Command::New(name) => {
-            let collection = if let Some(coll) = &opts.collection {
-                coll
-            } else {
-                &conf["default_collection"]
             };
 
-            let name = new_normalized_name(
-                &name,
-                &collection_path(&conf, &collection)?
-            ).context("Cannot create new file")?;
+            let alias = Query::from_str(&alias)?;
+
+            opts.filter.or(Some(alias))
+        },
+        Command::New(name) => {
+            let dir = collection_path(&conf, &collection)?;
+            let file = new_normalized_name(&name, &dir)
+                .context("Cannot create new file")?;
+
+            // Without fields to set, upim-edit creates the file from the
+            // collection's template.
+            if ! opts.fields.is_empty() {
+                write_new_contact(&dir.join(&file), &name, &opts.fields)?;
+            }
 
             Proc::new("upim-edit")
-                .args(&["-C", collection, &name])
+                .args(["-C", &collection, &file])
                 .spawn()?
                 .wait()?;
 
             None
         },
         Command::Edit(name) => {
-            let collection = if let Some(coll) = &opts.collection {
-                coll
-            } else {
-                &conf["default_collection"]
-            };
-
             if name.is_left() {
                 let name = name.left().unwrap();
                 let name = normalized_name(
@@ -114,13 +130,13 @@ fn main() -> anyhow::Result<()> {
                 )?;
 
                 Proc::new("upim-edit")
-                    .args(&["-C", collection, &name])
+                    .args(["-C", &collection, &name])
                     .spawn()?
                     .wait()?;
             } else {
                 let path = name.right().unwrap();
                 let path = if path.is_relative() {
-                    Path::new(collection).join(path)
+                    Path::new(&collection).join(path)
                 } else {
                     path.to_owned()
                 };
@@ -131,18 +147,85 @@ fn main() -> anyhow::Result<()> {
                     .wait()?;
             }
 
+            None
+        },
+        Command::Count => {
+            let path = collection_path(&conf, &collection)?;
+
+            let condition = opts.filter.as_ref()
+                .map(|f| f.condition.clone())
+                .unwrap_or_default();
+
+            let (count, skipped) = count_matching(&path, &condition)?;
+            println!("{}", count);
+
+            if let Some(summary) = skipped_summary(&skipped, opts.verbose) {
+                eprintln!("{}", summary);
+            }
+
             None
         },
     };
 
     if let Some(search) = search {
-        let collection = opts.collection
-            .unwrap_or_else(|| conf["default_collection"].to_owned());
         let path = collection_path(&conf, &collection)?;
-        let sep = &conf["field_separator"];
+        let sep = opts.separator.as_deref()
+            .unwrap_or(&conf["field_separator"]);
 
-        let contacts = read_contacts(&path, search.condition)?;
-        print_contacts(&contacts, &search.select, sep);
+        // REF fields may name contacts that don't match the query.
+        let references = if search.selects_references() {
+            read_contacts(&path, Condition::All)?.0
+        } else {
+            vec![]
+        };
+
+        let (contacts, skipped) =
+            read_contacts(&path, search.condition.clone())?;
+        let mut contacts = match opts.dedup {
+            Some(ref field) => dedup_contacts(contacts, field),
+            None => contacts,
+        };
+        sort_contacts(&mut contacts, &opts.sort);
+        let group_by = opts.group_by.as_deref();
+
+        write_output(opts.output.as_deref(), |mut out| {
+            match (format, group_by) {
+                (OutputFormat::Table, Some(field)) => print_grouped(
+                    &mut out,
+                    &group_contacts(contacts, field),
+                    field,
+                    &references,
+                    &search,
+                    sep
+                ),
+                (OutputFormat::Table, None) => print_contacts(
+                    &mut out,
+                    &contacts,
+                    &references,
+                    &search,
+                    sep
+                ),
+                // Headers would break the format; we only order the groups.
+                (OutputFormat::Ndjson, Some(field)) => write_ndjson(
+                    &mut out,
+                    &group_contacts(contacts, field).into_iter()
+                        .flat_map(|(_, contacts)| contacts)
+                        .collect::<Vec<_>>(),
+                    &references,
+                    &search
+                ),
+                (OutputFormat::Ndjson, None) => write_ndjson(
+                    &mut out,
+                    &contacts,
+                    &references,
+                    &search
+                ),
+            }
+        })?;
+
+        if let Some(summary) = skipped_summary(&skipped, opts.verbose) {
+            eprintln!("{}", summary);
+        }
     };
 
     Ok(())
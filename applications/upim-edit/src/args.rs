@@ -10,12 +10,23 @@ pub enum Action {
     Edit,
     AddTags(Vec<String>),
     AddAttribute(String, String),
+    /// Add a value to an attribute, keeping any existing values.
+    AppendAttribute(String, String),
     RemoveTags(Vec<String>),
     RemoveAttribute(String),
     PrintTags,
     PrintAttributes,
     PrintCollections,
     PrintContent,
+    PrintPath,
+    Migrate,
+    Validate,
+    /// Report style problems in the note without modifying it.
+    Lint,
+    /// Append the text read from the path (or stdin for "-") to the note.
+    AppendContent(PathBuf),
+    /// Write a starter configuration; see [InitOptions].
+    Init,
     PrintHelp,
 }
 
@@ -23,12 +34,62 @@ impl Default for Action {
     fn default() -> Action { Action::Edit }
 }
 
+impl Action {
+    /// Whether the action changes the note without opening an editor, and so
+    /// can print its changes instead with `--dry-run`.
+    pub fn supports_dry_run(&self) -> bool {
+        matches!(self,
+            Action::AddTags(_)
+            | Action::AddAttribute(_, _)
+            | Action::AppendAttribute(_, _)
+            | Action::RemoveTags(_)
+            | Action::RemoveAttribute(_)
+            | Action::Migrate
+            | Action::AppendContent(_)
+        )
+    }
+}
+
+/// The output format of the `--collections`, `--validate`, and `--lint`
+/// actions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// Plain text for people to read, one item per line.
+    Text,
+    /// A JSON array of objects.
+    Json,
+}
+
+impl Default for OutputFormat {
+    fn default() -> OutputFormat { OutputFormat::Text }
+}
+
+/// The settings written by the `--init` action.
+///
+/// Any value not given on the command line is prompted for.
+#[derive(Debug, Default)]
+pub struct InitOptions {
+    pub editor: Option<String>,
+    pub collection_base: Option<PathBuf>,
+    pub template_folder: Option<PathBuf>,
+    /// Write `collection_base` and `template_folder` to the global upim.conf
+    /// rather than upim-edit.conf.
+    pub global: bool,
+    /// Replace existing configuration files.
+    pub force: bool,
+}
+
 #[derive(Debug, Default)]
 pub struct Options {
     pub file: PathBuf,
     pub collection: Option<String>,
     pub conf_path: Option<PathBuf>,
     pub action: Action,
+    pub format: OutputFormat,
+    /// Print the changes a modifying action would make instead of saving
+    /// them.
+    pub dry_run: bool,
+    pub init: InitOptions,
 }
 
 impl Options {
@@ -69,6 +130,20 @@ impl Options {
                         ));
                     }
                 },
+                "--format" => {
+                    if args.len() < 2 {
+                        return Err(anyhow!("Missing output format"));
+                    }
+
+                    opts.format = match args[1].as_str() {
+                        "text" => OutputFormat::Text,
+                        "json" => OutputFormat::Json,
+                        f => return Err(
+                            anyhow!("Unknown output format: {}", f)
+                        ),
+                    };
+                    args = &mut args[2..];
+                },
                 "--tags" => {
                     opts.action = Action::PrintTags;
                     args = &mut args[1..];
@@ -85,6 +160,59 @@ impl Options {
                     opts.action = Action::PrintContent;
                     args = &mut args[1..];
                 },
+                "--print-path" => {
+                    opts.action = Action::PrintPath;
+                    args = &mut args[1..];
+                },
+                "--migrate" => {
+                    opts.action = Action::Migrate;
+                    args = &mut args[1..];
+                },
+                "--validate" => {
+                    opts.action = Action::Validate;
+                    args = &mut args[1..];
+                },
+                "--lint" => {
+                    opts.action = Action::Lint;
+                    args = &mut args[1..];
+                },
+                "--init" => {
+                    opts.action = Action::Init;
+                    args = &mut args[1..];
+                },
+                "--editor" => {
+                    if args.len() < 2 {
+                        return Err(anyhow!("Missing editor command"));
+                    }
+                    opts.init.editor = Some(args[1].clone());
+                    args = &mut args[2..];
+                },
+                "--collection-base" => {
+                    if args.len() < 2 {
+                        return Err(anyhow!("Missing collection directory"));
+                    }
+                    opts.init.collection_base = Some(PathBuf::from(&args[1]));
+                    args = &mut args[2..];
+                },
+                "--template-folder" => {
+                    if args.len() < 2 {
+                        return Err(anyhow!("Missing template directory"));
+                    }
+                    opts.init.template_folder = Some(PathBuf::from(&args[1]));
+                    args = &mut args[2..];
+                },
+                "--global" => {
+                    opts.init.global = true;
+                    args = &mut args[1..];
+                },
+                "--force" => {
+                    opts.init.force = true;
+                    args = &mut args[1..];
+                },
+                "--dry-run" => {
+                    opts.dry_run = true;
+                    args = &mut args[1..];
+                },
                 "--add-tags" => {
                     let tags = read_tags(&args)?;
                     assert!(tags.len() < args.len());
@@ -103,6 +231,17 @@ impl Options {
                     );
                     args = &mut args[3..];
                 },
+                "--append-attr" => {
+                    if args.len() < 3 {
+                        return Err(anyhow!("Missing attribute data"));
+                    }
+
+                    opts.action = Action::AppendAttribute(
+                        args[1].clone(),
+                        args[2].clone(),
+                    );
+                    args = &mut args[3..];
+                },
                 "--remove-tags" => {
                     let tags = read_tags(&args)?;
                     assert!(tags.len() < args.len());
@@ -110,6 +249,15 @@ impl Options {
                     args = &mut args[tags.len()+1..];
                     opts.action = Action::RemoveTags(tags);
                 },
+                "--append-content" => {
+                    if args.len() < 2 {
+                        return Err(anyhow!("Missing content source"));
+                    }
+
+                    opts.action =
+                        Action::AppendContent(PathBuf::from(&args[1]));
+                    args = &mut args[2..];
+                },
                 "--remove-attr" => {
                     if args.len() < 2 {
                         return Err(anyhow!("Missing attribute name"));
@@ -135,6 +283,10 @@ impl Options {
             }
         }
 
+        if opts.dry_run && ! opts.action.supports_dry_run() {
+            return Err(anyhow!("--dry-run is not supported by this action"));
+        }
+
         if opts.is_valid() {
             Ok(opts)
         } else {
@@ -145,7 +297,8 @@ impl Options {
 
     pub fn is_valid(&self) -> bool {
         self.action == Action::PrintCollections
-        || self.action == Action::PrintHelp || self.file != PathBuf::default()
+        || self.action == Action::PrintHelp || self.action == Action::Init
+        || self.file != PathBuf::default()
         && if self.collection.is_some() {
             ! self.file.is_absolute()
         } else {
@@ -154,30 +307,148 @@ impl Options {
     }
 }
 
+/// Read the list of tags following a `--add-tags` or `--remove-tags` option.
+///
+/// `args[0]` is the option itself. The tags are the consecutive arguments
+/// beginning with '@'; since the path to the note must be the final argument,
+/// it is never read as a tag, even if it begins with '@'.
 fn read_tags(args: &[String]) -> anyhow::Result<Vec<String>> {
-    let mut tags = vec![];
-    let mut i = 1;
-
-    while args[i].starts_with('@') {
-        tags.push(args[i].to_string());
-        i += 1;
-
-        if i == args.len() {
-            return Err(anyhow!("Missing file name"));
-        }
+    match args.len() {
+        0 | 1 => return Err(anyhow!("No tags provided")),
+        2 if args[1].starts_with('@') =>
+            return Err(anyhow!("Missing file name")),
+        _ => {},
     }
 
+    let tags = args[1..args.len()-1].iter()
+        .take_while(|a| a.starts_with('@'))
+        .cloned()
+        .collect::<Vec<String>>();
+
     if tags.is_empty() {
-        return Err(anyhow!("No tags provided"));
+        Err(anyhow!("No tags provided"))
+    } else {
+        Ok(tags)
     }
-
-    Ok(tags)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn args_print_path() {
+        let args = ["upim-edit", "-C", "work", "--print-path", "foo.txt"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(opts.file.to_str().unwrap(), "foo.txt");
+        assert_eq!(opts.collection.unwrap(), "work");
+        assert_eq!(opts.action, Action::PrintPath);
+    }
+
+    #[test]
+    fn args_migrate() {
+        let args = ["upim-edit", "--migrate", "some-file.txt"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(opts.file.to_str().unwrap(), "some-file.txt");
+        assert_eq!(opts.action, Action::Migrate);
+    }
+
+    #[test]
+    fn args_append_content() {
+        let args = ["upim-edit", "--append-content", "-", "some-file.txt"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(opts.file.to_str().unwrap(), "some-file.txt");
+        assert_eq!(opts.action, Action::AppendContent(PathBuf::from("-")));
+
+        let args = ["upim-edit", "--append-content", "some-file.txt"];
+        assert!(Options::new(args.iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn args_dry_run() {
+        let args = ["upim-edit", "--dry-run", "--add-tags", "@x", "a.txt"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert!(opts.dry_run);
+        assert_eq!(opts.action, Action::AddTags(vec!["@x".into()]));
+        assert_eq!(opts.file.to_str().unwrap(), "a.txt");
+
+        for action in ["--validate", "--tags", "--migrate"] {
+            let args = ["upim-edit", "--dry-run", action, "a.txt"];
+            let args = args.iter().map(|s| s.to_string());
+
+            assert_eq!(Options::new(args).is_ok(), action == "--migrate");
+        }
+
+        let args = ["upim-edit", "--dry-run", "a.txt"];
+        assert!(Options::new(args.iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn args_validate() {
+        let args = ["upim-edit", "--validate", "--format", "json", "a.txt"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(opts.file.to_str().unwrap(), "a.txt");
+        assert_eq!(opts.action, Action::Validate);
+        assert_eq!(opts.format, OutputFormat::Json);
+    }
+
+    #[test]
+    fn args_lint() {
+        let args = ["upim-edit", "--lint", "a.txt"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(opts.file.to_str().unwrap(), "a.txt");
+        assert_eq!(opts.action, Action::Lint);
+        assert_eq!(opts.format, OutputFormat::Text);
+    }
+
+    #[test]
+    fn args_init() {
+        let args = [
+            "upim-edit", "--init", "--editor", "nano", "--collection-base",
+            "/tmp/notes", "--global", "--force",
+        ];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(opts.action, Action::Init);
+        assert_eq!(opts.init.editor.as_deref(), Some("nano"));
+        assert_eq!(
+            opts.init.collection_base.unwrap(),
+            PathBuf::from("/tmp/notes")
+        );
+        assert_eq!(opts.init.template_folder, None);
+        assert!(opts.init.global);
+        assert!(opts.init.force);
+
+        let args = ["upim-edit", "--init", "--editor"];
+        assert!(Options::new(args.iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn args_collections_format() {
+        let args = ["upim-edit", "--collections", "--format", "json"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(opts.action, Action::PrintCollections);
+        assert_eq!(opts.format, OutputFormat::Json);
+
+        let args = ["upim-edit", "--format", "xml", "--collections"];
+        assert!(Options::new(args.iter().map(|s| s.to_string())).is_err());
+    }
+
     #[test]
     fn args_path() {
         let args = vec!["upim-edit", "some-file.txt"];
@@ -291,6 +562,55 @@ mod tests {
         assert_eq!(opts.action, Action::AddTags(tags));
     }
 
+    #[test]
+    fn args_add_tags_with_file_name() {
+        let args = ["upim-edit", "--add-tags", "@a", "@b", "file"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let tags = vec!["@a".into(), "@b".into()];
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(opts.action, Action::AddTags(tags));
+        assert_eq!(opts.file.to_str().unwrap(), "file");
+    }
+
+    #[test]
+    fn args_add_tags_missing_file() {
+        let args = ["upim-edit", "--add-tags", "@a"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let err = Options::new(args).unwrap_err();
+        assert_eq!(err.to_string(), "Missing file name");
+
+        let args = ["upim-edit", "--add-tags"];
+        let args = args.iter().map(|s| s.to_string());
+
+        assert!(Options::new(args).is_err());
+    }
+
+    #[test]
+    fn args_add_tags_file_name_begins_with_symbol() {
+        let args = ["upim-edit", "--add-tags", "@a", "@b", "@file.txt"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let tags = vec!["@a".into(), "@b".into()];
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(opts.action, Action::AddTags(tags));
+        assert_eq!(opts.file.to_str().unwrap(), "@file.txt");
+    }
+
+    #[test]
+    fn args_add_tags_followed_by_option() {
+        let args = ["upim-edit", "--add-tags", "@a", "-C", "coll", "file"];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(opts.action, Action::AddTags(vec!["@a".into()]));
+        assert_eq!(opts.collection.unwrap(), "coll");
+        assert_eq!(opts.file.to_str().unwrap(), "file");
+    }
+
     #[test]
     fn args_add_tags_missing_tags() {
         let args = vec!["upim-edit", "--add-tags", "/tmp/some-file.txt"];
@@ -321,6 +641,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn args_append_attribute() {
+        let args = [
+            "upim-edit", "--append-attr", "Phone", "555-0100", "some-file.txt"
+        ];
+        let args = args.iter().map(|s| s.to_string());
+
+        let opts = Options::new(args).unwrap();
+        assert_eq!(
+            opts.action,
+            Action::AppendAttribute("Phone".into(), "555-0100".into())
+        );
+    }
+
     #[test]
     fn args_add_attribute_missing_one_kv() {
         let args = vec!["upim-edit", "--add-attr", "key", "/tmp/some-file.txt"];
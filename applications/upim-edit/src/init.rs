@@ -0,0 +1,214 @@
+//! Creation of a starter configuration for new users
+
+use std::{
+    fs,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context as _};
+
+use upim_core::{config::Config, paths::home_dir};
+
+use crate::args::InitOptions;
+
+
+/// Write a starter configuration to the directory `dir`, and create the
+/// directories it names.
+///
+/// upim-edit.conf always receives the `editor`. The `collection_base` and
+/// `template_folder` are written to it as well, or with `global` set, to
+/// upim.conf instead. Settings not given in `opts` are prompted for on
+/// `output`, and read from `input`; an empty response (or the end of the
+/// input) accepts the default shown.
+///
+/// No file is written if any would replace an existing file, unless `force`
+/// is set.
+pub fn init<R: BufRead, W: Write>(
+    opts: &InitOptions,
+    dir: &Path,
+    input: R,
+    output: &mut W
+) -> anyhow::Result<()> {
+    let app_path = dir.join("upim-edit.conf");
+    let global_path = dir.join("upim.conf");
+
+    let targets = if opts.global {
+        vec![&app_path, &global_path]
+    } else {
+        vec![&app_path]
+    };
+
+    if ! opts.force {
+        if let Some(path) = targets.iter().find(|p| p.exists()) {
+            return Err(anyhow!(
+                "{} already exists; use --force to replace it",
+                path.display()
+            ));
+        }
+    }
+
+    let mut input = input;
+
+    let editor = match opts.editor {
+        Some(ref e) => e.clone(),
+        None => {
+            let default = std::env::var("EDITOR")
+                .unwrap_or_else(|_| "vi".into());
+            prompt(&mut input, output, "Text editor", &default)?
+        },
+    };
+
+    let collection_base = match opts.collection_base {
+        Some(ref p) => p.clone(),
+        None => {
+            let default = home_dir()
+                .ok_or_else(|| anyhow!("Cannot find the home directory"))?
+                .join("upim");
+            prompt_path(&mut input, output, "Collection folder", &default)?
+        },
+    };
+
+    let template_folder = match opts.template_folder {
+        Some(ref p) => p.clone(),
+        None => {
+            let default = dir.join("templates");
+            prompt_path(&mut input, output, "Template folder", &default)?
+        },
+    };
+
+    for folder in [dir, &collection_base, &template_folder].iter() {
+        fs::create_dir_all(folder)
+            .with_context(|| format!("Cannot create {}", folder.display()))?;
+    }
+
+    let folders = Config::default()
+        .set_default("collection_base", &collection_base.to_string_lossy())
+        .set_default("template_folder", &template_folder.to_string_lossy());
+
+    let app_conf = Config::default().set_default("editor", &editor);
+
+    if opts.global {
+        write_config(&app_conf, &app_path, output)?;
+        write_config(&folders, &global_path, output)?;
+    } else {
+        write_config(&app_conf.merge_with(folders), &app_path, output)?;
+    }
+
+    Ok(())
+}
+
+fn write_config<W: Write>(conf: &Config, path: &Path, output: &mut W)
+-> anyhow::Result<()> {
+    conf.write_to_file(path)
+        .with_context(|| format!("Cannot write {}", path.display()))?;
+    writeln!(output, "Wrote {}", path.display())?;
+    Ok(())
+}
+
+/// Ask for a value, returning `default` if none is given.
+fn prompt<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    name: &str,
+    default: &str
+) -> anyhow::Result<String> {
+    write!(output, "{} [{}]: ", name, default)?;
+    output.flush()?;
+
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+
+    match line.trim() {
+        "" => Ok(default.into()),
+        value => Ok(value.into()),
+    }
+}
+
+fn prompt_path<R: BufRead, W: Write>(
+    input: &mut R,
+    output: &mut W,
+    name: &str,
+    default: &Path
+) -> anyhow::Result<PathBuf> {
+    prompt(input, output, name, &default.to_string_lossy())
+        .map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn init_prompts_for_missing_values() {
+        let dir = env::temp_dir().join("upim_edit_init_prompt");
+        let _ = fs::remove_dir_all(&dir);
+
+        let opts = InitOptions {
+            editor: Some("nano".into()),
+            ..InitOptions::default()
+        };
+        let notes = dir.join("notes");
+        let input = format!("{}\n\n", notes.display());
+        let mut output = vec![];
+
+        let res = init(&opts, &dir, input.as_bytes(), &mut output);
+        let conf = Config::read_from_file(&dir.join("upim-edit.conf"));
+        let templates = dir.join("templates");
+        let created = notes.is_dir() && templates.is_dir();
+        let _ = fs::remove_dir_all(&dir);
+
+        res.unwrap();
+        let conf = conf.unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(created);
+        assert_eq!(conf["editor"], "nano");
+        assert_eq!(conf["collection_base"], notes.to_string_lossy());
+        assert_eq!(conf["template_folder"], templates.to_string_lossy());
+        assert!(! output.contains("Text editor"));
+        assert!(output.contains("Collection folder ["));
+        assert!(output.contains("Template folder ["));
+    }
+
+    #[test]
+    fn init_global_refuses_to_replace() {
+        let dir = env::temp_dir().join("upim_edit_init_global");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("upim.conf"), "kept = true\n").unwrap();
+
+        let mut opts = InitOptions {
+            editor: Some("nano".into()),
+            collection_base: Some(dir.join("notes")),
+            template_folder: Some(dir.join("templates")),
+            global: true,
+            force: false,
+        };
+
+        let refused = init(&opts, &dir, &b""[..], &mut vec![]);
+        let app_written = dir.join("upim-edit.conf").exists();
+        let kept = fs::read_to_string(dir.join("upim.conf"));
+
+        opts.force = true;
+        let forced = init(&opts, &dir, &b""[..], &mut vec![]);
+        let app = Config::read_from_file(&dir.join("upim-edit.conf"));
+        let global = Config::read_from_file(&dir.join("upim.conf"));
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(refused.unwrap_err().to_string().contains("--force"));
+        assert!(! app_written);
+        assert_eq!(kept.unwrap(), "kept = true\n");
+
+        forced.unwrap();
+        let (app, global) = (app.unwrap(), global.unwrap());
+        assert_eq!(app["editor"], "nano");
+        assert!(app.get_default("collection_base").is_none());
+        assert!(global.get_default("kept").is_none());
+        assert_eq!(
+            global["template_folder"],
+            dir.join("templates").to_string_lossy()
+        );
+    }
+}
@@ -10,6 +10,7 @@
 #![feature(drain_filter)]
 
 mod args;
+mod init;
 
 use std::{
     path::{Path, PathBuf},
@@ -23,20 +24,33 @@ use anyhow::{anyhow, Context as _};
 use upim_core::{
     config::*,
     error::FileError,
-    paths::expand_tilde,
+    paths::{collection_path, expand_tilde},
 };
-use upim_note::Note;
+use upim_note::{LintKind, LintWarning, Note};
 
 use crate::args::*;
 
 
-fn main() -> anyhow::Result<()> {
-    let options = Options::new(env::args());
-    let options = if let Ok(opt) = options {
-        opt
-    } else {
-        print_usage();
-        return Ok(());
+/// The version of the note format written by upim-edit.
+const FORMAT_VERSION: u32 = 1;
+
+/// The attribute that records the format version of a note.
+const VERSION_ATTRIBUTE: &str = "uPIM-Version";
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(Failure::of(&e).map_or(1, Failure::exit_code));
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let options = match Options::new(env::args()) {
+        Ok(opt) => opt,
+        Err(e) => {
+            print_usage();
+            return Err(e.context(Failure::Usage));
+        },
     };
 
     if options.action == Action::PrintHelp {
@@ -44,10 +58,29 @@ fn main() -> anyhow::Result<()> {
         return Ok(());
     }
 
+    if options.action == Action::Init {
+        use std::io;
+
+        let dir = user_configuration_dir().ok_or_else(|| anyhow!(
+            "Cannot determine the configuration directory"
+        )).context(Failure::Config)?;
+
+        return init::init(
+            &options.init,
+            &dir,
+            io::stdin().lock(),
+            &mut io::stdout()
+        );
+    }
+
     let conf = {
         let path = options.conf_path.clone() // Clone to avoid partial move.
             .or_else(find_default_configuration);
 
+        for w in configuration_warnings() {
+            eprintln!("Warning: {}", w);
+        }
+
         if let Some(path) = path {
             match read_config(&path) {
                 Ok(conf) => conf,
@@ -55,7 +88,8 @@ fn main() -> anyhow::Result<()> {
                     for e in errors.iter() {
                         eprintln!("Error: {}", e);
                     }
-                    return Err(anyhow!("Failed to read configuration file."));
+                    return Err(anyhow!("Failed to read configuration file."))
+                        .context(Failure::Config);
                 },
             }
         } else {
@@ -68,67 +102,181 @@ fn main() -> anyhow::Result<()> {
     match options.action {
         Action::Edit => {
             let editor = conf.get_default("editor")
-                .ok_or_else(|| anyhow!("No text editor configured"))?;
+                .ok_or_else(|| anyhow!("No text editor configured"))
+                .context(Failure::Config)?;
             let editor_arg = conf.get_default("editor_arg").map(|v| v.as_str());
+            let retries = match conf.get_default("validate_retries") {
+                Some(n) => Some(n.parse::<u32>().map_err(|_| anyhow!(
+                    "Invalid validate_retries value: {}", n
+                )).context(Failure::Config)?),
+                None => None,
+            };
 
             let (path, templ) = determine_file_path(&options, &conf)?;
-
-            if let Some(ref templ) = templ {
-                fs::copy(templ, &path)
-                    .context("While copying template")?;
+            let _lock = EditLock::acquire(&path)?;
+
+            // If the note appeared since we determined its path, we edit it
+            // as-is; launch_editor must not treat it as a fresh template
+            // instance, or it could delete the note.
+            let templ = match templ {
+                Some(templ) if ! path.exists() => {
+                    instantiate_template(&templ, &path)
+                        .context("While copying template")?;
+                    Some(templ)
+                },
+                _ => None,
             };
 
-            launch_editor(editor, editor_arg, &path, templ.as_deref())?;
+            launch_editor(
+                editor,
+                editor_arg,
+                &path,
+                templ.as_deref(),
+                retries
+            )?;
         },
-        Action::AddTags(tags) => {
-            let mut note = Note::read_from_file(&options.file)?;
+        Action::AddTags(ref tags) => {
+            let mut note = read_note(&options.file, false)?;
 
-            for tag in &tags { note.insert_tag(tag); }
-            note.write_to_file(&options.file)?;
+            for tag in tags { note.insert_tag(tag); }
+            save_note(&note, &options)?;
         },
         Action::AddAttribute(ref k, ref v) => {
-            let mut note = Note::read_from_file(&options.file)?;
+            validate_attribute(k, v).context(Failure::Validation)?;
+            let mut note = read_note(&options.file, false)?;
 
             note.set_attribute(k, v);
-            note.write_to_file(&options.file)?;
+            save_note(&note, &options)?;
         },
-        Action::RemoveTags(tags) => {
-            let mut note = Note::read_from_file(&options.file)?;
+        Action::AppendAttribute(ref k, ref v) => {
+            validate_attribute(k, v).context(Failure::Validation)?;
+            let mut note = read_note(&options.file, false)?;
+
+            note.add_attribute(k, v);
+            save_note(&note, &options)?;
+        },
+        Action::RemoveTags(ref tags) => {
+            let mut note = read_note(&options.file, false)?;
 
-            for tag in &tags { note.remove_tag(tag); }
-            note.write_to_file(&options.file)?;
+            for tag in tags { note.remove_tag(tag); }
+            save_note(&note, &options)?;
         },
         Action::RemoveAttribute(ref k) => {
-            let mut note = Note::read_from_file(&options.file)?;
+            let mut note = read_note(&options.file, false)?;
 
             note.remove_attribute(k);
-            note.write_to_file(&options.file)?;
+            save_note(&note, &options)?;
         },
         Action::PrintTags => {
-            let note = Note::read_header(&options.file)?;
+            let note = read_note(&options.file, true)?;
 
             for tag in note.tags().iter() {
                 println!("{}", tag);
             }
         },
         Action::PrintAttributes => {
-            let note = Note::read_header(&options.file)?;
+            let note = read_note(&options.file, true)?;
 
-            for (k, v) in note.attributes() {
+            for (k, v) in note.attributes_sorted() {
                 println!("{}:{}", k, v);
             }
         },
-        Action::PrintCollections => {
-            for coll in conf.variables_in_group("Collections") {
-                println!("{}", coll);
-            }
+        Action::PrintCollections => match options.format {
+            OutputFormat::Text => {
+                for coll in conf.variables_in_group("Collections") {
+                    println!("{}", coll);
+                }
+            },
+            OutputFormat::Json => println!("{}", collections_json(&conf)),
         },
         Action::PrintContent => {
-            let note = Note::read_from_file(&options.file)?;
+            let note = read_note(&options.file, false)?;
             println!("{}", note.content());
         },
-        Action::PrintHelp => {
-            // we printed above, prior to reading the configuration file.
+        Action::PrintPath => {
+            let (path, templ) = determine_file_path(&options, &conf)?;
+            let path = if path.is_relative() {
+                env::current_dir()?.join(path)
+            } else {
+                path
+            };
+
+            println!("{}", path.display());
+            if let Some(templ) = templ {
+                println!("Template: {}", templ.display());
+            }
+        },
+        Action::Migrate => {
+            let mut note = Note::read_from_file(&options.file)?;
+
+            migrate(&mut note).with_context(|| format!(
+                "Cannot migrate {}", options.file.display()
+            ))?;
+            save_note(&note, &options)?;
+        },
+        Action::Validate => {
+            let errors = Note::header_errors(&options.file, false)?;
+
+            match options.format {
+                OutputFormat::Text => {
+                    for e in errors.iter() {
+                        eprintln!("Error: {}", e);
+                    }
+                },
+                OutputFormat::Json => {
+                    println!("{}", diagnostics_json(&options.file, &errors));
+                },
+            }
+
+            if ! errors.is_empty() {
+                return Err(anyhow!(
+                    "{} is not a valid note", options.file.display()
+                )).context(Failure::Validation);
+            }
+        },
+        Action::Lint => {
+            let text = fs::read_to_string(&options.file)?;
+            let warnings = Note::lint(&text);
+
+            match options.format {
+                OutputFormat::Text => {
+                    for w in warnings.iter() {
+                        eprintln!("Warning: {}", w);
+                    }
+                },
+                OutputFormat::Json => println!("{}", lint_json(&warnings)),
+            }
+
+            if ! warnings.is_empty() {
+                return Err(anyhow!(
+                    "{} has {} style warning(s)",
+                    options.file.display(),
+                    warnings.len()
+                )).context(Failure::Validation);
+            }
+        },
+        Action::AppendContent(ref src) => {
+            let text = read_input(src)?;
+            let mut note = read_note(&options.file, false)?;
+
+            note.append_content(&text);
+
+            if options.dry_run {
+                print_changes(&note, &options.file)?;
+            } else {
+                use std::str::FromStr;
+
+                // Ensure the new note reads back before replacing the old
+                // one; writing the file keeps its permissions.
+                Note::from_str(&note.to_string())
+                    .map_err(|e| anyhow!("The new note is invalid: {}", e))
+                    .context(Failure::Validation)?;
+
+                note.write_to_file(&options.file)?;
+            }
+        },
+        Action::PrintHelp | Action::Init => {
+            // We handled these above, prior to reading the configuration file.
             panic!();
         },
     }
@@ -147,11 +295,30 @@ fn print_usage() {
         "\t--tags                    - Print the note's tags then exit\n",
         "\t--attributes              - Print the note's attributes then exit\n",
         "\t--collections             - Print the collections then exit\n",
+        "\t--format <text|json>      - The output format of --collections, ",
+        "--validate,\n\t                            and --lint\n",
         "\t--content                 - Print the note's content then exit\n",
+        "\t--print-path              - Print the note's path then exit\n",
         "\t--add-tags <tag>...       - Add one or more tags to the note\n",
         "\t--add-attr <name> <value> - Add or edit an attribute\n",
+        "\t--append-attr <name> <value>\n",
+        "\t                          - Add another value to an attribute\n",
         "\t--remove-tags <tag>...    - Remove one or more tags from the note\n",
         "\t--remove-attr <name>      - Remove an attribute from the note\n",
+        "\t--append-content <path>   - Append the file's text ('-' for stdin) ",
+        "to the\n\t                            note's content\n",
+        "\t--migrate                 - Upgrade the note to the latest format\n",
+        "\t--validate                - Check the note's header for errors\n",
+        "\t--lint                    - Check the note for style problems\n",
+        "\t--dry-run                 - Print the changes to the note instead ",
+        "of saving them\n",
+        "\t--init                    - Write a starter configuration then ",
+        "exit\n",
+        "\t  --editor <command>      - The editor to configure\n",
+        "\t  --collection-base <dir> - The base folder of collections\n",
+        "\t  --template-folder <dir> - The folder of note templates\n",
+        "\t  --global                - Write the folders to upim.conf\n",
+        "\t  --force                 - Replace existing configuration files\n",
         "\t--help                    - Print this help message\n",
 
         "\nWith the -C flag, <file> must be a path relative to the collection ",
@@ -163,10 +330,285 @@ fn print_usage() {
 
         "Attributes are key-value pairs of text. Spaces are allowed in both ",
         "parts.\n`--add-attr` for an attribute that already exists will ",
-        "replace its value with\nthe new value.\n",
+        "replace its value with\nthe new value; `--append-attr` keeps the ",
+        "existing values and adds the new one.\n",
     ));
 }
 
+/// Write the modified note to the file being edited, or with `--dry-run`,
+/// print the changes instead.
+fn save_note(note: &Note, options: &Options) -> anyhow::Result<()> {
+    if options.dry_run {
+        print_changes(note, &options.file)
+    } else {
+        note.write_to_file(&options.file).map_err(anyhow::Error::from)
+    }
+}
+
+/// Print the differences between the note at `path` and `note`.
+fn print_changes(note: &Note, path: &Path) -> anyhow::Result<()> {
+    let original = Note::read_from_file(path)?;
+
+    for change in original.diff(note) {
+        println!("{}", change);
+    }
+    Ok(())
+}
+
+/// Read the note at the given path, warning if it was written in a newer
+/// format than we support.
+///
+/// If `header_only` is true, the note's content is not read.
+fn read_note(path: &Path, header_only: bool) -> anyhow::Result<Note> {
+    let note = if header_only {
+        Note::read_header(path)?
+    } else {
+        Note::read_from_file(path)?
+    };
+
+    if let Err(e) = check_format_version(&note) {
+        eprintln!("Warning: {}: {}", path.display(), e);
+    }
+
+    Ok(note)
+}
+
+/// Ensure we understand the format version of the note.
+///
+/// A note without a version is treated as the oldest format.
+fn check_format_version(note: &Note) -> anyhow::Result<()> {
+    let version = match note.get_str(VERSION_ATTRIBUTE) {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    match version.parse::<u32>() {
+        Ok(v) if v <= FORMAT_VERSION => Ok(()),
+        Ok(v) => Err(anyhow!(
+            "Note format version {} is newer than the supported version {}",
+            v,
+            FORMAT_VERSION
+        )),
+        Err(_) => Err(anyhow!("Invalid note format version: {}", version)),
+    }
+}
+
+/// Ensure the attribute can be written to a note and read back unchanged.
+///
+/// A key cannot contain ':', '[', or ']', and a value cannot contain '[', ']',
+/// or a newline.
+fn validate_attribute(key: &str, value: &str) -> anyhow::Result<()> {
+    if let Some(c) = key.chars().find(|c| [':', '[', ']'].contains(c)) {
+        Err(anyhow!("An attribute key cannot contain '{}': {}", c, key))
+    } else if let Some(c) = value.chars().find(|c| ['[', ']'].contains(c)) {
+        Err(anyhow!("An attribute value cannot contain '{}': {}", c, value))
+    } else if value.contains(&['\n', '\r'][..]) {
+        Err(anyhow!("An attribute value cannot contain a newline"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Upgrade the note to the current format version.
+///
+/// Writing the note normalizes any older formatting, so we only need to stamp
+/// the version. Notes from a newer version are left untouched.
+fn migrate(note: &mut Note) -> anyhow::Result<()> {
+    check_format_version(note)?;
+    note.set_attribute(VERSION_ATTRIBUTE, &FORMAT_VERSION.to_string());
+    Ok(())
+}
+
+/// List the configured collections as a JSON array of objects with the
+/// collection's `name` and `path`.
+///
+/// If a collection's path cannot be resolved, its `path` is null and `error`
+/// describes the problem.
+fn collections_json(conf: &Config) -> serde_json::Value {
+    use serde_json::{json, Value};
+
+    let collections = conf.variables_in_group("Collections")
+        .map(|name| match collection_path(conf, name) {
+            Ok(path) => json!({
+                "name": name,
+                "path": path.to_string_lossy(),
+            }),
+            Err(e) => json!({
+                "name": name,
+                "path": Value::Null,
+                "error": e.to_string(),
+            }),
+        })
+        .collect();
+
+    Value::Array(collections)
+}
+
+/// Convert the note's header errors into a JSON array of diagnostics with
+/// the `line`, `column`, and `message` of each error.
+///
+/// Lines and columns begin at 1. The column is that of the text the error
+/// refers to, or 1 if it cannot be found on the line.
+fn diagnostics_json(path: &Path, errors: &[FileError]) -> serde_json::Value {
+    use serde_json::{json, Value};
+
+    let text = fs::read_to_string(path).unwrap_or_default();
+    let lines: Vec<&str> = text.lines().collect();
+
+    let diagnostics = errors.iter()
+        .map(|e| match e {
+            FileError::Parse { msg, data, line, .. } => {
+                let column = lines.get((*line as usize).saturating_sub(1))
+                    .and_then(|l| find_column(l, data))
+                    .unwrap_or(1);
+
+                json!({ "line": line, "column": column, "message": msg })
+            },
+            FileError::IO(_) => {
+                json!({ "line": 0, "column": 0, "message": e.to_string() })
+            },
+        })
+        .collect();
+
+    Value::Array(diagnostics)
+}
+
+/// Convert the note's lint warnings into a JSON array with the `line`,
+/// `kind`, and `message` of each warning.
+fn lint_json(warnings: &[LintWarning]) -> serde_json::Value {
+    use serde_json::{json, Value};
+
+    let warnings = warnings.iter()
+        .map(|w| json!({
+            "line": w.line,
+            "kind": match w.kind {
+                LintKind::UngroupedTags => "ungrouped-tags",
+                LintKind::UnsortedAttributes => "unsorted-attributes",
+                LintKind::TrailingWhitespace => "trailing-whitespace",
+                LintKind::ExtraBlankLines => "extra-blank-lines",
+            },
+            "message": w.message,
+        }))
+        .collect();
+
+    Value::Array(warnings)
+}
+
+/// Find the column of `data` within `line`, preferring an occurrence that
+/// stands alone as a whitespace-separated word.
+fn find_column(line: &str, data: &str) -> Option<usize> {
+    if data.is_empty() { return None; }
+
+    let is_word = |i: usize| {
+        let end = i + data.len();
+        ! matches!(line[..i].chars().last(), Some(c) if ! c.is_whitespace())
+            && ! matches!(line[end..].chars().next(),
+                Some(c) if ! c.is_whitespace())
+    };
+
+    let mut indices = line.match_indices(data).map(|(i, _)| i);
+    let first = indices.clone().next()?;
+    let i = indices.find(|i| is_word(*i)).unwrap_or(first);
+
+    Some(line[..i].chars().count() + 1)
+}
+
+/// Read all text from the file at `path`, or from stdin if `path` is "-".
+fn read_input(path: &Path) -> anyhow::Result<String> {
+    use std::io::{self, Read as _};
+
+    if path == Path::new("-") {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        Ok(text)
+    } else {
+        fs::read_to_string(path)
+            .with_context(|| format!("Cannot read {}", path.display()))
+    }
+}
+
+/// An advisory lock preventing other upim-edit processes from editing a note.
+///
+/// The lock is a `.<name>.lock` file beside the note, and is removed when the
+/// `EditLock` is dropped.
+struct EditLock {
+    path: PathBuf,
+}
+
+impl EditLock {
+    /// Lock the note at `note`, failing if another process holds the lock.
+    fn acquire(note: &Path) -> anyhow::Result<Self> {
+        use std::io::{ErrorKind, Write as _};
+
+        let path = lock_path(note);
+
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(mut f) => {
+                // The PID is informational only, to help find a stale lock.
+                let _ = writeln!(f, "{}", std::process::id());
+                Ok(Self { path })
+            },
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(anyhow!(
+                "{} is being edited by another process. If it is not, remove \
+                {} and try again",
+                note.display(),
+                path.display()
+            )),
+            Err(e) => Err(anyhow::Error::new(e).context(format!(
+                "Cannot create lock file {}", path.display()
+            ))),
+        }
+    }
+}
+
+impl Drop for EditLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Get the path of the lock file for the note at `note`.
+fn lock_path(note: &Path) -> PathBuf {
+    let name = note.file_name().unwrap_or_default().to_string_lossy();
+
+    match note.parent() {
+        Some(dir) if ! dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }.join(format!(".{}.lock", name))
+}
+
+/// Create a new note at `path` from the given template.
+///
+/// The note is opened with `create_new`, so if something else created the file
+/// after we determined its path we refuse to continue rather than overwrite it.
+fn instantiate_template(templ: &Path, path: &Path) -> anyhow::Result<()> {
+    use std::io::{self, ErrorKind};
+
+    let mut src = fs::File::open(templ)?;
+
+    let mut dest = match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+    {
+        Ok(f) => f,
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            return Err(anyhow!(
+                "Refusing to overwrite existing file: {}",
+                path.display()
+            ));
+        },
+        Err(e) => return Err(e.into()),
+    };
+
+    io::copy(&mut src, &mut dest)?;
+    Ok(())
+}
+
 /// Launch the editor and wait for it to exit.
 ///
 /// # Arguments
@@ -176,11 +618,16 @@ fn print_usage() {
 ///            detach from the shell that starts it.
 /// * path   - the path to a file to create or edit.
 /// * templ  - the path to the newly-created template file if applicable
+/// * retries - the number of times to re-open an invalid note without asking.
+///
+/// Once the retries are exhausted, the validation error is returned. If
+/// `retries` is `None`, the user is asked whether to re-open an invalid note.
 fn launch_editor(
     editor: &str,
     arg: Option<&str>,
     path: &Path,
-    templ: Option<&Path>
+    templ: Option<&Path>,
+    retries: Option<u32>,
 ) -> anyhow::Result<()> {
     use std::{
         process::Command,
@@ -235,9 +682,14 @@ fn launch_editor(
 
         Ok(())
     } else {
-        match Note::validate_header(&path) {
-            Ok(()) => Ok(()),
-            Err(e) => {
+        match (Note::validate_header(&path), retries) {
+            (Ok(()), _) => Ok(()),
+            (Err(e), Some(0)) => Err(e.into()),
+            (Err(e), Some(n)) => {
+                eprintln!("Error validating note. {}", e);
+                launch_editor(editor, arg, path, None, Some(n - 1))
+            },
+            (Err(e), None) => {
                 println!("Error validating note. {}", e);
                 print!("Would you like to re-open the file to fix? [Y/n] ");
                 io::stdout().flush()?;
@@ -246,7 +698,8 @@ fn launch_editor(
                 io::stdin().read_line(&mut inp)?;
 
                 match inp.trim() {
-                    "" | "y" | "Y" => launch_editor(editor, arg, path, None),
+                    "" | "y" | "Y" =>
+                        launch_editor(editor, arg, path, None, None),
                     _ => Err(e.into()),
                 }
             },
@@ -254,6 +707,61 @@ fn launch_editor(
     }
 }
 
+/// The category of a failure, which determines the exit status.
+///
+/// A category is attached to an error as context. An error without one is
+/// categorized by its cause: note parse errors are validation errors, and
+/// file errors are IO errors.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Failure {
+    /// Invalid command-line arguments.
+    Usage,
+    /// A missing or invalid configuration.
+    Config,
+    /// An invalid note or attribute.
+    Validation,
+    /// A file could not be read or written.
+    Io,
+}
+
+impl Failure {
+    /// Determine the category of the error, if it has one.
+    fn of(e: &anyhow::Error) -> Option<Self> {
+        if let Some(failure) = e.downcast_ref::<Failure>() {
+            Some(*failure)
+        } else if let Some(e) = e.downcast_ref::<FileError>() {
+            match e {
+                FileError::Parse { .. } => Some(Failure::Validation),
+                FileError::IO(_) => Some(Failure::Io),
+            }
+        } else if e.downcast_ref::<std::io::Error>().is_some() {
+            Some(Failure::Io)
+        } else {
+            None
+        }
+    }
+
+    fn exit_code(self) -> i32 {
+        match self {
+            Failure::Usage => 2,
+            Failure::Config => 3,
+            Failure::Validation => 4,
+            Failure::Io => 5,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Failure::Usage => "Invalid arguments",
+            Failure::Config => "Configuration error",
+            Failure::Validation => "Invalid note",
+            Failure::Io => "File error",
+        })
+    }
+}
+
 /// Errors that can occur while reading information from our exteral
 /// environment.
 #[derive(Debug, Clone)]
@@ -473,3 +981,72 @@ fn determine_file_path(options: &Options, conf: &Config)
         Err(anyhow!("Unknown collection - {}", coll))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn migrate_stamps_versionless_note() {
+        let mut note = Note::from_str("@tag\n[Key: Value]\n").unwrap();
+
+        migrate(&mut note).unwrap();
+        assert_eq!(note[VERSION_ATTRIBUTE], FORMAT_VERSION.to_string());
+        assert_eq!(note["Key"], "Value");
+        assert!(note.contains_tag("@tag"));
+    }
+
+    #[test]
+    fn migrate_rejects_newer_version() {
+        let text = format!("[{}: {}]\n", VERSION_ATTRIBUTE, FORMAT_VERSION + 1);
+        let mut note = Note::from_str(&text).unwrap();
+        let orig = note.clone();
+
+        assert!(migrate(&mut note).is_err());
+        assert_eq!(note, orig);
+    }
+
+    #[test]
+    fn check_invalid_format_version() {
+        let text = format!("[{}: one]\n", VERSION_ATTRIBUTE);
+        let note = Note::from_str(&text).unwrap();
+
+        assert!(check_format_version(&note).is_err());
+    }
+
+    #[test]
+    fn instantiate_template_creates_note() {
+        let templ = env::temp_dir().join("upim_edit_instantiate.template");
+        let path = env::temp_dir().join("upim_edit_instantiate_new.txt");
+        let _ = fs::remove_file(&path);
+
+        fs::write(&templ, "[Name: ]\n").unwrap();
+        let res = instantiate_template(&templ, &path);
+        let text = fs::read_to_string(&path);
+
+        let _ = fs::remove_file(&templ);
+        let _ = fs::remove_file(&path);
+
+        assert!(res.is_ok());
+        assert_eq!(text.unwrap(), "[Name: ]\n");
+    }
+
+    #[test]
+    fn instantiate_template_does_not_overwrite_note() {
+        let templ = env::temp_dir().join("upim_edit_no_clobber.template");
+        let path = env::temp_dir().join("upim_edit_no_clobber_existing.txt");
+
+        fs::write(&templ, "[Name: ]\n").unwrap();
+        fs::write(&path, "[Name: Somebody]\n").unwrap();
+
+        let res = instantiate_template(&templ, &path);
+        let text = fs::read_to_string(&path);
+
+        let _ = fs::remove_file(&templ);
+        let _ = fs::remove_file(&path);
+
+        assert!(res.is_err());
+        assert_eq!(text.unwrap(), "[Name: Somebody]\n");
+    }
+}
@@ -7,12 +7,7 @@ use std::{
     str,
 };
 
-use rand::{
-    distributions::Alphanumeric,
-    Rng,
-    thread_rng,
-};
-
+use upim_core::paths::temp_in_dir;
 use upim_note::Note;
 
 
@@ -20,22 +15,7 @@ const UPIM_EDIT: &str = "../../target/debug/upim-edit";
 
 /// Retrieve a path to a non-existent file in a temporary directory.
 fn temp_file() -> PathBuf {
-    let mut rng = thread_rng();
-    let path = env::temp_dir();
-
-    let file = loop {
-        let name: String = (&mut rng).sample_iter(Alphanumeric)
-            .take(4)
-            .map(char::from)
-            .collect();
-
-        let mut file = path.clone();
-
-        file.push(name);
-        file.set_extension("txt");
-
-        if ! file.exists() { break file; }
-    };
+    let file = temp_in_dir(&env::temp_dir()).with_extension("txt");
 
     println!("* temporary file path: {:?}", file);
     file
@@ -58,6 +38,22 @@ fn exec(command: &str, args: &[&str]) -> Output {
         .expect("Failed to execute process")
 }
 
+/// Execute the command, writing `input` to its stdin.
+fn exec_with_input(command: &str, args: &[&str], input: &str) -> Output {
+    use std::process::Stdio;
+
+    let mut child = Command::new(command)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to execute process");
+
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().expect("Failed to execute process")
+}
+
 
 #[test]
 fn add_tags_to_file() {
@@ -79,6 +75,22 @@ fn add_tags_to_file() {
     remove_file(path).unwrap();
 }
 
+#[test]
+fn dry_run_add_tags() {
+    let text = "@tag1\n[key: value]\n\nSome content.\n";
+    let (path, _) = temp_file_with(text);
+
+    let output = exec(UPIM_EDIT,
+        &["--dry-run", "--add-tags", "@x", "@tag1", path.to_str().unwrap()]
+    );
+    let after = std::fs::read_to_string(&path).unwrap();
+    remove_file(path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(str::from_utf8(&output.stdout).unwrap(), "+@x\n");
+    assert_eq!(after, text);
+}
+
 #[test]
 fn remove_tags() {
     let (path, _) = temp_file_with("\
@@ -138,6 +150,45 @@ fn add_attribute_to_file() {
     remove_file(path).unwrap();
 }
 
+#[test]
+fn append_attribute_values() {
+    let (path, _) = temp_file_with("\
+    @tag\n\
+    [Name: Favorite Person]\n\
+    \n\
+    Some content.\n\
+    ");
+    let path_str = path.to_str().unwrap();
+
+    let first =
+        exec(UPIM_EDIT, &["--append-attr", "Phone", "555-0100", path_str]);
+    let second =
+        exec(UPIM_EDIT, &["--append-attr", "Phone", "555-0199", path_str]);
+
+    let note = Note::read_from_file(&path).unwrap();
+    let attrs = exec(UPIM_EDIT, &["--attributes", path_str]);
+    remove_file(path).unwrap();
+
+    assert!(first.status.success());
+    assert!(second.status.success());
+
+    assert_eq!(note["Name"], "Favorite Person");
+    assert_eq!(note["Phone"], "555-0100");
+    assert_eq!(
+        note.attributes_sorted(),
+        vec![
+            (&"Name".to_string(), &"Favorite Person".to_string()),
+            (&"Phone".to_string(), &"555-0100".to_string()),
+            (&"Phone".to_string(), &"555-0199".to_string()),
+        ]
+    );
+    assert_eq!(note.content(), "Some content.\n");
+
+    let attrs = str::from_utf8(&attrs.stdout).unwrap();
+    assert!(attrs.contains("Phone:555-0100\n"));
+    assert!(attrs.contains("Phone:555-0199\n"));
+}
+
 #[test]
 fn remove_attribute() {
     let (path, _) = temp_file_with("\
@@ -160,6 +211,26 @@ fn remove_attribute() {
     remove_file(path).unwrap();
 }
 
+#[test]
+fn add_invalid_attribute_is_rejected() {
+    let content = "@tag\n[key: value]\n\nSome content.\n";
+    let (path, _) = temp_file_with(content);
+    let path_str = path.to_str().unwrap();
+
+    let colon_key = exec(UPIM_EDIT, &["--add-attr", "ke:y", "value", path_str]);
+    let bracket_value = exec(UPIM_EDIT,
+        &["--add-attr", "key2", "val]ue", path_str]
+    );
+    let text = std::fs::read_to_string(&path).unwrap();
+    remove_file(path).unwrap();
+
+    assert!(! colon_key.status.success());
+    assert!(str::from_utf8(&colon_key.stderr).unwrap().contains("':'"));
+    assert!(! bracket_value.status.success());
+    assert!(str::from_utf8(&bracket_value.stderr).unwrap().contains("']'"));
+    assert_eq!(text, content);
+}
+
 #[test]
 fn list_attributes() {
     let (path, _) = temp_file_with("\
@@ -173,11 +244,7 @@ fn list_attributes() {
     let output = exec(UPIM_EDIT, &["--attributes", path.to_str().unwrap()]);
     let output = str::from_utf8(&output.stdout).unwrap();
 
-    // We don't know the order in which attributes will be printed to stdout.
-    assert!(output.contains("key1:value1"));
-    assert!(output.contains("key2:value2"));
-    assert!(! output.contains("@tag"));
-    assert!(! output.contains("Some content"));
+    assert_eq!(output, "key1:value1\nkey2:value2\n");
 
     remove_file(path).unwrap();
 }
@@ -198,3 +265,483 @@ fn print_content() {
 
     remove_file(path).unwrap();
 }
+
+#[test]
+fn append_content_from_stdin() {
+    let (path, _) = temp_file_with("\
+    @tag\n\
+    [key: value]\n\
+    \n\
+    Some content.\
+    ");
+
+    let output = exec_with_input(
+        UPIM_EDIT,
+        &["--append-content", "-", path.to_str().unwrap()],
+        "New line.\n"
+    );
+    assert!(output.status.success());
+
+    let note = Note::read_from_file(&path).unwrap();
+
+    assert!(note.contains_tag("@tag"));
+    assert_eq!(note["key"], "value");
+    assert_eq!(note.content(), "Some content.\nNew line.\n");
+
+    remove_file(path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn append_content_keeps_permissions() {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    let (path, _) = temp_file_with("@tag\n\nSome content.\n");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+    let output = exec_with_input(
+        UPIM_EDIT,
+        &["--append-content", "-", path.to_str().unwrap()],
+        "New line.\n"
+    );
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    remove_file(path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn print_collections_as_json() {
+    use serde_json::Value;
+
+    let (conf, _) = temp_file_with("\
+    editor = true\n\
+    [Collections]\n\
+    work = /tmp/work\n\
+    home = relative/home\n\
+    ");
+
+    let output = exec(UPIM_EDIT,
+        &["--conf", conf.to_str().unwrap(), "--collections", "--format", "json"]
+    );
+    remove_file(&conf).unwrap();
+
+    let json: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let collections = json.as_array().unwrap();
+    let find = |name: &str| collections.iter()
+        .find(|c| c["name"] == name)
+        .unwrap_or_else(|| panic!("Missing collection {}", name));
+
+    assert_eq!(collections.len(), 2);
+    assert_eq!(find("work")["path"], "/tmp/work");
+    assert!(find("work").get("error").is_none());
+    assert!(find("home")["path"].is_null());
+    assert!(find("home")["error"].is_string());
+}
+
+#[test]
+fn validate_with_json_diagnostics() {
+    use serde_json::Value;
+
+    let (path, _) = temp_file_with("\
+    @tag\n\
+    [key: value]\n\
+    @ok @\n\
+    not metadata\n\
+    \n\
+    Some content.\n\
+    ");
+
+    let output = exec(UPIM_EDIT,
+        &["--validate", "--format", "json", path.to_str().unwrap()]
+    );
+    let (valid, _) = temp_file_with("@tag\n\nSome content.\n");
+    let valid_output = exec(UPIM_EDIT,
+        &["--validate", "--format", "json", valid.to_str().unwrap()]
+    );
+    remove_file(path).unwrap();
+    remove_file(valid).unwrap();
+
+    assert!(! output.status.success());
+
+    let json: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let diagnostics = json.as_array().unwrap();
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0]["line"], 3);
+    assert_eq!(diagnostics[0]["column"], 5);
+    assert_eq!(diagnostics[1]["line"], 4);
+    assert_eq!(diagnostics[1]["column"], 1);
+    assert!(diagnostics[1]["message"].is_string());
+
+    assert!(valid_output.status.success());
+    assert_eq!(str::from_utf8(&valid_output.stdout).unwrap(), "[]\n");
+}
+
+#[test]
+fn lint_clean_note() {
+    let text = "@a @b\n[Author: me]\n[Title: x]\n\nSome content.\n";
+    let (path, _) = temp_file_with(text);
+
+    let output = exec(UPIM_EDIT, &["--lint", path.to_str().unwrap()]);
+    let json = exec(UPIM_EDIT,
+        &["--lint", "--format", "json", path.to_str().unwrap()]
+    );
+    let after = std::fs::read_to_string(&path).unwrap();
+    remove_file(path).unwrap();
+
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    assert!(json.status.success());
+    assert_eq!(str::from_utf8(&json.stdout).unwrap(), "[]\n");
+    assert_eq!(after, text);
+}
+
+#[test]
+fn lint_messy_note() {
+    use serde_json::Value;
+
+    let text = "@a\n[Title: x ]\n[Author: me]\n@b\n\n\nSome content.\n";
+    let (path, _) = temp_file_with(text);
+
+    let output = exec(UPIM_EDIT, &["--lint", path.to_str().unwrap()]);
+    let json = exec(UPIM_EDIT,
+        &["--lint", "--format", "json", path.to_str().unwrap()]
+    );
+    let after = std::fs::read_to_string(&path).unwrap();
+    remove_file(path).unwrap();
+
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = str::from_utf8(&output.stderr).unwrap();
+    assert_eq!(stderr.matches("Warning: ").count(), 4);
+
+    assert_eq!(json.status.code(), Some(4));
+    let json: Value = serde_json::from_slice(&json.stdout).unwrap();
+    let kinds = json.as_array().unwrap().iter()
+        .map(|w| (w["line"].as_u64().unwrap(), w["kind"].as_str().unwrap()))
+        .collect::<Vec<_>>();
+
+    assert_eq!(kinds, [
+        (2, "trailing-whitespace"),
+        (3, "unsorted-attributes"),
+        (4, "ungrouped-tags"),
+        (6, "extra-blank-lines"),
+    ]);
+    assert_eq!(after, text);
+}
+
+/// Run `upim-edit --init` with the given arguments in a temporary home
+/// directory, returning the output and the directory.
+fn exec_init(home: &str, args: &[&str]) -> (Output, PathBuf) {
+    let home = env::temp_dir().join(home);
+    std::fs::create_dir_all(&home).unwrap();
+
+    let output = Command::new(UPIM_EDIT)
+        .arg("--init")
+        .args(args)
+        .env("HOME", &home)
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("UPIM_CONFIG_DIR")
+        .env_remove("EDITOR")
+        .output()
+        .expect("Failed to execute process");
+
+    (output, home)
+}
+
+#[test]
+fn init_creates_configuration() {
+    use std::fs;
+
+    let (output, home) = exec_init("upim_edit_init_home", &["--global"]);
+    let conf_dir = home.join(".config/upim");
+
+    let app = fs::read_to_string(conf_dir.join("upim-edit.conf"));
+    let global = fs::read_to_string(conf_dir.join("upim.conf"));
+    let created = home.join("upim").is_dir()
+        && conf_dir.join("templates").is_dir();
+    fs::remove_dir_all(&home).unwrap();
+
+    assert!(output.status.success());
+    assert!(created);
+    assert_eq!(app.unwrap(), "[DEFAULT]\neditor = vi\n");
+
+    let global = global.unwrap();
+    assert!(global.contains(&format!(
+        "collection_base = {}\n", home.join("upim").display()
+    )));
+    assert!(global.contains(&format!(
+        "template_folder = {}\n", conf_dir.join("templates").display()
+    )));
+}
+
+#[test]
+fn init_refuses_to_replace_configuration() {
+    use std::fs;
+
+    let home = env::temp_dir().join("upim_edit_init_existing");
+    let conf_dir = home.join(".config/upim");
+    fs::create_dir_all(&conf_dir).unwrap();
+    fs::write(conf_dir.join("upim-edit.conf"), "editor = ed\n").unwrap();
+
+    let (refused, _) = exec_init("upim_edit_init_existing", &[
+        "--editor", "nano",
+        "--collection-base", home.join("notes").to_str().unwrap(),
+    ]);
+    let kept = fs::read_to_string(conf_dir.join("upim-edit.conf"));
+
+    let (forced, _) = exec_init("upim_edit_init_existing", &[
+        "--force",
+        "--editor", "nano",
+        "--collection-base", home.join("notes").to_str().unwrap(),
+    ]);
+    let replaced = fs::read_to_string(conf_dir.join("upim-edit.conf"));
+    fs::remove_dir_all(&home).unwrap();
+
+    assert!(! refused.status.success());
+    assert!(str::from_utf8(&refused.stderr).unwrap().contains("--force"));
+    assert_eq!(kept.unwrap(), "editor = ed\n");
+
+    assert!(forced.status.success());
+    assert!(replaced.unwrap().contains("editor = nano\n"));
+}
+
+#[test]
+fn exit_status_for_usage_error() {
+    let output = exec(UPIM_EDIT, &["--add-attr", "key"]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let output = exec(UPIM_EDIT, &["--help"]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn exit_status_for_validation_error() {
+    let (path, _) = temp_file_with("@ok\nnot metadata\n\nSome content.\n");
+    let path_str = path.to_str().unwrap();
+
+    let validate = exec(UPIM_EDIT, &["--validate", path_str]);
+    let add_tags = exec(UPIM_EDIT, &["--add-tags", "@tag", path_str]);
+    remove_file(&path).unwrap();
+    let missing = exec(UPIM_EDIT, &["--tags", path_str]);
+
+    assert_eq!(validate.status.code(), Some(4));
+    assert_eq!(add_tags.status.code(), Some(4));
+    assert_eq!(missing.status.code(), Some(5));
+}
+
+#[test]
+fn migrate_versionless_note() {
+    let (path, _) = temp_file_with("\
+    @tag\n\
+    [key:value]\n\
+    \n\
+    Some content.\n\
+    ");
+
+    let output = exec(UPIM_EDIT, &["--migrate", path.to_str().unwrap()]);
+    assert!(output.status.success());
+
+    let note = Note::read_from_file(&path).unwrap();
+    let text = std::fs::read_to_string(&path).unwrap();
+
+    assert_eq!(note["uPIM-Version"], "1");
+    assert_eq!(note["key"], "value");
+    assert!(note.contains_tag("@tag"));
+    assert!(text.contains("[key: value]\n"));
+
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn dry_run_migrate() {
+    let text = "@tag\n[key: value]\n\nSome content.\n";
+    let (path, _) = temp_file_with(text);
+
+    let output = exec(UPIM_EDIT,
+        &["--dry-run", "--migrate", path.to_str().unwrap()]
+    );
+    let after = std::fs::read_to_string(&path).unwrap();
+    remove_file(path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        str::from_utf8(&output.stdout).unwrap(),
+        "+[uPIM-Version: 1]\n"
+    );
+    assert_eq!(after, text);
+}
+
+#[test]
+fn migrate_refuses_newer_version() {
+    let content = "[uPIM-Version: 1000]\n\nSome content.\n";
+    let (path, _) = temp_file_with(content);
+
+    let output = exec(UPIM_EDIT, &["--migrate", path.to_str().unwrap()]);
+    assert!(! output.status.success());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), content);
+
+    let output = exec(UPIM_EDIT, &["--content", path.to_str().unwrap()]);
+    let stderr = str::from_utf8(&output.stderr).unwrap();
+    assert!(output.status.success());
+    assert!(stderr.contains("Warning"));
+
+    remove_file(path).unwrap();
+}
+
+/// Create an executable editor script that writes an invalid note on each of
+/// the first `failures` opens and a valid note afterward, and a configuration
+/// file that uses it with the given `validate_retries`.
+///
+/// Each time the editor runs, it appends a line to `<note path>.opened`.
+#[cfg(unix)]
+fn scripted_editor_conf(failures: u32, retries: u32) -> (PathBuf, PathBuf) {
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    let script = temp_file().with_extension("sh");
+    fs::write(&script, format!("\
+        #!/bin/sh\n\
+        echo opened >> \"$1.opened\"\n\
+        if [ $(wc -l < \"$1.opened\") -gt {} ]; then\n\
+            printf '@tag\\n\\nFixed.\\n' > \"$1\"\n\
+        else\n\
+            printf 'Not a header\\n\\nBroken.\\n' > \"$1\"\n\
+        fi\n\
+    ", failures)).unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let (conf, _) = temp_file_with(&format!(
+        "editor = {}\nvalidate_retries = {}\n",
+        script.display(),
+        retries
+    ));
+
+    (script, conf)
+}
+
+#[cfg(unix)]
+#[test]
+fn retry_validation_until_note_is_fixed() {
+    let (script, conf) = scripted_editor_conf(1, 2);
+    let (path, _) = temp_file_with("@tag\n\nOriginal.\n");
+    let opened = path.with_extension("txt.opened");
+
+    let output = exec(UPIM_EDIT,
+        &["--conf", conf.to_str().unwrap(), path.to_str().unwrap()]
+    );
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    let opens = std::fs::read_to_string(&opened).unwrap();
+
+    for file in [&script, &conf, &path, &opened] {
+        remove_file(file).unwrap();
+    }
+
+    assert!(output.status.success());
+    assert_eq!(text, "@tag\n\nFixed.\n");
+    assert_eq!(opens.lines().count(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn retry_validation_gives_up() {
+    let (script, conf) = scripted_editor_conf(5, 1);
+    let (path, _) = temp_file_with("@tag\n\nOriginal.\n");
+    let opened = path.with_extension("txt.opened");
+
+    let output = exec(UPIM_EDIT,
+        &["--conf", conf.to_str().unwrap(), path.to_str().unwrap()]
+    );
+
+    let opens = std::fs::read_to_string(&opened).unwrap();
+
+    for file in [&script, &conf, &path, &opened] {
+        remove_file(file).unwrap();
+    }
+
+    assert!(! output.status.success());
+    assert_eq!(opens.lines().count(), 2);
+    assert!(! lock_file(&path).exists());
+}
+
+/// Get the path of the lock file upim-edit uses while editing `path`.
+fn lock_file(path: &std::path::Path) -> PathBuf {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    path.with_file_name(format!(".{}.lock", name))
+}
+
+#[cfg(unix)]
+#[test]
+fn refuse_to_edit_locked_note() {
+    let (script, conf) = scripted_editor_conf(0, 0);
+    let (path, _) = temp_file_with("@tag\n\nOriginal.\n");
+    let opened = path.with_extension("txt.opened");
+    let lock = lock_file(&path);
+    std::fs::write(&lock, "1\n").unwrap();
+
+    let output = exec(UPIM_EDIT,
+        &["--conf", conf.to_str().unwrap(), path.to_str().unwrap()]
+    );
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    let lock_remains = lock.exists();
+
+    for file in [&script, &conf, &path, &lock] {
+        remove_file(file).unwrap();
+    }
+
+    assert!(! output.status.success());
+    assert!(! opened.exists());
+    assert!(lock_remains);
+    assert_eq!(text, "@tag\n\nOriginal.\n");
+}
+
+#[test]
+fn print_path_in_collection() {
+    use std::fs;
+
+    let dir = temp_file().with_extension("d");
+    let collection = dir.join("work");
+    let templates = dir.join("templates");
+
+    fs::create_dir_all(&collection).unwrap();
+    fs::create_dir_all(&templates).unwrap();
+    fs::write(templates.join("work.template"), "@work\n").unwrap();
+    fs::write(collection.join("existing.txt"), "@work\n").unwrap();
+
+    let (conf, _) = temp_file_with(&format!(
+        "editor = true\n\
+        template_folder = {}\n\
+        [Collections]\n\
+        work = {}\n",
+        templates.display(),
+        collection.display()
+    ));
+    let conf = conf.to_str().unwrap();
+
+    let new = exec(UPIM_EDIT,
+        &["--conf", conf, "-C", "work", "--print-path", "foo.txt"]
+    );
+    let existing = exec(UPIM_EDIT,
+        &["--conf", conf, "-C", "work", "--print-path", "existing.txt"]
+    );
+
+    let created = collection.join("foo.txt").exists();
+    fs::remove_dir_all(&dir).unwrap();
+    remove_file(conf).unwrap();
+
+    assert_eq!(
+        str::from_utf8(&new.stdout).unwrap(),
+        format!(
+            "{}\nTemplate: {}\n",
+            collection.join("foo.txt").display(),
+            templates.join("work.template").display()
+        )
+    );
+    assert_eq!(
+        str::from_utf8(&existing.stdout).unwrap(),
+        format!("{}\n", collection.join("existing.txt").display())
+    );
+    assert!(! created);
+}
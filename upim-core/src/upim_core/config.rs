@@ -10,6 +10,21 @@
 //! - whitespace within group names, variable names, and values is allowed.
 //! - a semicolon (';') at the beginning of a line denotes a comment.
 //! - if a variable is set multiple times in a file, the last one read is kept.
+//! - `${variable}` within a value is replaced by the value of that variable in
+//!   the same group, or in the DEFAULT group if the group doesn't set it.
+//!   References are resolved after the entire file is read, so a variable may
+//!   be referenced before it is set; it must be set in the same file, as
+//!   references are resolved before files are merged. Write `$${` for a
+//!   literal `${`.
+//! - a group whose name ends with `.` and an operating system name, such as
+//!   `[DEFAULT.windows]`, sets values only on that operating system. On that
+//!   system its variables replace those of the plain group (`[DEFAULT]`)
+//!   regardless of their order in the file; on any other system they are
+//!   ignored. The names are those of `std::env::consts::OS`: "linux",
+//!   "macos", "windows", "freebsd", etc.
+//!
+//! Values whose surrounding whitespace is significant can be retrieved without
+//! modification via [Config::get_raw].
 //!
 //! Multiple INI files can be merged into a single [Config]; variables read in a
 //! later file replace any set in prior configuration files.
@@ -17,16 +32,22 @@
 //! The configuration values can be modified via the [Config::set] method; `set`
 //! also provides a convenient API for setting default values prior to reading a
 //! configuration file.
+//!
+//! With the `watch` feature, [Config::watch] reloads a configuration file
+//! each time it changes.
 
 use std::{
     collections::HashMap,
+    ffi::OsStr,
+    fmt,
     path::{Path, PathBuf},
     ops::Index,
+    time::Duration,
     env,
 };
 
 use super::{
-    error::FileError,
+    error::{FileError, ParseKind, ValueError},
     uniq::Uniq,
 };
 
@@ -34,6 +55,9 @@ use super::{
 #[cfg(target_os = "macos")]
 static BUNDLE_ID: &str = "us.simplifysystems.uPIM";
 
+/// The environment variable that overrides the uPIM configuration directories.
+pub const CONFIG_DIR_VAR: &str = "UPIM_CONFIG_DIR";
+
 /// Read the standard uPIM configuration.
 ///
 /// Configurations are read from the following paths, in the following order:
@@ -64,13 +88,18 @@ static BUNDLE_ID: &str = "us.simplifysystems.uPIM";
 /// Values set in later files override the earlier values, so the priority is in
 /// the reverse order of the list above.
 ///
+/// If the `UPIM_CONFIG_DIR` environment variable is set, it replaces the
+/// system and user directories above; see [get_upim_configuration_dirs]. The
+/// file in the current working directory is still read.
+///
 /// Applications built upon uPIM may place their own configuration files within
 /// a `upim` configuration directory but will need to read that configuration
 /// via the [Config] object rather than this function.
 ///
 /// # Returns
 ///
-/// If no configuration files were found, returns [Config::default].
+/// If no configuration files were found, returns [Config::default]; use
+/// [Config::is_empty_source] to tell this apart from empty files.
 ///
 /// Returns `Ok(Config)` if all discovered configuration files were successfully
 /// read. Otherwise, returns `Err(Config)` containing the settings from all
@@ -79,7 +108,94 @@ static BUNDLE_ID: &str = "us.simplifysystems.uPIM";
 /// This function will be updated to report what errors occured in the failure
 /// case.
 pub fn read_upim_configuration() -> Result<Config, Vec<FileError>> {
-    let conf_files = get_upim_configuration_paths().unwrap_or_default();
+    read_upim_configuration_from(
+        &get_upim_configuration_dirs().unwrap_or_default(),
+        local_configuration_path().as_deref()
+    )
+}
+
+/// A problem with the configuration files on the system that does not prevent
+/// them from being read.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConfigWarning {
+    /// Both `$XDG_CONFIG_HOME/upim` and `$HOME/.config/upim` contain a
+    /// `upim.conf` file; only the first is read.
+    AmbiguousUserDirectory { used: PathBuf, ignored: PathBuf },
+}
+
+impl fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigWarning::AmbiguousUserDirectory { used, ignored } => write!(
+                f,
+                "Both {} and {} contain a upim.conf; only the first is read",
+                used.display(),
+                ignored.display()
+            ),
+        }
+    }
+}
+
+/// Check the configuration directories for likely mistakes, such as a user
+/// configuration in both of the possible user directories.
+///
+/// Applications should report the warnings to the user; the configuration
+/// is read the same way regardless.
+pub fn configuration_warnings() -> Vec<ConfigWarning> {
+    let mut warnings = vec![];
+
+    if env::var_os(CONFIG_DIR_VAR).filter(|d| ! d.is_empty()).is_some() {
+        return warnings;
+    }
+
+    #[cfg(unix)]
+    {
+        let xdg = env::var_os("XDG_CONFIG_HOME");
+        let home = env::var_os("HOME");
+
+        if let (_, Some(w)) = unixy_user_dir(xdg.as_deref(), home.as_deref()) {
+            warnings.push(w);
+        }
+    }
+
+    warnings
+}
+
+/// Read and merge the `upim.conf` file in each of the given directories, in
+/// order, followed by the file at `local`.
+///
+/// Missing files are skipped.
+fn read_upim_configuration_from(dirs: &[PathBuf], local: Option<&Path>)
+-> Result<Config, Vec<FileError>> {
+    let mut paths = find_configuration_in(dirs.to_vec(), "upim");
+
+    if let Some(local) = local.filter(|p| p.exists()) {
+        paths.push(local.to_path_buf());
+    }
+
+    read_configuration_files(&paths)
+}
+
+/// Get the path of the uPIM configuration file in the current directory.
+///
+/// The file is not required to exist.
+fn local_configuration_path() -> Option<PathBuf> {
+    #![allow(unreachable_code)]
+
+    let dir = env::current_dir().ok()?;
+
+    #[cfg(windows)]
+    return Some(dir.join("upim.conf"));
+
+    #[cfg(unix)]
+    return Some(dir.join(".upim.conf"));
+
+    panic!();
+}
+
+/// Read and merge the given configuration files in order.
+fn read_configuration_files(conf_files: &[PathBuf])
+-> Result<Config, Vec<FileError>> {
     let mut conf = Config::default();
     let mut errors = vec![];
 
@@ -113,9 +229,18 @@ pub fn read_upim_configuration() -> Result<Config, Vec<FileError>> {
 ///
 /// See the documentation for [read_upim_configuration] for the possible
 /// locations of the configuration files.
+///
+/// If the `UPIM_CONFIG_DIR` environment variable is set to a non-empty value,
+/// it is the only directory returned, and the platform's standard directories
+/// are not searched. If that directory does not exist, returns `None`.
 pub fn get_upim_configuration_dirs() -> Option<Vec<PathBuf>> {
     #![allow(unreachable_code)]
 
+    if let Some(dir) = env::var_os(CONFIG_DIR_VAR).filter(|d| ! d.is_empty()) {
+        let dir = PathBuf::from(dir);
+        return dir.is_dir().then(|| vec![dir]);
+    }
+
     #[cfg(windows)]
     return get_windows_dirs();
 
@@ -125,6 +250,33 @@ pub fn get_upim_configuration_dirs() -> Option<Vec<PathBuf>> {
     panic!();
 }
 
+/// Get the directory in which the user's own uPIM configuration files belong,
+/// whether or not it exists.
+///
+/// This is the `UPIM_CONFIG_DIR` directory if that variable is set;
+/// otherwise `$XDG_CONFIG_HOME/upim` (or `$HOME/.config/upim`) on UNIXy
+/// systems and `%APPDATA%\uPIM` on Windows. Returns `None` if the directory
+/// cannot be determined from the environment.
+pub fn user_configuration_dir() -> Option<PathBuf> {
+    #![allow(unreachable_code)]
+
+    if let Some(dir) = env::var_os(CONFIG_DIR_VAR).filter(|d| ! d.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+
+    #[cfg(windows)]
+    return env::var_os("APPDATA").map(|p| PathBuf::from(p).join("uPIM"));
+
+    #[cfg(unix)]
+    return env::var_os("XDG_CONFIG_HOME")
+        .filter(|p| ! p.is_empty())
+        .map(|p| PathBuf::from(p).join("upim"))
+        .or_else(|| env::var_os("HOME")
+            .map(|p| PathBuf::from(p).join(".config/upim")));
+
+    panic!();
+}
+
 /// Find and return the paths to the configuration files discovered.
 ///
 /// See the documentation for [read_upim_configuration] for the possible
@@ -148,16 +300,57 @@ pub fn get_upim_configuration_paths() -> Option<Vec<PathBuf>> {
 /// - name: The name (without file extension) of the configuration file to
 ///         search for.
 pub fn find_application_configuration(name: &str) -> Option<PathBuf> {
-    let mut paths = get_upim_configuration_dirs().unwrap_or_default();
+    find_all_application_configuration(name).into_iter().next()
+}
+
+/// Get the paths to every application configuration file discovered.
+///
+/// The paths are in the same order as the directories searched by
+/// [read_upim_configuration], so later files should take precedence over
+/// earlier ones.
+///
+/// # Parameters
+///
+/// - name: The configuration file's name, without the file extension.
+pub fn find_all_application_configuration(name: &str) -> Vec<PathBuf> {
+    find_configuration_in(
+        get_upim_configuration_dirs().unwrap_or_default(),
+        name
+    )
+}
+
+/// Read and merge every application configuration file discovered.
+///
+/// Values in later files override those in earlier files, as with
+/// [read_upim_configuration]. If no files were found, returns
+/// [Config::default].
+///
+/// # Parameters
+///
+/// - name: The configuration file's name, without the file extension.
+pub fn read_application_configuration(name: &str)
+-> Result<Config, Vec<FileError>> {
+    read_configuration_files(&find_all_application_configuration(name))
+}
 
-    paths.iter_mut()
-        .find_map(|p| {
+/// Return the paths of the `<name>.conf` files within the given directories.
+fn find_configuration_in(dirs: Vec<PathBuf>, name: &str) -> Vec<PathBuf> {
+    dirs.into_iter()
+        .filter_map(|mut p| {
             p.push(name);
             p.set_extension("conf");
-            p.exists().then_some(p.clone())
+            p.exists().then_some(p)
         })
+        .collect()
 }
 
+/// The operating system names recognized as group suffixes; see the
+/// module documentation.
+const OS_NAMES: &[&str] = &[
+    "linux", "macos", "windows", "android", "ios", "freebsd", "dragonfly",
+    "netbsd", "openbsd", "solaris", "illumos",
+];
+
 /// The key used to look up a configuration value.
 ///
 /// The key is a group/variable pair. The default group is "DEFAULT".
@@ -168,6 +361,12 @@ type Key = (String, String);
 #[derive(Debug, Default)]
 pub struct Config {
     values: HashMap<Key, String>,
+    /// The values exactly as written, minus the line ending.
+    raw: HashMap<Key, String>,
+    /// The values set via [Config::set], which [Config::reload] keeps.
+    defaults: HashMap<Key, String>,
+    /// The files the values were read from, in the order they were read.
+    sources: Vec<PathBuf>,
 }
 
 impl Config {
@@ -178,6 +377,18 @@ impl Config {
     /// Returns the configuration file if successfully read; otherwise returns a
     /// list of errors that occurred while reading or parsing the file.
     pub fn read_from_file(path: &Path) -> Result<Self, Vec<FileError>> {
+        Self::read_from_file_with_flags(path, None)
+    }
+
+    /// Read a [Config] from the INI file at the path specified, optionally
+    /// allowing flag variables.
+    ///
+    /// A flag is a line containing only a variable name, such as `debug`. If
+    /// `flag_value` is `Some`, each flag is set to that value -- so `debug`
+    /// with a `flag_value` of "true" is read as `debug = true`. If it is
+    /// `None`, flags are errors, as with [Config::read_from_file].
+    pub fn read_from_file_with_flags(path: &Path, flag_value: Option<&str>)
+    -> Result<Self, Vec<FileError>> {
         use std::{
             fs::File,
             io::{prelude::*, BufReader},
@@ -194,6 +405,8 @@ impl Config {
 
         let mut errors = vec![];
         let mut map = HashMap::new();
+        let mut raw = HashMap::new();
+        let mut lines = HashMap::new();
         let mut group = String::from("DEFAULT");
 
         loop {
@@ -206,6 +419,10 @@ impl Config {
             };
 
             cnt += 1;
+            let raw_val = line.trim_end_matches(&['\n', '\r'][..])
+                .split_once('=')
+                .map(|(_, val)| val.to_string());
+
             line = line.trim().into();
             if line.is_empty() { continue; }
 
@@ -218,6 +435,7 @@ impl Config {
                 } else {
                     errors.push(FileError::Parse {
                         file: path.to_owned(),
+                        kind: ParseKind::MissingBracket,
                         msg: "Missing closing bracket for group name".into(),
                         data: line.to_owned(),
                         line: cnt,
@@ -230,19 +448,30 @@ impl Config {
                 if var.is_empty() {
                     errors.push(FileError::Parse {
                         file: path.to_owned(),
+                        kind: ParseKind::EmptyKey,
                         msg: "Assignment requires a variable name".into(),
                         data: line.to_owned(),
                         line: cnt,
                     });
                 } else {
                     map.insert(
-                        (group.clone(), var),
+                        (group.clone(), var.clone()),
                         val.trim_start().to_string()
                     );
+                    lines.insert((group.clone(), var.clone()), cnt);
+                    raw.insert(
+                        (group.clone(), var),
+                        raw_val.unwrap_or_default()
+                    );
                 }
+            } else if let Some(val) = flag_value {
+                map.insert((group.clone(), line.clone()), val.to_string());
+                lines.insert((group.clone(), line.clone()), cnt);
+                raw.insert((group.clone(), line.clone()), String::new());
             } else {
                 errors.push(FileError::Parse {
                     file: path.to_owned(),
+                    kind: ParseKind::InvalidLine,
                     msg: "Expected a variable assignment".into(),
                     data: line.to_owned(),
                     line: cnt,
@@ -251,8 +480,29 @@ impl Config {
             line.clear();
         }
 
+        let map = apply_os_overrides(map);
+        let raw = apply_os_overrides(raw);
+        let lines = apply_os_overrides(lines);
+
+        let (map, ref_errors) = resolve_references(&map);
+
+        for (key, msg) in ref_errors {
+            errors.push(FileError::Parse {
+                file: path.to_owned(),
+                kind: ParseKind::InvalidReference,
+                msg,
+                data: raw[&key].trim().to_owned(),
+                line: lines[&key],
+            });
+        }
+
         if errors.is_empty() {
-            Ok(Self { values: map })
+            Ok(Self {
+                values: map,
+                raw,
+                defaults: HashMap::new(),
+                sources: vec![path.to_owned()],
+            })
         } else {
             Err(errors)
         }
@@ -260,6 +510,9 @@ impl Config {
 
     /// Write this configuration to the given file. If the file exists, it is
     /// replaced with the contents of this configuration.
+    ///
+    /// The file contains the INI text produced by the [Config]'s `Display`
+    /// implementation.
     pub fn write_to_file(&self, path: &Path) -> Result<(), FileError> {
         use std::{
             io::Write as _,
@@ -267,23 +520,104 @@ impl Config {
         };
 
         let mut file = File::create(path)?;
+        write!(file, "{}", self)?;
 
-        for group in self.groups() {
-            writeln!(file, "[{}]", group)?;
+        Ok(())
+    }
 
-            for var in self.variables_in_group(&group) {
-                writeln!(
-                    file,
-                    "{} = {}",
-                    var,
-                    self[(group.as_str(), var.as_str())]
-                )?;
-            }
+    /// Replace the configuration's values with those read from the INI file
+    /// at the path specified.
+    ///
+    /// Values set via [Config::set] are defaults: each is kept unless the file
+    /// sets the same variable, in which case the file's value is used. Every
+    /// other value is discarded, including those read from other files, so
+    /// after reloading the configuration is the same as
+    /// `Config::default().set(...).merge_with(Config::read_from_file(path))`.
+    ///
+    /// If the file cannot be read or parsed, the configuration is unchanged.
+    pub fn reload(&mut self, path: &Path) -> Result<(), Vec<FileError>> {
+        let file = Self::read_from_file(path)?;
+
+        self.values = self.defaults.clone();
+        self.raw = self.defaults.clone();
+
+        for (k, v) in file.values {
+            self.values.insert(k, v);
+        }
+        for (k, v) in file.raw {
+            self.raw.insert(k, v);
         }
+        self.sources = file.sources;
 
         Ok(())
     }
 
+    /// Reload the configuration from the INI file at `path` whenever the file
+    /// changes, passing the result to `callback`.
+    ///
+    /// The file is reloaded via [Config::reload] on a background thread, so
+    /// values set via [Config::set] are kept. Events that occur within
+    /// `delay` of each other are combined into a single reload. If the file
+    /// cannot be read, `callback` receives the errors and the previous values
+    /// are kept for the next reload.
+    ///
+    /// The file is watched until the returned [ConfigWatcher] is dropped.
+    #[cfg(feature = "watch")]
+    pub fn watch<F>(mut self, path: &Path, delay: Duration, callback: F)
+    -> Result<ConfigWatcher, FileError>
+        where F: FnMut(Result<&Config, Vec<FileError>>) + Send + 'static,
+    {
+        use std::{sync::mpsc::channel, thread};
+        use notify::{DebouncedEvent, RecursiveMode, Watcher as _};
+
+        let to_file_error = |e: notify::Error| {
+            let kind = match e {
+                notify::Error::Io(e) => e.kind(),
+                notify::Error::PathNotFound => std::io::ErrorKind::NotFound,
+                _ => std::io::ErrorKind::Other,
+            };
+            FileError::IO((path.to_owned(), kind))
+        };
+
+        // Editors often replace a file rather than write to it, which would
+        // end a watch on the file itself; we watch its directory instead.
+        let dir = match path.parent() {
+            Some(p) if p != Path::new("") => p,
+            _ => Path::new("."),
+        };
+
+        let (tx, rx) = channel();
+        let mut watcher = notify::watcher(tx, delay).map_err(to_file_error)?;
+        watcher.watch(dir, RecursiveMode::NonRecursive)
+            .map_err(to_file_error)?;
+
+        let path = path.to_owned();
+        let mut callback = callback;
+
+        // The sender is dropped with the watcher, which ends the loop.
+        thread::spawn(move || {
+            let is_target = |p: &Path| p.file_name() == path.file_name();
+
+            for event in rx.iter() {
+                let changed = match event {
+                    DebouncedEvent::Create(ref p)
+                    | DebouncedEvent::Write(ref p)
+                    | DebouncedEvent::Rename(_, ref p) => is_target(p),
+                    _ => false,
+                };
+
+                if changed {
+                    match self.reload(&path) {
+                        Ok(_) => callback(Ok(&self)),
+                        Err(e) => callback(Err(e)),
+                    }
+                }
+            }
+        });
+
+        Ok(ConfigWatcher { _watcher: watcher })
+    }
+
     /// Merge two [Config]s, consuming both of the originals.
     ///
     /// Any duplicate variables will contain the values in `other`.
@@ -291,9 +625,34 @@ impl Config {
         for (k, v) in other.values {
             self.values.insert(k, v);
         }
+        for (k, v) in other.raw {
+            self.raw.insert(k, v);
+        }
+        for (k, v) in other.defaults {
+            self.defaults.insert(k, v);
+        }
+        self.sources.extend(other.sources);
         self
     }
 
+    /// Get the paths of the files this configuration was read from, in the
+    /// order they were read.
+    ///
+    /// Each [Config] merged into this one contributes its own files.
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
+
+    /// Check whether no file contributed to this configuration.
+    ///
+    /// This distinguishes a configuration built only in code -- including
+    /// the [Config::default] returned by [read_upim_configuration] when no
+    /// configuration file exists -- from one read from a file that happens
+    /// to be empty.
+    pub fn is_empty_source(&self) -> bool {
+        self.sources.is_empty()
+    }
+
     /// Add the specified value to the configuration.
     ///
     /// `set` can be used to create default settings by setting values prior to
@@ -319,6 +678,14 @@ impl Config {
             (group.into(), var.into()),
             val.into()
         );
+        self.raw.insert(
+            (group.into(), var.into()),
+            val.into()
+        );
+        self.defaults.insert(
+            (group.into(), var.into()),
+            val.into()
+        );
         self
     }
 
@@ -331,7 +698,7 @@ impl Config {
 
     /// Get the list of groups in the configuration file.
     pub fn groups(&self) -> impl Iterator<Item = &String> {
-        self.values.keys().uniq().map(|k| &k.0)
+        self.values.keys().map(|k| &k.0).uniq()
     }
 
     /// Get the list of variables set in the specified group.
@@ -353,6 +720,11 @@ impl Config {
             .map(|(k, v)| (k.1.to_owned(), v))
     }
 
+    /// Get a view of the variables in the specified group.
+    pub fn group_view(&self, group: &str) -> GroupView<'_> {
+        GroupView { conf: self, group: group.to_owned() }
+    }
+
     /// Retrieve the value of the specified variable within the DEFAULT group,
     /// or `None` if it is not set.
     pub fn get_default(&self, variable: &str) -> Option<&String> {
@@ -364,6 +736,250 @@ impl Config {
     pub fn get(&self, group: &str, variable: &str) -> Option<&String> {
         self.values.get(&(group.into(), variable.into()))
     }
+
+    /// Retrieve the value of the specified variable within the DEFAULT group,
+    /// or `default` if it is not set.
+    pub fn get_default_or<'a>(&'a self, variable: &str, default: &'a str)
+    -> &'a str {
+        self.get_default(variable).map_or(default, |v| v.as_str())
+    }
+
+    /// Retrieve the value of the specified variable within the specified group,
+    /// or `default` if it is not set.
+    pub fn get_or<'a>(&'a self, group: &str, variable: &str, default: &'a str)
+    -> &'a str {
+        self.get(group, variable).map_or(default, |v| v.as_str())
+    }
+
+    /// Retrieve the value of the specified variable within the specified group
+    /// exactly as it was written, or `None` if it is not set.
+    ///
+    /// Only the line ending is removed; all whitespace following the '=' is
+    /// kept, including the space that conventionally follows it. Values added
+    /// via [Config::set] are returned as given.
+    pub fn get_raw(&self, group: &str, variable: &str) -> Option<&String> {
+        self.raw.get(&(group.into(), variable.into()))
+    }
+
+    /// Retrieve the value of the specified variable as a number of bytes, or
+    /// `None` if it is not set.
+    ///
+    /// The value is an integer optionally followed by a case-insensitive unit:
+    /// `B`, `KB`, `MB`, or `GB`. Units are powers of 1024, so `1KB` is 1024
+    /// bytes. A value without a unit is a number of bytes.
+    pub fn get_bytes(&self, group: &str, variable: &str)
+    -> Option<Result<u64, ValueError>> {
+        self.get(group, variable)
+            .map(|v| parse_with_unit(v, &[
+                ("", 1),
+                ("B", 1),
+                ("KB", 1 << 10),
+                ("MB", 1 << 20),
+                ("GB", 1 << 30),
+            ]))
+    }
+
+    /// Retrieve the value of the specified variable as a [Duration], or `None`
+    /// if it is not set.
+    ///
+    /// The value is an integer optionally followed by a case-insensitive unit:
+    /// `s` (seconds), `m` (minutes), or `h` (hours). A value without a unit is
+    /// a number of seconds.
+    pub fn get_duration(&self, group: &str, variable: &str)
+    -> Option<Result<Duration, ValueError>> {
+        self.get(group, variable)
+            .map(|v| parse_with_unit(v, &[
+                ("", 1),
+                ("S", 1),
+                ("M", 60),
+                ("H", 60 * 60),
+            ]))
+            .map(|r| r.map(Duration::from_secs))
+    }
+}
+
+/// Parse a value of the form `<integer><unit>`, where the unit must be one of
+/// the given (uppercase) suffixes, and return the integer multiplied by the
+/// unit's scale.
+///
+/// Whitespace is allowed between the number and unit.
+fn parse_with_unit(val: &str, units: &[(&str, u64)])
+-> Result<u64, ValueError> {
+    let err = |msg: &str| ValueError { value: val.into(), msg: msg.into() };
+
+    let idx = val.find(|c: char| ! c.is_ascii_digit()).unwrap_or(val.len());
+    let (num, unit) = val.split_at(idx);
+
+    let num = num.parse::<u64>()
+        .map_err(|_| err("Expected an integer value"))?;
+    let unit = unit.trim().to_ascii_uppercase();
+
+    let scale = units.iter()
+        .find(|(u, _)| *u == unit)
+        .map(|(_, scale)| *scale)
+        .ok_or_else(|| err("Unknown unit"))?;
+
+    num.checked_mul(scale).ok_or_else(|| err("Value is too large"))
+}
+
+/// Replace the `${variable}` references in each value with the referenced
+/// value.
+///
+/// Returns the resolved values, and the key and error message of each value
+/// whose references could not be resolved.
+fn resolve_references(values: &HashMap<Key, String>)
+-> (HashMap<Key, String>, Vec<(Key, String)>) {
+    let mut resolved = HashMap::new();
+    let mut errors = vec![];
+
+    for key in values.keys() {
+        let res = resolve_value(key, values, &mut resolved, &mut vec![]);
+
+        if let Err(msg) = res {
+            errors.push((key.clone(), msg));
+        }
+    }
+
+    (resolved, errors)
+}
+
+/// Move the variables of groups for the current operating system (such as
+/// `[DEFAULT.linux]`) into their plain group, replacing any variables of the
+/// same name, and drop those of groups for other operating systems.
+fn apply_os_overrides<V>(values: HashMap<Key, V>) -> HashMap<Key, V> {
+    let mut generic = HashMap::new();
+    let mut specific = vec![];
+
+    for ((group, var), val) in values {
+        match group.rsplit_once('.') {
+            Some((base, os)) if OS_NAMES.contains(&os) => {
+                if os == env::consts::OS {
+                    specific.push(((base.trim_end().to_string(), var), val));
+                }
+            },
+            _ => { generic.insert((group, var), val); },
+        }
+    }
+
+    generic.extend(specific);
+    generic
+}
+
+/// Resolve the references in the value of `key`, storing the result in
+/// `resolved`.
+///
+/// `stack` holds the keys whose values are currently being resolved so we can
+/// detect cycles.
+fn resolve_value(
+    key: &Key,
+    values: &HashMap<Key, String>,
+    resolved: &mut HashMap<Key, String>,
+    stack: &mut Vec<Key>
+) -> Result<String, String> {
+    if let Some(val) = resolved.get(key) {
+        return Ok(val.clone());
+    }
+    if stack.contains(key) {
+        return Err(format!("Circular reference to variable '{}'", key.1));
+    }
+    stack.push(key.clone());
+
+    let mut val = String::new();
+    let mut rest = values[key].as_str();
+
+    while let Some(start) = rest.find("${") {
+        // `$${` is an escaped, literal `${`.
+        if rest[..start].ends_with('$') {
+            val.push_str(&rest[..start]);
+            val.push('{');
+            rest = &rest[start+2..];
+            continue;
+        }
+
+        val.push_str(&rest[..start]);
+
+        let end = rest[start..].find('}')
+            .map(|i| i + start)
+            .ok_or("Missing closing brace in variable reference")?;
+        let name = rest[start+2..end].trim();
+
+        let target = [key.0.as_str(), "DEFAULT"].iter()
+            .map(|group| (group.to_string(), name.to_string()))
+            .find(|k| values.contains_key(k))
+            .ok_or_else(|| format!("Unknown variable '{}'", name))?;
+
+        val.push_str(&resolve_value(&target, values, resolved, stack)?);
+        rest = &rest[end+1..];
+    }
+    val.push_str(rest);
+
+    stack.pop();
+    resolved.insert(key.clone(), val.clone());
+    Ok(val)
+}
+
+/// Watches a configuration file for changes; created by [Config::watch].
+///
+/// The file is no longer watched once this is dropped.
+#[cfg(feature = "watch")]
+pub struct ConfigWatcher {
+    _watcher: notify::RecommendedWatcher,
+}
+
+/// A read-only view of a single group of a [Config].
+///
+/// Created by [Config::group_view].
+#[derive(Debug, Clone)]
+pub struct GroupView<'a> {
+    conf: &'a Config,
+    group: String,
+}
+
+impl<'a> GroupView<'a> {
+    /// The name of the group.
+    pub fn name(&self) -> &str {
+        &self.group
+    }
+
+    /// Retrieve the value of the specified variable within the group, or
+    /// `None` if it is not set.
+    pub fn get(&self, variable: &str) -> Option<&'a String> {
+        self.conf.get(&self.group, variable)
+    }
+
+    /// Check whether the specified variable is set within the group.
+    pub fn contains(&self, variable: &str) -> bool {
+        self.get(variable).is_some()
+    }
+
+    /// Iterate over the variables and values of the group, in arbitrary order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a String, &'a String)> + '_ {
+        self.conf.values.iter()
+            .filter(move |(k, _)| k.0 == self.group)
+            .map(|(k, v)| (&k.1, v))
+    }
+}
+
+impl fmt::Display for Config {
+    /// Write the configuration in INI format.
+    ///
+    /// Every group, including DEFAULT, is written with its name in brackets.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for group in self.groups() {
+            writeln!(f, "[{}]", group)?;
+
+            for var in self.variables_in_group(&group) {
+                writeln!(
+                    f,
+                    "{} = {}",
+                    var,
+                    self[(group.as_str(), var.as_str())]
+                )?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl Index<&str> for Config {
@@ -388,7 +1004,7 @@ impl Index<(&str, &str)> for Config
 #[allow(dead_code)]
 fn get_unixy_paths() -> Option<Vec<PathBuf>> {
     let mut paths: Vec<PathBuf> = vec![];
-    let mut dirs = get_unixy_dirs().unwrap_or_default();
+    let mut dirs = get_upim_configuration_dirs().unwrap_or_default();
 
     for dir in dirs.iter_mut() {
         dir.push("upim.conf");
@@ -417,7 +1033,7 @@ fn get_unixy_paths() -> Option<Vec<PathBuf>> {
 #[allow(dead_code)]
 fn get_windows_paths() -> Option<Vec<PathBuf>> {
     let mut paths = vec![];
-    let mut dirs = get_windows_dirs().unwrap_or_default();
+    let mut dirs = get_upim_configuration_dirs().unwrap_or_default();
 
     for dir in dirs.iter_mut() {
         dir.push("upim.conf");
@@ -468,16 +1084,12 @@ fn get_unixy_dirs() -> Option<Vec<PathBuf>> {
         }
     }
 
-    let path = if let Some(p) = env::var_os("XDG_CONFIG_HOME") {
-        Path::new(&p).join("upim")
-    } else if let Some(p) = home {
-        Path::new(&p).join(".config/upim")
-    } else {
-        PathBuf::default()
-    };
+    let xdg = env::var_os("XDG_CONFIG_HOME");
 
-    if path.exists() {
-        paths.push(path);
+    if let (Some(path), _) = unixy_user_dir(xdg.as_deref(), home.as_deref()) {
+        if path.exists() {
+            paths.push(path);
+        }
     }
 
     if ! paths.is_empty() {
@@ -487,6 +1099,34 @@ fn get_unixy_dirs() -> Option<Vec<PathBuf>> {
     }
 }
 
+/// Choose the user's uPIM configuration directory on UNIXy systems, given the
+/// values of `XDG_CONFIG_HOME` and `HOME`.
+///
+/// The XDG directory is used whenever `XDG_CONFIG_HOME` is set. If the
+/// `HOME` directory would also have been used -- it contains a `upim.conf`,
+/// as does the XDG directory -- a warning is returned as well.
+#[allow(dead_code)]
+fn unixy_user_dir(xdg: Option<&OsStr>, home: Option<&OsStr>)
+-> (Option<PathBuf>, Option<ConfigWarning>) {
+    let home = home.map(|p| Path::new(p).join(".config/upim"));
+
+    let xdg = match xdg {
+        Some(p) => Path::new(p).join("upim"),
+        None => return (home, None),
+    };
+
+    let warning = home
+        .filter(|h| *h != xdg)
+        .filter(|h| h.join("upim.conf").exists())
+        .filter(|_| xdg.join("upim.conf").exists())
+        .map(|h| ConfigWarning::AmbiguousUserDirectory {
+            used: xdg.clone(),
+            ignored: h,
+        });
+
+    (Some(xdg), warning)
+}
+
 /// See the documentation for [read_upim_configuration] for the possible
 /// locations of the configuration files.
 #[allow(dead_code)]
@@ -512,6 +1152,174 @@ fn get_windows_dirs() -> Option<Vec<PathBuf>> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn reload_keeps_defaults() {
+        use std::fs;
+
+        let path = env::temp_dir().join("upim_core_config_reload.ini");
+        fs::write(&path, "var1 = first\n[Group]\nvar2 = old\n").unwrap();
+
+        let mut conf = Config::default()
+            .set_default("var1", "default")
+            .set_default("var3", "kept")
+            .merge_with(Config::read_from_file(&path).unwrap());
+
+        assert_eq!(conf["var1"], "first");
+        assert_eq!(conf[("Group", "var2")], "old");
+
+        fs::write(&path, "[Group]\nvar2 = new\nvar4 = added\n").unwrap();
+        let reloaded = conf.reload(&path);
+
+        fs::write(&path, "[Group\n").unwrap();
+        let failed = conf.reload(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(reloaded.is_ok());
+        assert!(failed.is_err());
+
+        assert_eq!(conf["var1"], "default");
+        assert_eq!(conf["var3"], "kept");
+        assert_eq!(conf[("Group", "var2")], "new");
+        assert_eq!(conf[("Group", "var4")], "added");
+        assert_eq!(conf.get_raw("Group", "var2").unwrap(), " new");
+    }
+
+    /// Write a configuration with an `editor` for each of several operating
+    /// systems, and read it back.
+    fn read_os_overrides() -> Config {
+        use std::fs;
+
+        let path = env::temp_dir().join("upim_core_config_os.ini");
+        fs::write(&path, "\
+            [DEFAULT.linux]\n\
+            editor = ${term} vim\n\
+            [DEFAULT.macos]\n\
+            editor = mvim\n\
+            [DEFAULT]\n\
+            editor = vi\n\
+            term = xterm -e\n\
+            [DEFAULT.windows]\n\
+            editor = notepad.exe\n\
+            [Group.plan9]\n\
+            var = kept\n\
+        ").unwrap();
+
+        let conf = Config::read_from_file(&path);
+        fs::remove_file(&path).unwrap();
+        conf.unwrap()
+    }
+
+    #[test]
+    fn os_groups_not_listed() {
+        let conf = read_os_overrides();
+
+        let mut groups = conf.groups().collect::<Vec<_>>();
+        groups.sort_unstable();
+        assert_eq!(groups, ["DEFAULT", "Group.plan9"]);
+        assert_eq!(conf["term"], "xterm -e");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn os_override_linux() {
+        assert_eq!(read_os_overrides()["editor"], "xterm -e vim");
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn os_override_macos() {
+        assert_eq!(read_os_overrides()["editor"], "mvim");
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn os_override_windows() {
+        assert_eq!(read_os_overrides()["editor"], "notepad.exe");
+    }
+
+    #[test]
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    fn os_override_other() {
+        assert_eq!(read_os_overrides()["editor"], "vi");
+    }
+
+    #[test]
+    #[cfg(feature = "watch")]
+    fn watch_reloads_on_change() {
+        use std::{fs, sync::mpsc::channel};
+
+        let dir = env::temp_dir().join("upim_core_config_watch");
+        let _ = fs::create_dir(&dir);
+        let path = dir.join("watched.ini");
+        fs::write(&path, "var = old\n").unwrap();
+
+        let (tx, rx) = channel();
+        let watcher = Config::default()
+            .set_default("kept", "default")
+            .watch(&path, Duration::from_millis(100), move |res| {
+                let conf = res.unwrap();
+                let _ = tx.send((conf["var"].clone(), conf["kept"].clone()));
+            })
+            .unwrap();
+
+        fs::write(&path, "var = first\n").unwrap();
+        fs::write(&path, "var = new\n").unwrap();
+        let received = rx.recv_timeout(Duration::from_secs(5));
+        let extra = rx.recv_timeout(Duration::from_millis(500));
+
+        drop(watcher);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(received.unwrap(), ("new".into(), "default".into()));
+        assert!(extra.is_err());
+    }
+
+    #[test]
+    fn display_as_ini() {
+        use std::fs;
+
+        let conf = Config::default()
+            .set_default("name", "value")
+            .set("Group A", "var", "a = b")
+            .set("Group A", "other", "c");
+
+        let path = env::temp_dir().join("upim_core_config_display.ini");
+        conf.write_to_file(&path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        let reread = Config::read_from_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        let text = conf.to_string();
+        assert_eq!(text, written);
+        assert!(text.contains("[DEFAULT]\nname = value\n"));
+        assert!(text.contains("var = a = b\n"));
+        assert_eq!(text.matches("[Group A]").count(), 1);
+
+        let reread = reread.unwrap();
+        assert_eq!(reread.get_default("name").unwrap(), "value");
+        assert_eq!(reread.get("Group A", "var").unwrap(), "a = b");
+    }
+
+    #[test]
+    fn view_single_group() {
+        let conf = Config::read_from_file(Path::new("test/test.ini")).unwrap();
+        let group = conf.group_view("Group A");
+
+        assert_eq!(group.name(), "Group A");
+        assert_eq!(group.get("var2").unwrap(), "value two");
+        assert!(group.contains("var 3"));
+        assert!(! group.contains("var1"));
+        assert_eq!(group.get("var1"), None);
+
+        let mut vars = group.iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<Vec<_>>();
+        vars.sort_unstable();
+        assert_eq!(vars, [("var 3", "value = three"), ("var2", "value two")]);
+
+        assert_eq!(conf.group_view("Nothing").iter().count(), 0);
+    }
+
     #[test]
     fn parse_variables() {
         let conf = Config::read_from_file(Path::new("test/test.ini")).unwrap();
@@ -583,6 +1391,19 @@ mod tests {
         assert_eq!(conf.get("Group A", "var1"), None);
     }
 
+    #[test]
+    fn get_value_or_default() {
+        let conf = Config::default()
+            .set_default("editor", "vim")
+            .set("Collections", "work", "/srv/work");
+
+        assert_eq!(conf.get_default_or("editor", "nano"), "vim");
+        assert_eq!(conf.get_default_or("editor_arg", ""), "");
+        assert_eq!(conf.get_or("Collections", "work", "/tmp"), "/srv/work");
+        assert_eq!(conf.get_or("Collections", "home", "/tmp"), "/tmp");
+        assert_eq!(conf.get_or("DEFAULT", "editor", "nano"), "vim");
+    }
+
     #[test]
     fn get_nonexistent_group_is_none() {
         let conf = Config::read_from_file(Path::new("test/test.ini")).unwrap();
@@ -602,6 +1423,385 @@ mod tests {
         assert_eq!(conf["some-var"], "my-value");
     }
 
+    #[test]
+    fn get_raw_value() {
+        use std::{fs, env};
+
+        let path = env::temp_dir().join("upim_core_config_get_raw.ini");
+        fs::write(
+            &path,
+            "sep =  \r\nname = value  \n[Group A]\nvar=  padded \n"
+        ).unwrap();
+
+        let conf = Config::read_from_file(&path).unwrap()
+            .set("Group B", "set", " x ");
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(conf.get_raw("DEFAULT", "sep").unwrap(), "  ");
+        assert_eq!(conf.get_default("sep").unwrap(), "");
+        assert_eq!(conf.get_raw("DEFAULT", "name").unwrap(), " value  ");
+        assert_eq!(conf.get_default("name").unwrap(), "value");
+        assert_eq!(conf.get_raw("Group A", "var").unwrap(), "  padded ");
+        assert_eq!(conf.get_raw("Group B", "set").unwrap(), " x ");
+        assert!(conf.get_raw("Group A", "nothing").is_none());
+    }
+
+    /// Write the text to a temporary INI file and read it.
+    fn read_temp_config(name: &str, text: &str)
+    -> Result<Config, Vec<FileError>> {
+        use std::fs;
+
+        let path = env::temp_dir().join(name);
+        fs::write(&path, text).unwrap();
+
+        let conf = Config::read_from_file(&path);
+        fs::remove_file(&path).unwrap();
+        conf
+    }
+
+    #[test]
+    fn read_bare_flags() {
+        use std::fs;
+
+        let path = env::temp_dir().join("upim_core_config_flags.ini");
+        fs::write(&path, "debug\nname = value\n[Group A]\nverbose\n").unwrap();
+
+        let strict = Config::read_from_file(&path);
+        let empty = Config::read_from_file_with_flags(&path, Some(""));
+        let truthy = Config::read_from_file_with_flags(&path, Some("true"));
+        fs::remove_file(&path).unwrap();
+
+        let errors = strict.unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], FileError::Parse { line: 1, .. }));
+        assert!(matches!(errors[1], FileError::Parse { line: 4, .. }));
+
+        let empty = empty.unwrap();
+        assert_eq!(empty.get_default("debug").unwrap(), "");
+        assert_eq!(empty.get("Group A", "verbose").unwrap(), "");
+        assert_eq!(empty.get_default("name").unwrap(), "value");
+
+        let truthy = truthy.unwrap();
+        assert_eq!(truthy.get_default("debug").unwrap(), "true");
+        assert_eq!(truthy.get("Group A", "verbose").unwrap(), "true");
+        assert_eq!(truthy.get_raw("Group A", "verbose").unwrap(), "");
+        assert_eq!(truthy.get_default("name").unwrap(), "value");
+    }
+
+    #[test]
+    fn resolve_variable_references() {
+        let conf = read_temp_config(
+            "upim_core_config_refs.ini",
+            "\
+            base = /home/me\n\
+            templates = ${base}/templates\n\
+            later = ${docs}/later\n\
+            docs = ${ base }/docs\n\
+            [Collections]\n\
+            base = /srv\n\
+            Local = ${base}/local\n\
+            Notes = ${templates} and ${docs}\n\
+            "
+        ).unwrap();
+
+        assert_eq!(conf["templates"], "/home/me/templates");
+        assert_eq!(conf["later"], "/home/me/docs/later");
+        assert_eq!(conf[("Collections", "Local")], "/srv/local");
+        assert_eq!(
+            conf[("Collections", "Notes")],
+            "/home/me/templates and /home/me/docs"
+        );
+        assert_eq!(
+            conf.get_raw("DEFAULT", "templates").unwrap(),
+            " ${base}/templates"
+        );
+    }
+
+    #[test]
+    fn escaped_variable_reference() {
+        let conf = read_temp_config(
+            "upim_core_config_ref_escape.ini",
+            "\
+            file = note.txt\n\
+            editor = sh -c 'vim $${FILE}'\n\
+            both = $${file} is ${file}\n\
+            "
+        ).unwrap();
+
+        assert_eq!(conf["editor"], "sh -c 'vim ${FILE}'");
+        assert_eq!(conf["both"], "${file} is note.txt");
+    }
+
+    #[test]
+    fn circular_variable_reference_is_err() {
+        let errs = read_temp_config(
+            "upim_core_config_ref_cycle.ini",
+            "a = ${b}\nb = x${c}\nc = ${a}\nd = fine\n"
+        ).unwrap_err();
+
+        assert_eq!(errs.len(), 3);
+        for e in errs {
+            match e {
+                FileError::Parse { msg, line, .. } => {
+                    assert!(msg.contains("Circular reference"));
+                    assert!(line <= 3);
+                },
+                _ => panic!("Expected a parse error"),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_variable_reference_is_err() {
+        let errs = read_temp_config(
+            "upim_core_config_ref_unknown.ini",
+            "a = 1\nb = ${nothing}\nc = ${a\n"
+        ).unwrap_err();
+
+        assert_eq!(errs.len(), 2);
+        assert!(errs.iter().any(|e| matches!(e,
+            FileError::Parse { msg, line: 2, .. } if msg.contains("nothing")
+        )));
+        assert!(errs.iter().any(|e| matches!(e,
+            FileError::Parse { msg, line: 3, .. } if msg.contains("brace")
+        )));
+    }
+
+    #[test]
+    fn find_configuration_in_each_dir() {
+        use std::fs;
+
+        let base = env::temp_dir().join("upim_core_find_all_app_conf");
+        let _ = fs::remove_dir_all(&base);
+
+        let system = base.join("system");
+        let user = base.join("user");
+        let empty = base.join("empty");
+
+        for dir in [&system, &user, &empty] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(system.join("app.conf"), "a = system\nb = system\n")
+            .unwrap();
+        fs::write(user.join("app.conf"), "b = user\n").unwrap();
+
+        let paths = find_configuration_in(
+            vec![system.clone(), empty, user.clone()],
+            "app"
+        );
+        let conf = read_configuration_files(&paths);
+
+        fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(paths, [system.join("app.conf"), user.join("app.conf")]);
+
+        let conf = conf.unwrap();
+        assert_eq!(conf["a"], "system");
+        assert_eq!(conf["b"], "user");
+    }
+
+    #[test]
+    fn read_upim_configuration_from_dirs() {
+        use std::fs;
+
+        let base = env::temp_dir().join("upim_core_read_upim_conf");
+        let _ = fs::remove_dir_all(&base);
+
+        let system = base.join("system");
+        let user = base.join("user");
+        let local = base.join(".upim.conf");
+
+        for dir in [&system, &user] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(
+            system.join("upim.conf"),
+            "a = system\nb = system\nc = system\n[Collections]\nx = /x\n"
+        ).unwrap();
+        fs::write(user.join("upim.conf"), "b = user\nc = user\n").unwrap();
+        fs::write(&local, "c = local\n").unwrap();
+
+        let conf = read_upim_configuration_from(
+            &[system.clone(), user.clone()],
+            Some(&local)
+        );
+        let without_local = read_upim_configuration_from(
+            &[system, user, base.join("missing")],
+            Some(&base.join("missing.conf"))
+        );
+
+        fs::remove_dir_all(&base).unwrap();
+
+        let conf = conf.unwrap();
+        assert_eq!(conf["a"], "system");
+        assert_eq!(conf["b"], "user");
+        assert_eq!(conf["c"], "local");
+        assert_eq!(conf[("Collections", "x")], "/x");
+        assert_eq!(conf.sources().len(), 3);
+        assert!(! conf.is_empty_source());
+
+        assert_eq!(without_local.unwrap()["c"], "user");
+    }
+
+    #[test]
+    fn read_upim_configuration_without_files() {
+        let base = env::temp_dir().join("upim_core_read_no_conf");
+
+        let conf = read_upim_configuration_from(
+            &[base.join("system"), base.join("user")],
+            Some(&base.join(".upim.conf"))
+        ).unwrap();
+
+        assert!(conf.is_empty_source());
+        assert!(conf.sources().is_empty());
+        assert!(conf.get_default("a").is_none());
+    }
+
+    #[test]
+    fn empty_file_is_a_source() {
+        use std::fs;
+
+        let path = env::temp_dir().join("upim_core_empty_source.conf");
+        fs::write(&path, "").unwrap();
+
+        let conf = Config::read_from_file(&path);
+        let _ = fs::remove_file(&path);
+
+        let conf = Config::default().set_default("a", "b").merge_with(
+            conf.unwrap()
+        );
+        assert_eq!(conf.sources(), [path]);
+        assert!(! conf.is_empty_source());
+        assert!(Config::default().set_default("a", "b").is_empty_source());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn warn_on_xdg_and_home_configuration() {
+        use std::fs;
+
+        let base = env::temp_dir().join("upim_core_config_xdg_home");
+        let xdg = base.join("xdg");
+        let home = base.join("home");
+        let _ = fs::remove_dir_all(&base);
+
+        fs::create_dir_all(xdg.join("upim")).unwrap();
+        fs::create_dir_all(home.join(".config/upim")).unwrap();
+        fs::write(xdg.join("upim/upim.conf"), "a = 1\n").unwrap();
+
+        let (xdg_os, home_os) = (Some(xdg.as_os_str()), Some(home.as_os_str()));
+        let only_xdg = unixy_user_dir(xdg_os, home_os);
+
+        fs::write(home.join(".config/upim/upim.conf"), "a = 2\n").unwrap();
+        let both = unixy_user_dir(xdg_os, home_os);
+        let only_home = unixy_user_dir(None, home_os);
+        let same = unixy_user_dir(
+            Some(home.join(".config").as_os_str()),
+            home_os
+        );
+        fs::remove_dir_all(&base).unwrap();
+
+        assert_eq!(only_xdg, (Some(xdg.join("upim")), None));
+        assert_eq!(both.0, Some(xdg.join("upim")));
+
+        let warning = both.1.unwrap();
+        assert_eq!(warning, ConfigWarning::AmbiguousUserDirectory {
+            used: xdg.join("upim"),
+            ignored: home.join(".config/upim"),
+        });
+        assert!(warning.to_string().contains("only the first is read"));
+
+        assert_eq!(only_home, (Some(home.join(".config/upim")), None));
+        assert_eq!(same.1, None);
+    }
+
+    #[test]
+    fn config_dir_env_var_replaces_discovered_dirs() {
+        use std::fs;
+
+        let dir = env::temp_dir().join("upim_core_config_dir_var");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("upim.conf"), "a = 1\n").unwrap();
+        fs::write(dir.join("app.conf"), "b = 2\n").unwrap();
+
+        // No other test reads the process environment's configuration.
+        let previous = env::var_os(CONFIG_DIR_VAR);
+        env::set_var(CONFIG_DIR_VAR, &dir);
+
+        let dirs = get_upim_configuration_dirs();
+        let apps = find_all_application_configuration("app");
+        let conf = read_upim_configuration();
+
+        env::set_var(CONFIG_DIR_VAR, dir.join("missing"));
+        let missing = get_upim_configuration_dirs();
+
+        match previous {
+            Some(v) => env::set_var(CONFIG_DIR_VAR, v),
+            None => env::remove_var(CONFIG_DIR_VAR),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(dirs, Some(vec![dir.clone()]));
+        assert_eq!(apps, [dir.join("app.conf")]);
+        assert_eq!(conf.unwrap()["a"], "1");
+        assert!(missing.is_none());
+    }
+
+    #[test]
+    fn get_bytes_value() {
+        let conf = Config::default()
+            .set_default("small", "512")
+            .set_default("medium", "10MB")
+            .set_default("large", "1 gb")
+            .set_default("bad", "10XB");
+
+        assert_eq!(conf.get_bytes("DEFAULT", "small"), Some(Ok(512)));
+        assert_eq!(
+            conf.get_bytes("DEFAULT", "medium"),
+            Some(Ok(10 * 1024 * 1024))
+        );
+        assert_eq!(
+            conf.get_bytes("DEFAULT", "large"),
+            Some(Ok(1024 * 1024 * 1024))
+        );
+        assert!(conf.get_bytes("DEFAULT", "bad").unwrap().is_err());
+        assert!(conf.get_bytes("DEFAULT", "missing").is_none());
+    }
+
+    #[test]
+    fn get_duration_value() {
+        let conf = Config::default()
+            .set_default("seconds", "30s")
+            .set_default("minutes", "5m")
+            .set_default("hours", "2H")
+            .set_default("bad", "5d")
+            .set_default("not-a-number", "ms");
+
+        assert_eq!(
+            conf.get_duration("DEFAULT", "seconds"),
+            Some(Ok(Duration::from_secs(30)))
+        );
+        assert_eq!(
+            conf.get_duration("DEFAULT", "minutes"),
+            Some(Ok(Duration::from_secs(5 * 60)))
+        );
+        assert_eq!(
+            conf.get_duration("DEFAULT", "hours"),
+            Some(Ok(Duration::from_secs(2 * 60 * 60)))
+        );
+        assert!(conf.get_duration("DEFAULT", "bad").unwrap().is_err());
+        assert!(conf.get_duration("DEFAULT", "not-a-number").unwrap().is_err());
+    }
+
+    #[test]
+    fn get_value_with_unit_overflow_is_err() {
+        let conf = Config::default()
+            .set_default("huge", "18446744073709551615GB");
+
+        assert!(conf.get_bytes("DEFAULT", "huge").unwrap().is_err());
+    }
+
     #[test]
     fn collect_all_parse_errors() {
         let conf = Config::read_from_file(Path::new("test/invalid.ini"));
@@ -609,7 +1809,8 @@ mod tests {
         let mut errs = errs.iter();
 
         match errs.next() {
-            Some(FileError::Parse { file, msg, data, line }) => {
+            Some(FileError::Parse { file, kind, msg, data, line }) => {
+                assert_eq!(*kind, ParseKind::InvalidLine);
                 assert!(*file == *PathBuf::from("test/invalid.ini"));
                 assert!(msg.contains("variable assignment"));
                 assert_eq!(data, "some variable");
@@ -619,7 +1820,8 @@ mod tests {
         }
 
         match errs.next() {
-            Some(FileError::Parse { file, msg, data, line }) => {
+            Some(FileError::Parse { file, kind, msg, data, line }) => {
+                assert_eq!(*kind, ParseKind::EmptyKey);
                 assert!(*file == *PathBuf::from("test/invalid.ini"));
                 assert!(msg.contains("variable name"));
                 assert_eq!(data, "= some value");
@@ -629,7 +1831,8 @@ mod tests {
         }
 
         match errs.next() {
-            Some(FileError::Parse { file, msg, data, line }) => {
+            Some(FileError::Parse { file, kind, msg, data, line }) => {
+                assert_eq!(*kind, ParseKind::MissingBracket);
                 assert!(*file == *PathBuf::from("test/invalid.ini"));
                 assert!(msg.contains("closing bracket"));
                 assert_eq!(data, "[Bad Group");
@@ -639,7 +1842,8 @@ mod tests {
         }
 
         match errs.next() {
-            Some(FileError::Parse { file, msg, data, line }) => {
+            Some(FileError::Parse { file, kind, msg, data, line }) => {
+                assert_eq!(*kind, ParseKind::InvalidLine);
                 assert!(*file == *PathBuf::from("test/invalid.ini"));
                 assert!(msg.contains("variable assignment"));
                 assert_eq!(data, "# Bad comment");
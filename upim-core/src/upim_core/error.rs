@@ -13,7 +13,39 @@ use std::{
 pub enum FileError {
     #[allow(clippy::upper_case_acronyms)]
     IO((PathBuf, io::ErrorKind)),
-    Parse { file: PathBuf, msg: String, data: String, line: u32 },
+    Parse {
+        file: PathBuf,
+        kind: ParseKind,
+        msg: String,
+        data: String,
+        line: u32,
+    },
+}
+
+/// The kind of problem described by a [FileError::Parse], so that programs
+/// need not match on its message.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseKind {
+    /// A bracketed group name or attribute is not closed.
+    MissingBracket,
+    /// A tag is empty or is missing its prefix.
+    InvalidTag,
+    /// A variable assignment has no variable name.
+    EmptyKey,
+    /// An attribute key is set more than once.
+    DuplicateKey,
+    /// A tag appears more than once.
+    DuplicateTag,
+    /// A bracketed attribute is not a valid key-value pair.
+    InvalidAttribute,
+    /// A line is not any kind of data that may appear in the file.
+    InvalidLine,
+    /// A `${variable}` reference cannot be resolved.
+    InvalidReference,
+    /// The file is not valid UTF-8.
+    InvalidEncoding,
+    /// The file is valid but its data cannot be used.
+    Other,
 }
 
 impl fmt::Display for FileError {
@@ -21,7 +53,7 @@ impl fmt::Display for FileError {
         match *self {
             FileError::IO((ref file, ref e)) =>
                 write!(f, "{:?} in file {}", e, file.to_string_lossy()),
-            FileError::Parse { ref file, ref msg, ref data, ref line } =>
+            FileError::Parse { ref file, ref msg, ref data, ref line, .. } =>
                 write!(f, "{} at line {} in {}:\n\t{}"
                     , msg, line, file.to_string_lossy(), data),
         }
@@ -35,3 +67,21 @@ impl From<io::Error> for FileError {
         FileError::IO((PathBuf::default(), err.kind()))
     }
 }
+
+/// Error for configuration or attribute values that cannot be interpreted as
+/// the requested type.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ValueError {
+    /// The value that could not be interpreted.
+    pub value: String,
+    /// A description of the problem.
+    pub msg: String,
+}
+
+impl fmt::Display for ValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid value '{}': {}", self.value, self.msg)
+    }
+}
+
+impl Error for ValueError {}
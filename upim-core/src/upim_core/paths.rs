@@ -30,6 +30,70 @@ pub fn home_dir() -> Option<PathBuf> {
     home::home_dir()
 }
 
+/// Return a path to a file that does not yet exist in the same directory as
+/// `target`.
+///
+/// Writing to this path then renaming it to `target` replaces `target`
+/// atomically, since both are on the same filesystem. The file name begins
+/// with `target`'s name, prefixed by a '.'.
+pub fn temp_sibling(target: &Path) -> PathBuf {
+    let dir = match target.parent() {
+        Some(p) if p != Path::new("") => p,
+        _ => Path::new("."),
+    };
+    let name = target.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    unique_path(dir, &format!(".{}", name))
+}
+
+/// Return a path to a file that does not yet exist in the given directory.
+pub fn temp_in_dir(dir: &Path) -> PathBuf {
+    unique_path(dir, "upim")
+}
+
+/// Generate a path in `dir` beginning with `prefix` that does not exist.
+///
+/// Names are unique within the process, and include the process ID and time
+/// to avoid collisions with other processes.
+fn unique_path(dir: &Path, prefix: &str) -> PathBuf {
+    use std::{
+        process,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    loop {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+
+        let path = dir.join(format!(
+            "{}.{}-{}-{}.tmp",
+            prefix,
+            process::id(),
+            nanos,
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        if ! path.exists() { break path; }
+    }
+}
+
+/// Check whether the path names a hidden file or directory: one whose name
+/// begins with a '.'.
+///
+/// Code walking a collection skips hidden entries, which include the files
+/// from [temp_sibling] and upim-edit's lock files.
+pub fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .map(|n| n.to_string_lossy().starts_with('.'))
+        .unwrap_or(false)
+}
+
 /// Expand the tilde in a path to the user's home directory.
 pub fn expand_tilde(path: &Path) -> Option<PathBuf> {
     if path.starts_with("~") {
@@ -75,6 +139,49 @@ mod tests {
         );
     }
 
+    #[test]
+    fn hidden_paths() {
+        assert!(is_hidden(Path::new("dir/.note.txt.lock")));
+        assert!(is_hidden(&temp_sibling(Path::new("dir/note.txt"))));
+        assert!(! is_hidden(Path::new(".dir/note.txt")));
+        assert!(! is_hidden(Path::new("..")));
+    }
+
+    #[test]
+    fn temp_sibling_is_in_target_dir() {
+        use std::{env, fs};
+
+        let target = env::temp_dir().join("upim_core_temp_sibling.txt");
+        let first = temp_sibling(&target);
+
+        fs::write(&first, "").unwrap();
+        let second = temp_sibling(&target);
+        fs::remove_file(&first).unwrap();
+
+        assert_eq!(first.parent(), target.parent());
+        assert!(first.file_name().unwrap().to_string_lossy()
+            .starts_with(".upim_core_temp_sibling.txt."));
+        assert_ne!(first, second);
+        assert!(! second.exists());
+
+        assert_eq!(
+            temp_sibling(Path::new("note.txt")).parent(),
+            Some(Path::new("."))
+        );
+    }
+
+    #[test]
+    fn temp_in_dir_is_in_dir() {
+        use std::env;
+
+        let dir = env::temp_dir();
+        let path = temp_in_dir(&dir);
+
+        assert_eq!(path.parent(), Some(dir.as_path()));
+        assert!(! path.exists());
+        assert_ne!(path, temp_in_dir(&dir));
+    }
+
     #[test]
     fn expand_tilde_ignored_in_path() {
         assert_eq!(
@@ -42,3 +42,47 @@ pub trait Uniq<I, T>: Iterator {
 }
 
 impl<I, T> Uniq<I, T> for I where I: Iterator {}
+
+pub struct UniqByKeyIterator<I, F, K> {
+    source: I,
+    key: F,
+    seen: BTreeSet<K>,
+}
+
+impl<I, F, K> Iterator for UniqByKeyIterator<I, F, K>
+    where I: Iterator,
+          F: FnMut(&I::Item) -> Option<K>,
+          K: Ord,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let seen = &mut self.seen;
+        let key = &mut self.key;
+
+        self.source.find(|i| match key(i) {
+            Some(k) => seen.insert(k),
+            None => true,
+        })
+    }
+}
+
+/// Filter out elements whose key has already been seen, keeping the first.
+///
+/// Elements for which the key function returns `None` have no key and are
+/// never considered duplicates.
+pub trait UniqByKey: Iterator {
+    fn uniq_by_key<F, K>(self, key: F) -> UniqByKeyIterator<Self, F, K>
+        where Self: Sized,
+              F: FnMut(&Self::Item) -> Option<K>,
+              K: Ord,
+    {
+        UniqByKeyIterator {
+            source: self,
+            key,
+            seen: BTreeSet::new(),
+        }
+    }
+}
+
+impl<I> UniqByKey for I where I: Iterator {}
@@ -42,68 +42,288 @@
 #![feature(with_options)]
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs::File,
     io::Write,
     ops::{Index, IndexMut},
-    path::Path,
+    path::{Path, PathBuf},
     str::FromStr,
 };
 
-use upim_core::error::FileError;
+use indexmap::IndexMap;
+use regex::Regex;
+use upim_core::error::{FileError, ParseKind};
+
+
+/// The character that begins a tag unless a note is configured otherwise.
+pub const DEFAULT_TAG_PREFIX: char = '@';
+
+/// Summary statistics of a [Note], returned by [Note::stats].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct NoteStats {
+    /// The number of tags on the note.
+    pub tags: usize,
+    /// The number of key-value attributes on the note.
+    pub attributes: usize,
+    /// The length of the content in bytes.
+    pub content_bytes: usize,
+    /// The number of lines in the content.
+    pub content_lines: usize,
+}
+
+/// A difference between two notes, returned by [Note::diff].
+///
+/// When displayed, each change is written as lines of a diff: header lines
+/// and content lines that were added begin with '+', and those that were
+/// removed begin with '-'.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NoteChange {
+    TagAdded(String),
+    TagRemoved(String),
+    /// The key and value of the new attribute.
+    AttributeAdded(String, String),
+    /// The key and value of the removed attribute.
+    AttributeRemoved(String, String),
+    /// The key, the old value, and the new value.
+    AttributeChanged(String, String, String),
+    /// The lines of content that differ; lines common to the beginning and end
+    /// of both notes are omitted.
+    ContentChanged { removed: Vec<String>, added: Vec<String> },
+}
+
+impl std::fmt::Display for NoteChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::TagAdded(tag) => write!(f, "+{}", tag),
+            Self::TagRemoved(tag) => write!(f, "-{}", tag),
+            Self::AttributeAdded(k, v) => write!(f, "+[{}: {}]", k, v),
+            Self::AttributeRemoved(k, v) => write!(f, "-[{}: {}]", k, v),
+            Self::AttributeChanged(k, old, new) =>
+                write!(f, "-[{}: {}]\n+[{}: {}]", k, old, k, new),
+            Self::ContentChanged { removed, added } => {
+                let lines = removed.iter().map(|l| ('-', l))
+                    .chain(added.iter().map(|l| ('+', l)));
+
+                for (i, (c, line)) in lines.enumerate() {
+                    if i > 0 { writeln!(f)?; }
+                    write!(f, "{}{}", c, line)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+/// A line of a note's content that matched a search, returned by
+/// [Note::search_with_context].
+///
+/// Lines are stored without their line endings.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContentMatch {
+    /// The line number of the match within the content; the first line is 1.
+    pub line: usize,
+    /// The matching line.
+    pub text: String,
+    /// The lines immediately before the match, in order.
+    pub before: Vec<String>,
+    /// The lines immediately after the match, in order.
+    pub after: Vec<String>,
+}
+
+/// The kind of style problem reported by [Note::lint].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LintKind {
+    /// The tags are spread across more than one line.
+    UngroupedTags,
+    /// An attribute's key sorts before the key on the line above it.
+    UnsortedAttributes,
+    /// An attribute's value ends with whitespace.
+    TrailingWhitespace,
+    /// More than one blank line separates the header and content.
+    ExtraBlankLines,
+}
+
+/// A style problem in a note's text, returned by [Note::lint].
+///
+/// Unlike the errors returned by [Note::header_errors], a warning does not
+/// prevent the note from being read.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LintWarning {
+    pub kind: LintKind,
+    /// The line number of the problem; the first line is 1.
+    pub line: u32,
+    pub message: String,
+}
+
+impl std::fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at line {}", self.message, self.line)
+    }
+}
+
+/// The memory-mapped content of a note, returned by
+/// [Note::open_content_mmap].
+///
+/// The content dereferences to a `str`. If the file could not be mapped, the
+/// content is read into memory instead.
+#[cfg(feature = "mmap")]
+pub struct MmapContent {
+    inner: MmapInner,
+}
+
+#[cfg(feature = "mmap")]
+enum MmapInner {
+    Mapped(memmap2::Mmap),
+    Read(String),
+}
+
+#[cfg(feature = "mmap")]
+impl MmapContent {
+    /// Get the content as a string slice.
+    pub fn as_str(&self) -> &str {
+        match &self.inner {
+            // Safety: the mapping was verified to be UTF-8 when it was
+            // created.
+            MmapInner::Mapped(map) => unsafe {
+                std::str::from_utf8_unchecked(map)
+            },
+            MmapInner::Read(s) => s,
+        }
+    }
+
+    /// Check whether the content is memory-mapped rather than read into
+    /// memory.
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.inner, MmapInner::Mapped(_))
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl std::ops::Deref for MmapContent {
+    type Target = str;
 
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+/// Errors that can occur while modifying a [Note].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NoteError {
+    /// The content is not valid UTF-8; the first invalid sequence begins
+    /// `offset` bytes into the data.
+    InvalidUtf8 { offset: usize },
+    /// The attribute could not be written and read back; see
+    /// [Note::is_valid_attribute_key] and [Note::is_valid_attribute_value].
+    InvalidAttribute { key: String, value: String },
+    /// The character cannot begin a tag; see [Note::with_tag_prefix].
+    InvalidTagPrefix(char),
+}
+
+impl std::fmt::Display for NoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::InvalidUtf8 { offset } => write!(f,
+                "The content is not valid UTF-8: invalid byte sequence at \
+                offset {}",
+                offset
+            ),
+            Self::InvalidAttribute { key, value } =>
+                write!(f, "Invalid attribute: [{}: {}]", key, value),
+            Self::InvalidTagPrefix(prefix) =>
+                write!(f, "Invalid tag prefix: {:?}", prefix),
+        }
+    }
+}
+
+impl std::error::Error for NoteError {}
 
 /// uPIM's note type.
 ///
 /// No interpretation of the metadata is performed. Duplicate keys in the
 /// attribute list is allowed; applications that seek to disallow duplicates
-/// must validate the keys.
+/// must validate the keys. Lookups by key use the first value of a duplicated
+/// key; see [Note::add_attribute] and [Note::get_attribute_all].
 ///
 /// A tag must begin with the '@' character, must have at least one character
 /// following the '@' symbol, and ends with the following space or newline; no
 /// other name requirements exist. Duplicate tags are allowed but are only
-/// stored once.
+/// stored once. The prefix character can be changed via
+/// [Note::with_tag_prefix] or [Note::from_str_with_prefix].
 ///
 /// Key-value attributes must not have an open or closing square brace within
 /// its content ('[', ']'); keys cannot have a colon character (':'); whether
 /// values may contain a colon is application-specific.
 ///
 /// The content must be valid UTF-8.
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq)]
 pub struct Note {
+    /// The character that begins each tag.
+    tag_prefix: char,
     /// Arbitrary data tags on a note.
     tags: Vec<String>,
-    /// Key-value attributes on a note.
-    map: HashMap<String, String>,
+    /// Whether each tag was read on the same line as the tag before it, so
+    /// the note is written with its tags grouped as they were read. A tag
+    /// without an entry begins its own line.
+    tag_joined: Vec<bool>,
+    /// Key-value attributes on a note, in the order each key was first added;
+    /// a duplicated key maps to its first value.
+    map: IndexMap<String, String>,
+    /// The values after the first of each duplicated attribute key.
+    extra: HashMap<String, Vec<String>>,
     // Large notes are possible; we may not always want to store the full
     // document in memory -- we could use a wrapper type that sets some maximum
     // buffer, backed by a file.
     content: String,
 }
 
+impl Default for Note {
+    fn default() -> Self {
+        Self {
+            tag_prefix: DEFAULT_TAG_PREFIX,
+            tags: vec![],
+            tag_joined: vec![],
+            map: IndexMap::new(),
+            extra: HashMap::new(),
+            content: String::new(),
+        }
+    }
+}
+
+// The grouping of tags on lines is formatting, not data; notes that differ
+// only in their tag lines are equal.
+impl PartialEq for Note {
+    fn eq(&self, other: &Self) -> bool {
+        self.tag_prefix == other.tag_prefix
+            && self.tags == other.tags
+            && self.map == other.map
+            && self.extra == other.extra
+            && self.content == other.content
+    }
+}
+
 impl FromStr for Note {
     type Err = FileError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut note = Self::default();
-        let mut lines = s.split_inclusive('\n');
-        let mut cnt = 0;
-
-        // Don't want to fight the borrow checker over ownership of `lines`.
-        #[allow(clippy::explicit_counter_loop)]
-        for line in &mut lines {
-            cnt += 1;
-            if line == "\n" { break; }
+        Self::from_str_with_prefix(s, DEFAULT_TAG_PREFIX)
+    }
+}
 
-            match Self::read_metadata_line(Path::new(""), line, cnt)? {
-                Metadata::Tag(mut vs) => { note.tags.append(&mut vs); },
-                Metadata::KV(k, v) => { note.map.insert(k, v); },
-            }
+/// Write the note as it is stored in a file; see [Note::write_to_file].
+impl std::fmt::Display for Note {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, tag) in self.tags.iter().enumerate() {
+            let end = if self.is_tag_joined(i + 1) { ' ' } else { '\n' };
+            write!(f, "{}{}", tag, end)?;
         }
 
-        note.content = lines.collect();
+        for (k, v) in self.attributes() {
+            writeln!(f, "[{}: {}]", k, v)?;
+        }
 
-        Ok(note)
+        writeln!(f)?;
+        f.write_str(&self.content)
     }
 }
 
@@ -127,113 +347,578 @@ impl IndexMut<&str> for Note {
 }
 
 impl Note {
-    pub fn new(tags: &[String], attrs: HashMap<String, String>, text: &str)
-    -> Self {
+    /// Create a note with the given tags, attributes, and content.
+    ///
+    /// The attributes may be any collection of key-value pairs, such as a
+    /// `HashMap` or a `Vec` of tuples; later duplicate keys replace earlier
+    /// ones.
+    pub fn new<A>(tags: &[String], attrs: A, text: &str) -> Self
+        where A: IntoIterator<Item = (String, String)>,
+    {
         Self {
+            tag_prefix: DEFAULT_TAG_PREFIX,
             tags: tags.into(),
-            map: attrs,
+            tag_joined: vec![],
+            map: attrs.into_iter().collect(),
+            extra: HashMap::new(),
+            content: text.into(),
+        }
+    }
+
+    /// Create a note with the given attributes and no tags or content.
+    ///
+    /// ```
+    /// # use upim_note::Note;
+    /// let note = Note::with_attributes(vec![
+    ///     ("Name".to_string(), "Favorite Person".to_string()),
+    /// ]);
+    /// assert_eq!(note["Name"], "Favorite Person");
+    /// ```
+    pub fn with_attributes<I>(attrs: I) -> Self
+        where I: IntoIterator<Item = (String, String)>,
+    {
+        Self::new(&[], attrs, "")
+    }
+
+    /// Create a note with an empty header, treating all of `text` as its
+    /// content.
+    ///
+    /// Unlike parsing, no part of `text` is read as metadata; the note is
+    /// serialized with a leading blank line.
+    ///
+    /// ```
+    /// # use upim_note::Note;
+    /// let note = Note::content_only("@not-a-tag\n");
+    /// assert!(note.tags().is_empty());
+    /// assert_eq!(note.content(), "@not-a-tag\n");
+    /// ```
+    pub fn content_only(text: &str) -> Self {
+        Self {
             content: text.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Check whether the note has no tags, attributes, or content, as with
+    /// [Note::default].
+    ///
+    /// An empty note is written as a single blank line, which is read back as
+    /// an empty note.
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.map.is_empty() && self.content.is_empty()
+    }
+
+    /// Use the given character to begin the note's tags.
+    ///
+    /// The prefix is used when adding tags via [Note::insert_tag]; tags already
+    /// present on the note are re-prefixed so the note serializes
+    /// consistently.
+    ///
+    /// Returns [NoteError::InvalidTagPrefix] if `prefix` is whitespace or '[',
+    /// since those would be ambiguous within a note header.
+    pub fn with_tag_prefix(mut self, prefix: char) -> Result<Self, NoteError> {
+        if prefix.is_whitespace() || prefix == '[' {
+            return Err(NoteError::InvalidTagPrefix(prefix));
+        }
+
+        let old = self.tag_prefix;
+        for tag in self.tags.iter_mut() {
+            if let Some(name) = tag.strip_prefix(old) {
+                *tag = format!("{}{}", prefix, name);
+            }
+        }
+
+        self.tag_prefix = prefix;
+        Ok(self)
+    }
+
+    /// Get the character that begins the note's tags.
+    pub fn tag_prefix(&self) -> char {
+        self.tag_prefix
+    }
+
+    /// Parse a `Note` whose tags begin with the given prefix character rather
+    /// than '@'.
+    ///
+    /// See [Note::with_tag_prefix] for the restrictions on the prefix.
+    pub fn from_str_with_prefix(s: &str, prefix: char)
+    -> Result<Self, FileError> {
+        Self::parse_str(s, Path::new(""), prefix, false)
+    }
+
+    /// Parse a `Note` read from the named source.
+    ///
+    /// The name is used only as the `file` of any [FileError::Parse] -- for
+    /// example, the path of a file whose text the caller read itself. Use
+    /// [Note::from_str] when there is no name.
+    pub fn from_str_named(s: &str, name: &Path) -> Result<Self, FileError> {
+        Self::parse_str(s, name, DEFAULT_TAG_PREFIX, false)
+    }
+
+    /// Parse a `Note`, rejecting a header that lists any tag more than once.
+    ///
+    /// Other parsers keep only the first instance of a repeated tag. The
+    /// error's message lists every repeated tag, and its line is that of the
+    /// first repetition.
+    ///
+    /// ```
+    /// # use std::str::FromStr;
+    /// # use upim_note::Note;
+    /// let text = "@a @b\n@a\n";
+    ///
+    /// assert!(Note::from_str_strict(text).is_err());
+    /// assert_eq!(Note::from_str(text).unwrap().tags(), ["@a", "@b"]);
+    /// ```
+    pub fn from_str_strict(s: &str) -> Result<Self, FileError> {
+        Self::parse_str(s, Path::new(""), DEFAULT_TAG_PREFIX, true)
+    }
+
+    fn parse_str(s: &str, name: &Path, prefix: char, strict: bool)
+    -> Result<Self, FileError> {
+        let mut note = Self::empty_with_prefix(name, prefix)?;
+
+        // A leading blank line is an empty header; skip the metadata parser.
+        if let Some(content) = s.strip_prefix('\n') {
+            note.content = content.into();
+            return Ok(note);
+        }
+
+        let mut reader = s.as_bytes();
+        let mut duplicates = vec![];
+        let mut first_line = 0;
+
+        Self::read_header_lines(&mut reader, name, prefix, |meta, _, cnt| {
+            let mut dups = note.push_metadata(meta?);
+
+            if first_line == 0 && ! dups.is_empty() {
+                first_line = cnt;
+            }
+            duplicates.append(&mut dups);
+            Ok(())
+        })?;
+
+        if strict && ! duplicates.is_empty() {
+            return Err(FileError::Parse {
+                file: name.to_owned(),
+                kind: ParseKind::DuplicateTag,
+                msg: format!("Duplicate tags: {}", duplicates.join(", ")),
+                data: duplicates.join(" "),
+                line: first_line,
+            });
         }
+
+        // The header ends on a line boundary, so the rest is valid UTF-8.
+        note.content = s[s.len() - reader.len()..].into();
+
+        Ok(note)
     }
 
     /// Validate the header of a note at the given path.
     pub fn validate_header(path: &Path) -> Result<(), FileError> {
-        use std::io::{prelude::*, BufReader};
+        Self::validate_header_with(path, false)
+    }
 
-        let mut reader = BufReader::new(File::open(path)?);
-        let mut line = String::new();
-        let mut cnt = 0;
+    /// Validate the header of a note at the given path.
+    ///
+    /// If `strict` is true, an attribute key that appears more than once in
+    /// the header is an error.
+    pub fn validate_header_with(path: &Path, strict: bool)
+    -> Result<(), FileError> {
+        Self::validate_header_with_prefix(path, DEFAULT_TAG_PREFIX, strict)
+    }
 
-        while reader.read_line(&mut line)? > 1 {
-            cnt += 1;
-            Self::read_metadata_line(path, &line, cnt)?;
-            line.clear();
+    /// Validate the header of a note at the given path, whose tags begin with
+    /// the given prefix character rather than '@'.
+    ///
+    /// `strict` is the same as for [Note::validate_header_with].
+    pub fn validate_header_with_prefix(path: &Path, prefix: char, strict: bool)
+    -> Result<(), FileError> {
+        let errors = Self::header_errors_with_prefix(path, prefix, strict)?;
+
+        match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(()),
         }
+    }
 
-        Ok(())
+    /// Validate the header of a note at the given path, collecting every
+    /// error rather than stopping at the first.
+    ///
+    /// `strict` is the same as for [Note::validate_header_with]. The parse
+    /// errors are returned in line order; an error is returned only if the
+    /// file cannot be read.
+    pub fn header_errors(path: &Path, strict: bool)
+    -> Result<Vec<FileError>, FileError> {
+        Self::header_errors_with_prefix(path, DEFAULT_TAG_PREFIX, strict)
+    }
+
+    /// Collect the errors in the header of a note at the given path, whose
+    /// tags begin with the given prefix character rather than '@'.
+    ///
+    /// See [Note::header_errors]; an invalid prefix is also returned as an
+    /// error.
+    pub fn header_errors_with_prefix(path: &Path, prefix: char, strict: bool)
+    -> Result<Vec<FileError>, FileError> {
+        use std::io::BufReader;
+
+        Self::empty_with_prefix(path, prefix)?;
+
+        let file = File::open(path).map_err(io_error(path))?;
+        let mut reader = BufReader::new(file);
+        let mut keys = HashMap::new();
+        let mut errors = vec![];
+
+        Self::read_header_lines(&mut reader, path, prefix,
+            |meta, line, cnt| {
+                match meta {
+                    Err(e) => errors.push(e),
+                    Ok(Metadata::KV(key, _)) if strict => {
+                        if let Some(first) = keys.insert(key.clone(), cnt) {
+                            errors.push(FileError::Parse {
+                                file: path.to_owned(),
+                                kind: ParseKind::DuplicateKey,
+                                msg: format!(
+                                    "Duplicate attribute key '{}' \
+                                    (first on line {})",
+                                    key,
+                                    first
+                                ),
+                                data: line.trim_end().into(),
+                                line: cnt,
+                            });
+                        }
+                    },
+                    Ok(_) => {},
+                }
+                Ok(())
+            }
+        )?;
+
+        Ok(errors)
+    }
+
+    /// Check the text of a note for style problems.
+    ///
+    /// Style is a property of the text rather than of a parsed note -- values
+    /// are trimmed and blank lines discarded when a note is read -- so the
+    /// text is checked directly. The warnings are returned in line order.
+    /// Lines that cannot be parsed are skipped; use [Note::header_errors] to
+    /// find them.
+    pub fn lint(text: &str) -> Vec<LintWarning> {
+        let mut warnings = vec![];
+        let mut reader = text.as_bytes();
+        let mut tag_line = None;
+        let mut last_key: Option<String> = None;
+
+        let res = Self::read_header_lines(&mut reader, Path::new(""),
+            DEFAULT_TAG_PREFIX, |meta, line, cnt| {
+                match meta {
+                    Ok(Metadata::Tag(_)) => match tag_line {
+                        Some(first) => warnings.push(LintWarning {
+                            kind: LintKind::UngroupedTags,
+                            line: cnt,
+                            message: format!(
+                                "Tags should be on one line (first on line {})",
+                                first
+                            ),
+                        }),
+                        None => tag_line = Some(cnt),
+                    },
+                    Ok(Metadata::KV(key, _)) => {
+                        let value = line.trim_end()
+                            .trim_end_matches(']')
+                            .split_once(':')
+                            .map(|(_, v)| v)
+                            .unwrap_or_default();
+
+                        if value.ends_with(char::is_whitespace) {
+                            warnings.push(LintWarning {
+                                kind: LintKind::TrailingWhitespace,
+                                line: cnt,
+                                message: format!(
+                                    "The value of '{}' has trailing whitespace",
+                                    key
+                                ),
+                            });
+                        }
+
+                        if let Some(last) = &last_key {
+                            if key.to_lowercase() < last.to_lowercase() {
+                                warnings.push(LintWarning {
+                                    kind: LintKind::UnsortedAttributes,
+                                    line: cnt,
+                                    message: format!(
+                                        "Attribute '{}' should be before '{}'",
+                                        key,
+                                        last
+                                    ),
+                                });
+                            }
+                        }
+                        last_key = Some(key);
+                    },
+                    Err(_) => {},
+                }
+                Ok(())
+            }
+        );
+
+        // Reading from a string never fails, and we ignore parse errors.
+        debug_assert!(res.is_ok());
+
+        let consumed = text.len() - reader.len();
+        let blank_lines = text[consumed..].lines()
+            .take_while(|l| l.trim().is_empty())
+            .count();
+
+        if blank_lines > 0 {
+            warnings.push(LintWarning {
+                kind: LintKind::ExtraBlankLines,
+                line: text[..consumed].lines().count() as u32 + 1,
+                message: "Only one blank line should separate the header \
+                    and content".into(),
+            });
+        }
+
+        warnings
     }
 
     /// Read the file at the given path and parse it as a `Note`.
     pub fn read_from_file(path: &Path) -> Result<Self, FileError> {
-        use std::io::{prelude::*, BufReader};
+        Self::read_from_file_with_prefix(path, DEFAULT_TAG_PREFIX)
+    }
 
-        let mut note = Note::default();
-        let mut reader = BufReader::new(File::open(path)?);
-        let mut line = String::new();
-        let mut cnt = 0;
+    /// Read the file at the given path and parse it as a `Note` whose tags
+    /// begin with the given prefix character rather than '@'.
+    ///
+    /// See [Note::with_tag_prefix] for the restrictions on the prefix.
+    pub fn read_from_file_with_prefix(path: &Path, prefix: char)
+    -> Result<Self, FileError> {
+        use std::io::BufReader;
 
-        while reader.read_line(&mut line)? > 1 {
-            cnt += 1;
+        let file = File::open(path).map_err(io_error(path))?;
+        Self::read_from(BufReader::new(file), path, prefix)
+    }
 
-            match Self::read_metadata_line(path, &line, cnt)? {
-                Metadata::Tag(mut vs) => { note.tags.append(&mut vs); },
-                Metadata::KV(k, v) => { note.map.insert(k, v); },
-            }
-            line.clear();
-        }
+    /// Read a `Note` from `reader`, which is read to its end.
+    ///
+    /// The header is parsed as it is read, so a reader that does not begin
+    /// with a note header fails without reading the rest of its data.
+    ///
+    /// ```
+    /// # use upim_note::Note;
+    /// let note = Note::from_reader(&b"@tag\n\nSome text.\n"[..]).unwrap();
+    /// assert_eq!(note.content(), "Some text.\n");
+    /// ```
+    pub fn from_reader<R: std::io::BufRead>(reader: R)
+    -> Result<Self, FileError> {
+        Self::from_reader_with_prefix(reader, DEFAULT_TAG_PREFIX)
+    }
 
-        reader.read_to_string(&mut note.content)?;
+    /// Read a `Note` whose tags begin with the given prefix character rather
+    /// than '@' from `reader`, which is read to its end.
+    ///
+    /// See [Note::with_tag_prefix] for the restrictions on the prefix.
+    pub fn from_reader_with_prefix<R: std::io::BufRead>(reader: R, prefix: char)
+    -> Result<Self, FileError> {
+        Self::read_from(reader, Path::new(""), prefix)
+    }
 
-        Ok(note)
+    /// Write the note to `writer` in the format read by [Note::from_reader].
+    ///
+    /// This writes the same text as [Note::write_to_file].
+    pub fn to_writer<W>(&self, writer: &mut W) -> std::io::Result<()>
+        where W: Write + ?Sized,
+    {
+        write!(writer, "{}", self)
+    }
+
+    /// Read the file at the given path and parse it as a `Note`, reporting
+    /// whether a failure was due to the file or its contents.
+    ///
+    /// Returns [FileError::IO] with the path if the file could not be read,
+    /// or [FileError::Parse] if it was read but is not a note -- including
+    /// files that are not valid UTF-8. Code walking a collection can skip
+    /// the latter and report the former.
+    pub fn try_from_file(path: &Path) -> Result<Self, FileError> {
+        use std::io::ErrorKind;
+
+        Self::read_from_file(path).map_err(|e| match e {
+            FileError::IO((_, ErrorKind::InvalidData)) => FileError::Parse {
+                file: path.to_owned(),
+                kind: ParseKind::InvalidEncoding,
+                msg: "The file is not valid UTF-8".into(),
+                data: String::new(),
+                line: 0,
+            },
+            FileError::IO((_, kind)) => FileError::IO((path.to_owned(), kind)),
+            e @ FileError::Parse { .. } => e,
+        })
     }
 
     /// Read a Note header from a file.
     ///
     /// Returns a [Note] with an empty content field.
     pub fn read_header(path: &Path) -> Result<Self, FileError> {
-        use std::io::{prelude::*, BufReader};
+        Self::read_header_with_prefix(path, DEFAULT_TAG_PREFIX)
+    }
 
-        let mut note = Note::default();
-        let mut reader = BufReader::new(File::open(path)?);
-        let mut line = String::new();
-        let mut cnt = 0;
+    /// Read the header of a note whose tags begin with the given prefix
+    /// character rather than '@' from a file.
+    ///
+    /// Returns a [Note] with an empty content field.
+    pub fn read_header_with_prefix(path: &Path, prefix: char)
+    -> Result<Self, FileError> {
+        use std::io::BufReader;
 
-        while reader.read_line(&mut line)? > 1 {
-            cnt += 1;
+        let file = File::open(path).map_err(io_error(path))?;
+        Self::read_header_from(&mut BufReader::new(file), path, prefix)
+    }
 
-            match Self::read_metadata_line(path, &line, cnt)? {
-                Metadata::Tag(mut vs) => { note.tags.append(&mut vs); },
-                Metadata::KV(k, v) => { note.map.insert(k, v); },
-            }
-            line.clear();
-        }
+    /// Read a Note header from a file, and the byte offset in the file at
+    /// which the note's content begins.
+    ///
+    /// Returns a [Note] with an empty content field.
+    pub fn read_header_with_offset(path: &Path)
+    -> Result<(Self, u64), FileError> {
+        use std::io::{BufReader, Seek as _};
 
-        Ok(note)
-    }
+        let file = File::open(path).map_err(io_error(path))?;
+        let mut reader = BufReader::new(file);
+        let note = Self::read_header_from(&mut reader, path,
+            DEFAULT_TAG_PREFIX)?;
+        let offset = reader.stream_position().map_err(io_error(path))?;
 
-    /// Save the note to the specified path.
-    pub fn write_to_file(&self, path: &Path) -> std::io::Result<()> {
-        let mut file = File::create(path)?;
+        Ok((note, offset))
+    }
 
-        for tag in &self.tags {
-            file.write_all(tag.as_bytes())?;
-            file.write_all(b"\n")?;
+    /// Read at most `len` bytes of a note's content, beginning `start` bytes
+    /// into the content.
+    ///
+    /// The window is narrowed to whole characters: a multibyte character
+    /// split by `start` or by the end of the window is left out, so fewer than
+    /// `len` bytes may be returned. A window that extends past the end of the
+    /// content ends with the content.
+    ///
+    /// Returns an `InvalidInput` IO error if `start` is past the end of the
+    /// content, or an `InvalidData` IO error if the window is not valid UTF-8.
+    pub fn read_content_range(path: &Path, start: usize, len: usize)
+    -> Result<String, FileError> {
+        use std::io::{ErrorKind, Read as _, Seek as _, SeekFrom};
+
+        let (_, offset) = Self::read_header_with_offset(path)?;
+        let mut file = File::open(path).map_err(io_error(path))?;
+        let content_len = file.metadata().map_err(io_error(path))?
+            .len()
+            .saturating_sub(offset);
+
+        if start as u64 > content_len {
+            return Err(
+                FileError::IO((path.to_owned(), ErrorKind::InvalidInput))
+            );
         }
 
-        for (k, v) in &self.map {
-            file.write_all(b"[")?;
-            file.write_all(k.as_bytes())?;
-            file.write_all(b": ")?;
-            file.write_all(v.as_bytes())?;
-            file.write_all(b"]\n")?;
-        }
+        let mut buf = vec![];
+        file.seek(SeekFrom::Start(offset + start as u64))
+            .and_then(|_| file.take(len as u64).read_to_end(&mut buf))
+            .map_err(io_error(path))?;
+
+        // Skip the rest of a character that began before the window.
+        let skip = if start > 0 {
+            buf.iter().take_while(|b| *b & 0xC0 == 0x80).count()
+        } else {
+            0
+        };
+        let bytes = &buf[skip..];
+
+        let text = match std::str::from_utf8(bytes) {
+            Ok(text) => text,
+            // The window ends within a character.
+            Err(e) if e.error_len().is_none() =>
+                std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+            Err(_) => return Err(
+                FileError::IO((path.to_owned(), ErrorKind::InvalidData))
+            ),
+        };
 
-        file.write_all(b"\n")?;
-        file.write_all(self.content.as_bytes())?;
+        Ok(text.into())
+    }
 
-        Ok(())
+    /// Read a Note header from a file and memory-map its content.
+    ///
+    /// The returned [Note] has an empty content field; the content is
+    /// returned separately. If the content cannot be mapped -- for example,
+    /// because it is empty -- it is read into memory instead.
+    ///
+    /// The content must be valid UTF-8. As with any memory-mapped file, the
+    /// content must not be modified while it is mapped.
+    #[cfg(feature = "mmap")]
+    pub fn open_content_mmap(path: &Path)
+    -> Result<(Self, MmapContent), FileError> {
+        use std::io::{ErrorKind, Read as _, Seek as _, SeekFrom};
+
+        let (note, offset) = Self::read_header_with_offset(path)?;
+        let mut file = File::open(path).map_err(io_error(path))?;
+
+        let len = file.metadata().map_err(io_error(path))?.len();
+
+        let map = if offset < len {
+            // Safety: the caller must ensure the file is not modified while
+            // it's mapped, as documented above.
+            unsafe { memmap2::MmapOptions::new().offset(offset).map(&file) }
+                .ok()
+        } else {
+            None
+        };
+
+        let inner = match map {
+            Some(map) => {
+                if std::str::from_utf8(&map).is_err() {
+                    return Err(FileError::IO(
+                        (path.to_owned(), ErrorKind::InvalidData)
+                    ));
+                }
+                MmapInner::Mapped(map)
+            },
+            None => {
+                let mut content = String::new();
+                file.seek(SeekFrom::Start(offset))
+                    .and_then(|_| file.read_to_string(&mut content))
+                    .map_err(io_error(path))?;
+                MmapInner::Read(content)
+            },
+        };
+
+        Ok((note, MmapContent { inner }))
+    }
+
+    /// Save the note to the specified path.
+    ///
+    /// Reading the file with [Note::read_from_file] produces a note equal to
+    /// this one, provided its attributes are valid (see
+    /// [Note::is_valid_attribute_key]); an empty note is written as a single
+    /// blank line.
+    ///
+    /// Tags that were read on the same line are written on the same line;
+    /// each tag added afterward is written on a line of its own.
+    ///
+    /// The file contains the text produced by the note's `Display`
+    /// implementation, so `Note::from_str(&note.to_string())` is equal to
+    /// the note as well.
+    ///
+    /// The note is written to a temporary file in the same directory, which
+    /// is then renamed over `path`; if writing fails, an existing file at
+    /// `path` is left unchanged.
+    pub fn write_to_file(&self, path: &Path) -> std::io::Result<()> {
+        write_atomically(path, |w| self.to_writer(w))
     }
 
     /// Add the given tag to the note.
     ///
     /// If the note already exists, does nothing. If the tag is not prepended
-    /// with a '@', it is added.
+    /// with the note's tag prefix ('@' by default), it is added.
     pub fn insert_tag(&mut self, tag: &str) {
-        let tag = if tag.starts_with('@') {
-            tag.into()
-        } else {
-            format!("@{}", tag)
-        };
+        let tag = self.prefixed_tag(tag);
 
         if ! self.tags.contains(&tag) {
             self.tags.push(tag);
@@ -244,11 +929,54 @@ impl Note {
     ///
     /// If the tag was present, it is returned. Otherwise returns `None`.
     pub fn remove_tag(&mut self, tag: &str) -> Option<String> {
-        if let Some(pos) = self.tags.iter().position(|x| *x == tag) {
-            Some(self.tags.remove(pos))
-        } else {
-            None
+        self.tags.iter()
+            .position(|x| *x == tag)
+            .map(|pos| self.remove_tag_at(pos))
+    }
+
+    /// Remove the tag at `pos`, keeping the other tags on their lines.
+    fn remove_tag_at(&mut self, pos: usize) -> String {
+        if pos < self.tag_joined.len() {
+            let joined = self.tag_joined.remove(pos);
+
+            // The next tag takes the removed tag's place at a line's start.
+            if let Some(next) = self.tag_joined.get_mut(pos) {
+                *next = *next && joined;
+            }
+        }
+
+        self.tags.remove(pos)
+    }
+
+    /// Check whether the tag at `pos` was read on the same line as the tag
+    /// before it.
+    fn is_tag_joined(&self, pos: usize) -> bool {
+        pos < self.tags.len() && self.tag_joined.get(pos) == Some(&true)
+    }
+
+    /// Replace the tag `old` with `new`, keeping its place among the note's
+    /// tags.
+    ///
+    /// The tag prefix is optional for both tags. If the note already has the
+    /// new tag, the old tag is removed. Returns false if the note does not
+    /// have the old tag.
+    pub fn rename_tag(&mut self, old: &str, new: &str) -> bool {
+        let old = self.prefixed_tag(old);
+        let new = self.prefixed_tag(new);
+
+        let pos = match self.tags.iter().position(|t| *t == old) {
+            Some(pos) => pos,
+            None => return false,
+        };
+
+        if old != new {
+            if self.tags.contains(&new) {
+                self.remove_tag_at(pos);
+            } else {
+                self.tags[pos] = new;
+            }
         }
+        true
     }
 
     /// Check whether the note contains the specified tag.
@@ -256,68 +984,796 @@ impl Note {
         self.tags.contains(&tag.to_string())
     }
 
+    /// Check whether the note contains every one of the specified tags.
+    ///
+    /// The tag prefix is optional. Returns true if `tags` is empty.
+    pub fn has_all_tags(&self, tags: &[&str]) -> bool {
+        tags.iter().all(|t| self.tags.contains(&self.prefixed_tag(t)))
+    }
+
+    /// Check whether the note contains at least one of the specified tags.
+    ///
+    /// The tag prefix is optional. Returns false if `tags` is empty.
+    pub fn has_any_tag(&self, tags: &[&str]) -> bool {
+        tags.iter().any(|t| self.tags.contains(&self.prefixed_tag(t)))
+    }
+
+    /// Add the note's tag prefix to the tag if it is missing.
+    fn prefixed_tag(&self, tag: &str) -> String {
+        if tag.starts_with(self.tag_prefix) {
+            tag.into()
+        } else {
+            format!("{}{}", self.tag_prefix, tag)
+        }
+    }
+
     /// Retrieve the list of tags on the note.
     pub fn tags(&self) -> &[String] {
         &self.tags
     }
 
-    /// Look up the attribute value matching the given key.
-    pub fn get_attribute(&self, key: &str) -> Option<&String> {
-        self.map.get(key)
+    /// Retrieve the note's tags as a set, for fast membership tests.
+    pub fn tags_set(&self) -> HashSet<&str> {
+        self.tags.iter().map(String::as_str).collect()
     }
 
-    /// Add or update the specified attribute on the note.
-    pub fn set_attribute(&mut self, key: &str, value: &str) {
-        self.map.insert(key.into(), value.into());
+    /// Retrieve the note's tags in sorted order.
+    ///
+    /// The note itself is not modified.
+    pub fn tags_sorted(&self) -> Vec<&String> {
+        let mut tags: Vec<&String> = self.tags.iter().collect();
+        tags.sort();
+        tags
     }
 
-    pub fn remove_attribute(&mut self, key: &str) -> Option<String> {
-        self.map.remove(key)
+    /// Retrieve the tags at or beneath the given tag in a `/`-separated tag
+    /// hierarchy.
+    ///
+    /// Tags are compared segment by segment, so `@project` matches `@project`
+    /// and `@project/alpha` but not `@projectx`. The tag prefix symbol may be
+    /// omitted from `prefix`.
+    pub fn tags_under(&self, prefix: &str) -> Vec<&String> {
+        let prefix = if prefix.starts_with(self.tag_prefix) {
+            prefix.to_string()
+        } else {
+            format!("{}{}", self.tag_prefix, prefix)
+        };
+        let prefix = Self::tag_segments(&prefix);
+
+        self.tags.iter()
+            .filter(|t| Self::tag_segments(t).starts_with(&prefix))
+            .collect()
     }
 
-    /// Check whether the note contains the specified attribute.
-    pub fn contains_attribute(&self, key: &str) -> bool {
-        self.map.contains_key(key)
+    /// Split a hierarchical tag into its `/`-separated segments.
+    ///
+    /// Empty segments are skipped, so `@a//b/` has the segments `@a` and `b`.
+    pub fn tag_segments(tag: &str) -> Vec<&str> {
+        tag.split('/').filter(|s| ! s.is_empty()).collect()
     }
 
-    pub fn attribute_keys(&self) -> impl Iterator<Item = &String> {
-        self.map.keys()
+    /// Look up the attribute value matching the given key.
+    ///
+    /// If the key is duplicated, returns its first value.
+    pub fn get_attribute(&self, key: &str) -> Option<&String> {
+        self.map.get(key)
     }
 
-    pub fn attributes(&self) -> impl Iterator<Item = (&String, &String)> {
-        self.map.iter()
+    /// Get every value of the specified attribute, in the order they were
+    /// added.
+    ///
+    /// Returns an empty list if the note does not have the attribute.
+    ///
+    /// ```
+    /// # use std::str::FromStr;
+    /// # use upim_note::Note;
+    /// let note = Note::from_str("[Ref: a]\n[Ref: b]\n").unwrap();
+    /// assert_eq!(note.get_attribute_all("Ref"), ["a", "b"]);
+    /// ```
+    pub fn get_attribute_all(&self, key: &str) -> Vec<&String> {
+        self.map.get(key).into_iter()
+            .chain(self.extra.get(key).into_iter().flatten())
+            .collect()
     }
 
-    /// Get the note's content (document).
-    pub fn content(&self) -> &str {
-        &self.content
+    /// Look up the attribute value matching the given key as a string slice.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.map.get(key).map(String::as_str)
     }
 
-    /// Erase the note's content.
+    /// Look up the attribute value matching the given key and parse it as
+    /// `T`.
+    ///
+    /// Returns `None` if the attribute is not present, or the result of
+    /// parsing the value otherwise.
+    ///
+    /// ```
+    /// # use std::str::FromStr;
+    /// # use upim_note::Note;
+    /// let note = Note::from_str("[Count: 3]\n").unwrap();
+    /// assert_eq!(note.get_attribute_as::<u32>("Count"), Some(Ok(3)));
+    /// ```
+    pub fn get_attribute_as<T: FromStr>(&self, key: &str)
+    -> Option<Result<T, T::Err>> {
+        self.map.get(key).map(|v| v.trim().parse::<T>())
+    }
+
+    /// Check whether the text can be written and read back as an attribute key.
+    ///
+    /// A key must not be empty, must not begin or end with whitespace, and
+    /// must not contain ':', '[', ']', or a line break.
+    ///
+    /// ```
+    /// # use upim_note::Note;
+    /// assert!(Note::is_valid_attribute_key("Work phone"));
+    /// assert!(! Note::is_valid_attribute_key("Time: 10"));
+    /// ```
+    pub fn is_valid_attribute_key(key: &str) -> bool {
+        ! key.is_empty()
+            && key.trim() == key
+            && ! key.contains(&[':', '[', ']', '\n', '\r'][..])
+    }
+
+    /// Check whether the text can be written and read back as an attribute
+    /// value.
+    ///
+    /// A value must not contain '[', ']', or a line break. Note that
+    /// whitespace surrounding a value is removed when the note is read.
+    pub fn is_valid_attribute_value(value: &str) -> bool {
+        ! value.contains(&['[', ']', '\n', '\r'][..])
+    }
+
+    /// Add or update the specified attribute on the note.
+    ///
+    /// If the key is duplicated, only its first value is replaced. Use
+    /// [Note::is_valid_attribute_key] and [Note::is_valid_attribute_value] to
+    /// ensure the attribute can be written to a file.
+    pub fn set_attribute(&mut self, key: &str, value: &str) {
+        self.map.insert(key.into(), value.into());
+    }
+
+    /// Add the specified attribute to the note, keeping any values already set
+    /// for the key.
+    ///
+    /// ```
+    /// # use std::str::FromStr;
+    /// # use upim_note::Note;
+    /// let mut note = Note::from_str("[Phone: 555-0100]\n").unwrap();
+    /// note.add_attribute("Phone", "555-0199");
+    ///
+    /// assert_eq!(note["Phone"], "555-0100");
+    /// assert_eq!(note.attributes().count(), 2);
+    /// ```
+    pub fn add_attribute(&mut self, key: &str, value: &str) {
+        if self.map.contains_key(key) {
+            self.extra.entry(key.into()).or_default().push(value.into());
+        } else {
+            self.map.insert(key.into(), value.into());
+        }
+    }
+
+    /// Remove every value of the specified attribute, returning the first.
+    pub fn remove_attribute(&mut self, key: &str) -> Option<String> {
+        self.extra.remove(key);
+        self.map.shift_remove(key)
+    }
+
+    /// Change the key of the attribute `old` to `new`, keeping its values and
+    /// its place among the note's attributes.
+    ///
+    /// If the note already has the key `new`, its values are replaced by those
+    /// of `old`. Returns false if the note does not have the key `old`.
+    pub fn rename_attribute_key(&mut self, old: &str, new: &str) -> bool {
+        if ! self.map.contains_key(old) {
+            return false;
+        }
+        if old == new {
+            return true;
+        }
+
+        self.remove_attribute(new);
+
+        let map = std::mem::take(&mut self.map);
+        self.map = map.into_iter()
+            .map(|(k, v)| if k == old { (new.to_owned(), v) } else { (k, v) })
+            .collect();
+
+        if let Some(rest) = self.extra.remove(old) {
+            self.extra.insert(new.into(), rest);
+        }
+        true
+    }
+
+    /// Fill in the attributes of `other` that this note does not have, and add
+    /// its tags.
+    ///
+    /// Attributes already on this note keep their values; for the keys it
+    /// lacks, every value in `other` is added. Tags are re-prefixed with this
+    /// note's tag prefix. This is useful for applying defaults from a template.
+    pub fn merge_attributes_from(&mut self, other: &Note) {
+        for tag in &other.tags {
+            self.insert_tag(tag.strip_prefix(other.tag_prefix).unwrap_or(tag));
+        }
+
+        let missing = other.map.keys()
+            .filter(|k| ! self.map.contains_key(*k))
+            .collect::<Vec<_>>();
+
+        for key in missing {
+            for value in other.get_attribute_all(key) {
+                self.add_attribute(key, value);
+            }
+        }
+    }
+
+    /// Retrieve the attribute keys that are set more than once on the note.
+    pub fn duplicate_attribute_keys(&self) -> Vec<String> {
+        let mut counts: HashMap<&String, usize> = HashMap::new();
+
+        for (k, _) in self.attributes() {
+            *counts.entry(k).or_default() += 1;
+        }
+
+        let mut dups = counts.into_iter()
+            .filter(|(_, n)| *n > 1)
+            .map(|(k, _)| k.to_owned())
+            .collect::<Vec<String>>();
+
+        dups.sort();
+        dups
+    }
+
+    /// Check whether the note contains the specified attribute.
+    pub fn contains_attribute(&self, key: &str) -> bool {
+        self.map.contains_key(key)
+    }
+
+    /// Iterate over the note's attribute keys in the order they were first
+    /// read or added.
+    ///
+    /// See [Note::attribute_keys_sorted] for a sorted order.
+    pub fn attribute_keys(&self) -> impl Iterator<Item = &String> {
+        self.map.keys()
+    }
+
+    /// Retrieve the note's attribute keys in sorted order.
+    ///
+    /// The note itself is not modified.
+    pub fn attribute_keys_sorted(&self) -> Vec<&String> {
+        let mut keys: Vec<&String> = self.map.keys().collect();
+        keys.sort();
+        keys
+    }
+
+    /// Iterate over the note's attributes in the order their keys were first
+    /// read or added.
+    ///
+    /// Each value of a duplicated key is listed after its first value, in the
+    /// order the values were added. See [Note::attributes_sorted] for a
+    /// sorted order.
+    pub fn attributes(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.map.iter()
+            .flat_map(move |(k, v)| {
+                let rest = self.extra.get(k).into_iter().flatten();
+                std::iter::once(v).chain(rest).map(move |v| (k, v))
+            })
+    }
+
+    /// Borrow the map of the note's attributes.
+    ///
+    /// Only the first value of a duplicated key is in the map. The keys are in
+    /// the order of [Note::attribute_keys].
+    pub fn attributes_map(&self) -> &IndexMap<String, String> {
+        &self.map
+    }
+
+    /// Retrieve the note's attributes sorted by key, then value.
+    ///
+    /// The note itself is not modified.
+    pub fn attributes_sorted(&self) -> Vec<(&String, &String)> {
+        let mut attrs: Vec<(&String, &String)> = self.attributes().collect();
+        attrs.sort();
+        attrs
+    }
+
+    /// Retrieve the attributes whose values match `val_re`, and if given,
+    /// whose keys match `key_re`.
+    ///
+    /// The patterns are unanchored, so they match any part of the text. Each
+    /// matching value of a duplicated key is listed; the attributes are sorted
+    /// as with [Note::attributes_sorted].
+    ///
+    /// ```
+    /// # use std::str::FromStr;
+    /// # use regex::Regex;
+    /// # use upim_note::Note;
+    /// let note = Note::from_str("[Phone: 555-0100]\n[Fax: 555-0199]\n")
+    ///     .unwrap();
+    /// let key = Regex::new("(?i)^phone$").unwrap();
+    /// let value = Regex::new("^555-").unwrap();
+    ///
+    /// assert_eq!(note.attributes_matching(None, &value).len(), 2);
+    /// assert_eq!(note.attributes_matching(Some(&key), &value).len(), 1);
+    /// ```
+    pub fn attributes_matching(&self, key_re: Option<&Regex>, val_re: &Regex)
+    -> Vec<(&String, &String)> {
+        self.attributes_sorted().into_iter()
+            .filter(|(k, v)| {
+                key_re.iter().all(|re| re.is_match(k)) && val_re.is_match(v)
+            })
+            .collect()
+    }
+
+    /// Get the note's content (document).
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// Get at most the first `max_chars` characters of the note's content.
+    ///
+    /// The preview never ends within a multibyte character.
+    pub fn content_preview(&self, max_chars: usize) -> &str {
+        match self.content.char_indices().nth(max_chars) {
+            Some((i, _)) => &self.content[..i],
+            None => &self.content,
+        }
+    }
+
+    /// Get the first line of the note's content, without its line ending.
+    pub fn first_line(&self) -> &str {
+        self.content.lines().next().unwrap_or("")
+    }
+
+    /// Split the note's content into sections at each line that begins with
+    /// '#', as in a Markdown heading.
+    ///
+    /// Each section is its heading -- the line without its leading '#'
+    /// characters, surrounding whitespace, and line ending -- and the text
+    /// after the heading line up to the next heading. Any text before the
+    /// first heading is returned first with no heading; empty content has no
+    /// sections.
+    ///
+    /// ```
+    /// # use upim_note::Note;
+    /// let note = Note::content_only("Intro\n# One\nText\n## Two\n");
+    ///
+    /// assert_eq!(note.content_sections(), [
+    ///     (None, "Intro\n"),
+    ///     (Some("One"), "Text\n"),
+    ///     (Some("Two"), ""),
+    /// ]);
+    /// ```
+    pub fn content_sections(&self) -> Vec<(Option<&str>, &str)> {
+        let content = self.content.as_str();
+        let mut sections = vec![];
+        let mut heading = None;
+        let mut start = 0;
+        let mut pos = 0;
+
+        for line in content.split_inclusive('\n') {
+            if line.starts_with('#') {
+                if heading.is_some() || pos > 0 {
+                    sections.push((heading, &content[start..pos]));
+                }
+                heading = Some(line.trim_start_matches('#').trim());
+                start = pos + line.len();
+            }
+            pos += line.len();
+        }
+
+        if heading.is_some() || start < content.len() {
+            sections.push((heading, &content[start..]));
+        }
+        sections
+    }
+
+    /// Replace the note's content with the given UTF-8 encoded bytes.
+    ///
+    /// If `bytes` is not valid UTF-8, the content is left unchanged and the
+    /// error reports the offset of the first invalid byte sequence.
+    pub fn set_content_from_bytes(&mut self, bytes: &[u8])
+    -> Result<(), NoteError> {
+        let text = std::str::from_utf8(bytes)
+            .map_err(|e| NoteError::InvalidUtf8 { offset: e.valid_up_to() })?;
+
+        self.content = text.into();
+        Ok(())
+    }
+
+    /// Get the note's content for editing in place.
+    ///
+    /// ```
+    /// # use upim_note::Note;
+    /// let mut note = Note::content_only("Some text.\n");
+    /// note.content_mut().insert_str(0, "# Title\n");
+    ///
+    /// assert_eq!(note.content(), "# Title\nSome text.\n");
+    /// ```
+    pub fn content_mut(&mut self) -> &mut String {
+        &mut self.content
+    }
+
+    /// Replace the note's content with the given text.
+    pub fn set_content(&mut self, text: &str) {
+        self.content = text.into();
+    }
+
+    /// Add text to the end of the note's content.
+    ///
+    /// Unlike [Note::append_content], no newline is added between the
+    /// existing content and the new text.
+    pub fn push_content(&mut self, text: &str) {
+        self.content.push_str(text);
+    }
+
+    /// Erase the note's content.
     pub fn clear_content(&mut self) {
         self.content = String::new();
     }
 
+    /// Append text to the note's content.
+    ///
+    /// If the existing content does not end with a newline, one is added
+    /// before the new text.
+    pub fn append_content(&mut self, text: &str) {
+        if ! self.content.is_empty() && ! self.content.ends_with('\n') {
+            self.content.push('\n');
+        }
+        self.content.push_str(text);
+    }
 
-    fn read_metadata_line(file: &Path, line: &str, line_num: u32)
-    -> Result<Metadata, FileError> {
-        assert!(line.len() > 1);
-        assert!(line.ends_with('\n'), "{}", line.to_string());
+    /// Replace the note's content with the result of `f`.
+    ///
+    /// ```
+    /// # use upim_note::Note;
+    /// let mut note = Note::content_only("Some text.\n");
+    /// note.map_content(|c| c.replace("Some", "More"));
+    ///
+    /// assert_eq!(note.content(), "More text.\n");
+    /// ```
+    pub fn map_content<F: FnOnce(&str) -> String>(&mut self, f: F) {
+        self.content = f(&self.content);
+    }
+
+    /// Replace each attribute value with the result of `f`, which receives
+    /// the key and value.
+    ///
+    /// If `f` returns `None`, the value is removed; a key with no values left
+    /// is removed from the note. Every value of a duplicated key is passed to
+    /// `f`, in order.
+    pub fn map_attributes<F>(&mut self, f: F)
+        where F: FnMut(&str, &str) -> Option<String>,
+    {
+        let mut f = f;
+        let map = std::mem::take(&mut self.map);
+        let mut extra = std::mem::take(&mut self.extra);
+
+        for (k, v) in map {
+            let rest = extra.remove(&k).unwrap_or_default();
+
+            let mut values = std::iter::once(v).chain(rest)
+                .filter_map(|v| f(&k, &v));
+
+            if let Some(first) = values.next() {
+                let rest = values.collect::<Vec<_>>();
+
+                if ! rest.is_empty() {
+                    self.extra.insert(k.clone(), rest);
+                }
+                self.map.insert(k, first);
+            }
+        }
+    }
 
-        let line = &line[0..line.len()-1];
+    /// Find each line of the content that contains `needle`, along with up to
+    /// `before` lines preceding it and `after` lines following it.
+    ///
+    /// Matches are listed in order; the context of adjacent matches may
+    /// overlap. An empty `needle` matches every line.
+    ///
+    /// ```
+    /// # use upim_note::Note;
+    /// let note = Note::content_only("one\ntwo\nthree\n");
+    /// let matches = note.search_with_context("TWO", 1, 1, true);
+    ///
+    /// assert_eq!(matches[0].line, 2);
+    /// assert_eq!(matches[0].before, ["one"]);
+    /// assert_eq!(matches[0].after, ["three"]);
+    /// ```
+    pub fn search_with_context(
+        &self,
+        needle: &str,
+        before: usize,
+        after: usize,
+        case_insensitive: bool
+    ) -> Vec<ContentMatch> {
+        let lines = self.content.lines().collect::<Vec<_>>();
+        let needle = if case_insensitive {
+            needle.to_lowercase()
+        } else {
+            needle.to_owned()
+        };
+
+        let to_owned = |lines: &[&str]| {
+            lines.iter().map(|l| l.to_string()).collect::<Vec<_>>()
+        };
+
+        lines.iter()
+            .enumerate()
+            .filter(|(_, line)| if case_insensitive {
+                line.to_lowercase().contains(&needle)
+            } else {
+                line.contains(&needle)
+            })
+            .map(|(i, line)| {
+                let end = (i + 1).saturating_add(after).min(lines.len());
+
+                ContentMatch {
+                    line: i + 1,
+                    text: line.to_string(),
+                    before: to_owned(&lines[i.saturating_sub(before)..i]),
+                    after: to_owned(&lines[i + 1..end]),
+                }
+            })
+            .collect()
+    }
+
+    /// Summarize the note's header and content.
+    pub fn stats(&self) -> NoteStats {
+        NoteStats {
+            tags: self.tags.len(),
+            attributes: self.attributes().count(),
+            content_bytes: self.content.len(),
+            content_lines: self.content.lines().count(),
+        }
+    }
+
+    /// Count the occurrences of each word in the note's content.
+    ///
+    /// A word is a run of alphanumeric characters and apostrophes; every other
+    /// character (whitespace and punctuation) separates words. Apostrophes at
+    /// the beginning or end of a word are removed, so quoted words are counted
+    /// with their unquoted forms. Words are lowercased before they are counted.
+    pub fn word_frequencies(&self) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+
+        let words = self.content
+            .split(|c: char| ! (c.is_alphanumeric() || c == '\''))
+            .map(|w| w.trim_matches('\''))
+            .filter(|w| ! w.is_empty());
+
+        for word in words {
+            *counts.entry(word.to_lowercase()).or_insert(0) += 1;
+        }
+
+        counts
+    }
+
+    /// List the changes that would turn this note into `other`.
+    ///
+    /// Tag changes are listed first, in the order of the tags on each note,
+    /// followed by attribute changes ordered by key, then content changes.
+    pub fn diff(&self, other: &Note) -> Vec<NoteChange> {
+        let mut changes = vec![];
+
+        for tag in self.tags.iter().filter(|t| ! other.tags.contains(t)) {
+            changes.push(NoteChange::TagRemoved(tag.to_owned()));
+        }
+        for tag in other.tags.iter().filter(|t| ! self.tags.contains(t)) {
+            changes.push(NoteChange::TagAdded(tag.to_owned()));
+        }
+
+        let mut keys = self.map.keys().chain(other.map.keys())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+
+        for key in keys {
+            // Each value of a duplicated key is compared with the value at the
+            // same position on the other note.
+            let old = self.get_attribute_all(key);
+            let new = other.get_attribute_all(key);
+
+            for i in 0..old.len().max(new.len()) {
+                let change = match (old.get(i), new.get(i)) {
+                    (Some(old), Some(new)) if old != new => Some(
+                        NoteChange::AttributeChanged(
+                            key.to_owned(), old.to_string(), new.to_string()
+                        )
+                    ),
+                    (Some(old), None) => Some(
+                        NoteChange::AttributeRemoved(
+                            key.to_owned(), old.to_string()
+                        )
+                    ),
+                    (None, Some(new)) => Some(
+                        NoteChange::AttributeAdded(
+                            key.to_owned(), new.to_string()
+                        )
+                    ),
+                    _ => None,
+                };
+                changes.extend(change);
+            }
+        }
+
+        if self.content != other.content {
+            let old = self.content.lines().collect::<Vec<_>>();
+            let new = other.content.lines().collect::<Vec<_>>();
+
+            let start = old.iter().zip(new.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            let end = old[start..].iter().rev().zip(new[start..].iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            let lines = |l: &[&str]| {
+                l.iter().map(|s| s.to_string()).collect::<Vec<_>>()
+            };
+
+            changes.push(NoteChange::ContentChanged {
+                removed: lines(&old[start..old.len() - end]),
+                added: lines(&new[start..new.len() - end]),
+            });
+        }
+
+        changes
+    }
+
+    /// Replace any characters that would prevent the note from being read back
+    /// after it is written.
+    ///
+    /// - '[' and ']' in attribute keys and values become '(' and ')'.
+    /// - line breaks in attribute keys and values become spaces.
+    /// - ':' is removed from attribute keys.
+    /// - whitespace surrounding attribute keys and values is removed.
+    /// - whitespace within tags becomes '-'.
+    ///
+    /// If two keys are identical after sanitizing, only one attribute is kept.
+    ///
+    /// Returns the number of characters that were replaced or removed.
+    pub fn sanitize(&mut self) -> usize {
+        let mut count = 0;
+
+        for tag in self.tags.iter_mut() {
+            if tag.contains(char::is_whitespace) {
+                count += tag.chars().filter(|c| c.is_whitespace()).count();
+                *tag = tag.replace(char::is_whitespace, "-");
+            }
+        }
+
+        let map = std::mem::take(&mut self.map);
+        let extra = std::mem::take(&mut self.extra);
+
+        for (k, v) in map {
+            let (k, k_count) = sanitize_text(&k, true);
+            let (v, v_count) = sanitize_text(&v, false);
+
+            count += k_count + v_count;
+            self.map.insert(k, v);
+        }
+
+        for (k, vs) in extra {
+            let (k, k_count) = sanitize_text(&k, true);
+            count += k_count;
+
+            for v in vs {
+                let (v, v_count) = sanitize_text(&v, false);
+                count += v_count;
+                self.add_attribute(&k, &v);
+            }
+        }
+
+        count
+    }
+
+    /// Read a note from `reader`, naming `path` in any parse errors.
+    fn read_from<R: std::io::BufRead>(reader: R, path: &Path, prefix: char)
+    -> Result<Self, FileError> {
+        let mut reader = reader;
+        let mut note = Self::read_header_from(&mut reader, path, prefix)?;
+
+        reader.read_to_string(&mut note.content).map_err(io_error(path))?;
+        Ok(note)
+    }
+
+    /// Read a note's header, leaving `reader` at the start of the content.
+    fn read_header_from<R>(reader: &mut R, path: &Path, prefix: char)
+    -> Result<Self, FileError>
+        where R: std::io::BufRead,
+    {
+        let mut note = Self::empty_with_prefix(path, prefix)?;
+
+        Self::read_header_lines(reader, path, prefix,
+            |meta, _, _| { note.push_metadata(meta?); Ok(()) }
+        )?;
+
+        Ok(note)
+    }
+
+    /// Create an empty note whose tags begin with `prefix`, naming `path` in
+    /// the error if the prefix is invalid.
+    fn empty_with_prefix(path: &Path, prefix: char) -> Result<Self, FileError> {
+        Self::default().with_tag_prefix(prefix).map_err(|e| FileError::Parse {
+            file: path.to_owned(),
+            kind: ParseKind::InvalidTag,
+            msg: e.to_string(),
+            data: prefix.into(),
+            line: 0,
+        })
+    }
+
+    /// Parse each line of a header, passing `f` the metadata (or the error
+    /// parsing it), the line, and its line number.
+    ///
+    /// Every read path uses this so line numbers are the same for a file and
+    /// its text: the first line is 1, and reading stops after the blank line
+    /// that ends the header.
+    fn read_header_lines<R, F>(reader: &mut R, path: &Path, prefix: char, f: F)
+    -> Result<(), FileError>
+        where R: std::io::BufRead,
+              F: FnMut(Result<Metadata, FileError>, &str, u32)
+                  -> Result<(), FileError>,
+    {
+        let mut f = f;
+        let mut line = String::new();
+        let mut cnt = 0;
+
+        while reader.read_line(&mut line).map_err(io_error(path))? > 0
+            && line != "\n"
+        {
+            cnt += 1;
+            f(Self::read_metadata_line(path, &line, cnt, prefix), &line, cnt)?;
+            line.clear();
+        }
+
+        Ok(())
+    }
+
+    /// Add the metadata to the note, returning the tags that were not added
+    /// because the note already had them.
+    fn push_metadata(&mut self, meta: Metadata) -> Vec<String> {
+        let mut duplicates = vec![];
+
+        match meta {
+            Metadata::Tag(vs) => {
+                let line_start = self.tags.len();
+                self.tag_joined.resize(line_start, false);
+
+                for tag in vs {
+                    if self.tags.contains(&tag) {
+                        duplicates.push(tag);
+                    } else {
+                        self.tag_joined.push(self.tags.len() > line_start);
+                        self.tags.push(tag);
+                    }
+                }
+            },
+            Metadata::KV(k, v) => self.add_attribute(&k, &v),
+        }
+
+        duplicates
+    }
+
+    fn read_metadata_line(file: &Path, line: &str, line_num: u32, prefix: char)
+    -> Result<Metadata, FileError> {
+        // The last line of a header-only note may not end with a newline.
+        let line = line.strip_suffix('\n').unwrap_or(line);
 
-        if line.starts_with('@') {
+        if line.starts_with(prefix) {
             let mut tags = vec![];
 
             for tag in line.split(' ') {
                 if tag.is_empty() { continue; }
 
-                if tag.starts_with('@') {
-                    if tag.len() == 1 {
+                if tag.starts_with(prefix) {
+                    if tag.len() == prefix.len_utf8() {
                         return Err(FileError::Parse {
                             file: file.to_owned(),
+                            kind: ParseKind::InvalidTag,
                             msg: "Empty tags are invalid.".into(),
-                            data: "@".into(),
+                            data: tag.into(),
                             line: line_num,
                         });
                     }
@@ -326,7 +1782,8 @@ impl Note {
                 } else {
                     return Err(FileError::Parse {
                         file: file.to_owned(),
-                        msg: "Tag is missing the '@' symbol".into(),
+                        kind: ParseKind::InvalidTag,
+                        msg: format!("Tag is missing the '{}' symbol", prefix),
                         data: tag.into(),
                         line: line_num,
                     });
@@ -341,6 +1798,7 @@ impl Note {
             if line.find(banned).is_some() {
                 return Err(FileError::Parse {
                     file: file.to_owned(),
+                    kind: ParseKind::InvalidAttribute,
                     msg: "Key-value pairs cannot contain '[' or ']'".into(),
                     data: line.into(),
                     line: line_num,
@@ -357,6 +1815,7 @@ impl Note {
                 None => {
                     Err(FileError::Parse {
                         file: file.to_owned(),
+                        kind: ParseKind::InvalidAttribute,
                         msg: "Invalid key/value metadata line".into(),
                         data: line.into(),
                         line: line_num,
@@ -364,8 +1823,17 @@ impl Note {
                 },
             }
         } else {
+            // An attribute missing its closing bracket is reported the same
+            // way as any other invalid line, but we can tell callers why.
+            let kind = if line.starts_with('[') {
+                ParseKind::MissingBracket
+            } else {
+                ParseKind::InvalidLine
+            };
+
             Err(FileError::Parse {
                 file: file.to_owned(),
+                kind,
                 msg: "Invalid metadata object".into(),
                 data: line.into(),
                 line: line_num,
@@ -374,65 +1842,311 @@ impl Note {
     }
 }
 
-/// Supported metadata types in a note.
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
-enum Metadata {
-    Tag(Vec<String>),
-    #[allow(clippy::upper_case_acronyms)]
-    KV(String, String),
+/// Build a [Note] one tag or attribute at a time.
+///
+/// ```
+/// # use std::str::FromStr;
+/// # use upim_note::{Note, NoteBuilder};
+/// let note = NoteBuilder::new()
+///     .tag("tag1")
+///     .tag("@tag2")
+///     .attribute("Key", "Value")
+///     .tag("tag3")
+///     .content("This is the document.\n")
+///     .build()
+///     .unwrap();
+///
+/// let text = "@tag1 @tag2\n[Key: Value]\n@tag3\n\nThis is the document.\n";
+/// assert_eq!(note, Note::from_str(text).unwrap());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct NoteBuilder {
+    note: Note,
+    /// The first attribute that cannot be written, reported by
+    /// [NoteBuilder::build].
+    invalid: Option<(String, String)>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl NoteBuilder {
+    /// Begin building an empty note.
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    #[test]
-    fn read_tag_meta_line() {
-        if let Metadata::Tag(vs) =
-            Note::read_metadata_line(Path::new(""), "@some-tag\n", 1).unwrap()
-        {
-            assert_eq!(vs.len(), 1);
-            assert_eq!(vs[0], "@some-tag");
-        } else {
-            panic!();
-        }
+    /// Add a tag to the note.
+    ///
+    /// As with [Note::insert_tag], the '@' prefix is added if it is missing,
+    /// and a tag already on the note is not added again.
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.note.insert_tag(tag);
+        self
     }
 
-    #[test]
-    fn read_multiple_tags_meta_line() {
-        if let Metadata::Tag(vs)=
-            Note::read_metadata_line(
-                Path::new(""), "@some-tag @other-tag\n", 1).unwrap()
-        {
-            assert_eq!(vs.len(), 2);
-            assert_eq!(vs[0], "@some-tag");
-            assert_eq!(vs[1], "@other-tag");
-        } else {
-            panic!();
+    /// Add an attribute to the note, keeping any values already added for
+    /// the key.
+    pub fn attribute(mut self, key: &str, value: &str) -> Self {
+        let valid = Note::is_valid_attribute_key(key)
+            && Note::is_valid_attribute_value(value);
+
+        if ! valid && self.invalid.is_none() {
+            self.invalid = Some((key.into(), value.into()));
         }
+        self.note.add_attribute(key, value);
+        self
     }
 
-    #[test]
-    fn tags_must_be_prefixed_with_symbol() {
-        assert!(Note::read_metadata_line(
-            Path::new(""), "@some tag\n", 1).is_err());
+    /// Set the note's content, replacing any content set earlier.
+    pub fn content(mut self, text: &str) -> Self {
+        self.note.set_content(text);
+        self
     }
 
-    #[test]
-    fn read_key_value_meta_line() {
-        if let Metadata::KV(k, v) =
-            Note::read_metadata_line(
-                Path::new(""), "[Key: Value]\n", 1).unwrap()
-        {
-            assert_eq!(k, "Key");
-            assert_eq!(v, "Value");
+    /// Create the note.
+    ///
+    /// Returns [NoteError::InvalidAttribute] with the first attribute that
+    /// could not be written to a file, such as one whose key contains ':'.
+    pub fn build(self) -> Result<Note, NoteError> {
+        match self.invalid {
+            Some((key, value)) =>
+                Err(NoteError::InvalidAttribute { key, value }),
+            None => Ok(self.note),
         }
     }
+}
 
-    #[test]
-    fn only_one_kv_is_on_a_line() {
-        assert!(Note::read_metadata_line(
-            Path::new(""), "[k:v] [k:v]\n", 1).is_err());
+/// Count the notes in the collection at `path` that carry each tag.
+///
+/// Every file beneath `path` is read as a note; only the headers are read.
+/// Files that cannot be read as notes are skipped, and their errors are
+/// returned with the counts. An error is returned only if the collection
+/// itself cannot be read.
+pub fn tag_counts(path: &Path)
+-> Result<(HashMap<String, usize>, Vec<FileError>), FileError> {
+    let mut counts = HashMap::new();
+    let mut skipped = vec![];
+
+    for file in collection_files(path)? {
+        let note = match Note::read_header(&file) {
+            Ok(note) => note,
+            Err(e) => {
+                skipped.push(e);
+                continue;
+            },
+        };
+
+        // A tag repeated within a note only counts once.
+        let mut tags = note.tags_sorted();
+        tags.dedup();
+
+        for tag in tags {
+            *counts.entry(tag.to_owned()).or_insert(0) += 1;
+        }
+    }
+
+    Ok((counts, skipped))
+}
+
+/// Rename the tag `old` to `new` in every note in the collection at `path`.
+///
+/// The tag prefix is optional for both tags. Other tags are preserved; if a
+/// note already has the new tag, the old tag is removed.
+///
+/// Every note is read before any is written, so if a file cannot be read as a
+/// note, its error is returned and no note is changed. Each changed note is
+/// then written to a temporary file and renamed over the original, so an
+/// error writing leaves every note either changed or untouched -- but notes
+/// changed before the error remain changed.
+///
+/// Returns the number of notes changed.
+pub fn rename_tag_in_collection(path: &Path, old: &str, new: &str)
+-> Result<usize, FileError> {
+    let mut changed = vec![];
+
+    for file in collection_files(path)? {
+        let mut note = Note::read_from_file(&file)?;
+
+        if note.prefixed_tag(old) != note.prefixed_tag(new)
+            && note.rename_tag(old, new)
+        {
+            changed.push((file, note));
+        }
+    }
+
+    for (file, note) in &changed {
+        note.write_to_file(file).map_err(io_error(file))?;
+    }
+
+    Ok(changed.len())
+}
+
+/// List every file beneath the collection directory at `path`.
+///
+/// Hidden files and directories, such as lock files, are skipped.
+fn collection_files(path: &Path) -> Result<Vec<PathBuf>, FileError> {
+    use std::io::ErrorKind;
+    use walkdir::WalkDir;
+    use upim_core::paths::is_hidden;
+
+    if ! path.is_dir() {
+        return Err(FileError::IO((path.to_owned(), ErrorKind::NotFound)));
+    }
+
+    let mut files = vec![];
+
+    let entries = WalkDir::new(path).min_depth(1).follow_links(true)
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || ! is_hidden(e.path()));
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                if e.loop_ancestor().is_some() { continue; }
+
+                let file = e.path().map(Path::to_path_buf).unwrap_or_default();
+                let kind = e.io_error()
+                    .map(|e| e.kind())
+                    .unwrap_or(ErrorKind::Other);
+
+                return Err(FileError::IO((file, kind)));
+            },
+        };
+
+        if entry.file_type().is_file() {
+            files.push(entry.into_path());
+        }
+    }
+
+    Ok(files)
+}
+
+/// Convert an error reading the file at `path` into a [FileError] that names
+/// the file.
+fn io_error(path: &Path) -> impl Fn(std::io::Error) -> FileError + '_ {
+    move |e| FileError::IO((path.to_owned(), e.kind()))
+}
+
+/// Replace the file at `path` with the data written by `f`.
+///
+/// The data is written to a temporary file beside `path`, which is renamed
+/// over `path` only if `f` succeeds; otherwise the temporary file is removed.
+/// A replaced file's permissions are kept.
+///
+/// If `path` is a symbolic link, the file it points to is replaced and the
+/// link is left in place.
+fn write_atomically<F>(path: &Path, f: F) -> std::io::Result<()>
+    where F: FnOnce(&mut dyn Write) -> std::io::Result<()>,
+{
+    use std::{fs, io::BufWriter};
+    use upim_core::paths::temp_sibling;
+
+    let target = fs::canonicalize(path);
+    let path = target.as_deref().unwrap_or(path);
+    let temp = temp_sibling(path);
+
+    let res = File::create(&temp)
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            f(&mut out)?;
+
+            let file = out.into_inner().map_err(|e| e.into_error())?;
+            if let Ok(meta) = fs::metadata(path) {
+                file.set_permissions(meta.permissions())?;
+            }
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp, path));
+
+    if res.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    res
+}
+
+/// Replace the characters that are not allowed in an attribute key or value.
+///
+/// Returns the new text and the number of characters replaced or removed.
+fn sanitize_text(text: &str, is_key: bool) -> (String, usize) {
+    let mut count = 0;
+    let mut s = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '[' => s.push('('),
+            ']' => s.push(')'),
+            '\n' | '\r' => s.push(' '),
+            ':' if is_key => {},
+            c => { s.push(c); continue; },
+        }
+        count += 1;
+    }
+
+    let trimmed = s.trim();
+    count += s.chars().count() - trimmed.chars().count();
+
+    (trimmed.into(), count)
+}
+
+/// Supported metadata types in a note.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+enum Metadata {
+    Tag(Vec<String>),
+    #[allow(clippy::upper_case_acronyms)]
+    KV(String, String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_tag_meta_line() {
+        if let Metadata::Tag(vs) =
+            Note::read_metadata_line(
+                Path::new(""), "@some-tag\n", 1, '@').unwrap()
+        {
+            assert_eq!(vs.len(), 1);
+            assert_eq!(vs[0], "@some-tag");
+        } else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn read_multiple_tags_meta_line() {
+        if let Metadata::Tag(vs)=
+            Note::read_metadata_line(
+                Path::new(""), "@some-tag @other-tag\n", 1, '@').unwrap()
+        {
+            assert_eq!(vs.len(), 2);
+            assert_eq!(vs[0], "@some-tag");
+            assert_eq!(vs[1], "@other-tag");
+        } else {
+            panic!();
+        }
+    }
+
+    #[test]
+    fn tags_must_be_prefixed_with_symbol() {
+        assert!(Note::read_metadata_line(
+            Path::new(""), "@some tag\n", 1, '@').is_err());
+    }
+
+    #[test]
+    fn read_key_value_meta_line() {
+        if let Metadata::KV(k, v) =
+            Note::read_metadata_line(
+                Path::new(""), "[Key: Value]\n", 1, '@').unwrap()
+        {
+            assert_eq!(k, "Key");
+            assert_eq!(v, "Value");
+        }
+    }
+
+    #[test]
+    fn only_one_kv_is_on_a_line() {
+        assert!(Note::read_metadata_line(
+            Path::new(""), "[k:v] [k:v]\n", 1, '@').is_err());
     }
 
     #[test]
@@ -445,6 +2159,53 @@ mod tests {
         assert_eq!(val.content, "Some text.\n");
     }
 
+    #[test]
+    fn read_note_with_empty_header_keeps_metadata_in_content() {
+        let text = "\n@tag\n[key: value]\n\nSome text.\n";
+
+        let val = Note::from_str_with_prefix(text, '+').unwrap();
+        assert!(val.tags.is_empty());
+        assert!(val.map.is_empty());
+        assert_eq!(val.content, "@tag\n[key: value]\n\nSome text.\n");
+        assert_eq!(val.tag_prefix(), '+');
+    }
+
+    #[test]
+    fn content_only_note_round_trip() {
+        use std::{env, fs};
+
+        let text = "@tag\n[key: value]\n\nSome text.\n";
+        let note = Note::content_only(text);
+        assert!(note.tags.is_empty());
+        assert!(note.map.is_empty());
+        assert_eq!(note.content, text);
+
+        let path = env::temp_dir().join("upim_note_content_only.note");
+        note.write_to_file(&path).unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        let read = Note::read_from_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(written, format!("\n{}", text));
+        assert_eq!(read.unwrap(), note);
+        assert_eq!(Note::from_str(&written).unwrap(), note);
+    }
+
+    #[test]
+    fn parse_errors_have_kinds() {
+        let kind = |text: &str| match Note::from_str(text) {
+            Err(FileError::Parse { kind, .. }) => kind,
+            _ => panic!("Expected a parse error: {:?}", text),
+        };
+
+        assert_eq!(kind("@tag @\n\n"), ParseKind::InvalidTag);
+        assert_eq!(kind("@tag other\n\n"), ParseKind::InvalidTag);
+        assert_eq!(kind("[key: value\n\n"), ParseKind::MissingBracket);
+        assert_eq!(kind("[key: [value]]\n\n"), ParseKind::InvalidAttribute);
+        assert_eq!(kind("[no separator]\n\n"), ParseKind::InvalidAttribute);
+        assert_eq!(kind("Some text.\n"), ParseKind::InvalidLine);
+    }
+
     #[test]
     fn fail_to_read_note_with_missing_header() {
         let text = "Some text.\n";
@@ -580,4 +2341,1441 @@ mod tests {
         note.clear_content();
         assert!(note.content().is_empty());
     }
+
+    #[test]
+    fn read_note_with_custom_tag_prefix() {
+        let text = "#tag1 #tag2\n[Key: Value]\n\nSome #content.\n";
+        let note = Note::from_str_with_prefix(text, '#').unwrap();
+
+        assert_eq!(note.tag_prefix(), '#');
+        assert_eq!(note.tags(), ["#tag1".to_string(), "#tag2".to_string()]);
+        assert_eq!(note["Key"], "Value");
+        assert_eq!(note.content(), "Some #content.\n");
+
+        assert!(Note::from_str(text).is_err());
+        assert!(Note::from_str_with_prefix("#tag1 @tag2\n", '#').is_err());
+        assert!(Note::from_str_with_prefix("#\n", '#').is_err());
+    }
+
+    #[test]
+    fn insert_tag_uses_custom_prefix() {
+        let mut note = Note::default().with_tag_prefix('#').unwrap();
+
+        note.insert_tag("tag1");
+        note.insert_tag("#tag2");
+
+        assert_eq!(note.tags(), ["#tag1".to_string(), "#tag2".to_string()]);
+    }
+
+    #[test]
+    fn note_has_all_or_any_tags() {
+        let note = Note::from_str("@work @urgent\n").unwrap();
+
+        assert!(note.has_all_tags(&["@work", "urgent"]));
+        assert!(! note.has_all_tags(&["work", "@home"]));
+        assert!(note.has_all_tags(&[]));
+
+        assert!(note.has_any_tag(&["home", "@urgent"]));
+        assert!(! note.has_any_tag(&["home", "@later"]));
+        assert!(! note.has_any_tag(&[]));
+
+        let note = note.with_tag_prefix('#').unwrap();
+        assert!(note.has_all_tags(&["#work", "urgent"]));
+    }
+
+    #[test]
+    fn change_prefix_of_existing_tags() {
+        let note = Note::from_str("@tag1 @tag2\n").unwrap()
+            .with_tag_prefix('#').unwrap();
+
+        assert_eq!(note.tags(), ["#tag1".to_string(), "#tag2".to_string()]);
+    }
+
+    #[test]
+    fn invalid_tag_prefix_is_err() {
+        for prefix in [' ', '\n', '['] {
+            assert!(matches!(
+                Note::default().with_tag_prefix(prefix),
+                Err(NoteError::InvalidTagPrefix(p)) if p == prefix
+            ));
+            assert!(Note::from_str_with_prefix("#tag\n", prefix).is_err());
+            assert!(Note::from_reader_with_prefix(&b"\n"[..], prefix).is_err());
+        }
+    }
+
+    #[test]
+    fn read_file_with_custom_tag_prefix() {
+        use std::{env, fs};
+
+        let path = env::temp_dir().join("upim_note_read_custom_prefix.txt");
+        fs::write(&path, "#tag1 #tag2\n[Key: Value]\n\nText.\n").unwrap();
+
+        let note = Note::read_from_file_with_prefix(&path, '#');
+        let header = Note::read_header_with_prefix(&path, '#');
+        let valid = Note::validate_header_with_prefix(&path, '#', true);
+        let default = Note::read_from_file(&path);
+        let invalid = Note::validate_header(&path);
+        let _ = fs::remove_file(&path);
+
+        let note = note.unwrap();
+        assert_eq!(note.tags(), ["#tag1", "#tag2"]);
+        assert_eq!(note.tag_prefix(), '#');
+        assert_eq!(note.content(), "Text.\n");
+
+        let header = header.unwrap();
+        assert_eq!(header.tags(), ["#tag1", "#tag2"]);
+        assert!(header.content().is_empty());
+
+        assert!(valid.is_ok());
+        assert!(default.is_err());
+        assert!(invalid.is_err());
+
+        let text = &b"#tag\n\nText.\n"[..];
+        let note = Note::from_reader_with_prefix(text, '#').unwrap();
+        assert_eq!(note.tags(), ["#tag"]);
+        assert_eq!(note, Note::from_str_with_prefix("#tag\n\nText.\n", '#')
+            .unwrap());
+    }
+
+    #[test]
+    fn write_note_with_custom_tag_prefix() {
+        use std::{env, fs};
+
+        let path = env::temp_dir().join("upim_note_custom_tag_prefix.txt");
+
+        let mut note = Note::default().with_tag_prefix('#').unwrap();
+        note.insert_tag("tag1");
+        note.set_attribute("Key", "Value");
+
+        note.write_to_file(&path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(text, "#tag1\n[Key: Value]\n\n");
+        assert_eq!(Note::from_str_with_prefix(&text, '#').unwrap(), note);
+    }
+
+    #[test]
+    fn note_sorted_tags() {
+        let text = "@tag3 @tag1\n@tag2\n";
+        let note = Note::from_str(text).unwrap();
+        let orig = note.clone();
+
+        assert_eq!(note.tags_sorted(), ["@tag1", "@tag2", "@tag3"]);
+        assert_eq!(note, orig);
+        assert_eq!(note.tags(), ["@tag3", "@tag1", "@tag2"]);
+    }
+
+    #[test]
+    fn note_sorted_attributes() {
+        let text = "[b: 2]\n[c: 3]\n[a: 1]\n";
+        let note = Note::from_str(text).unwrap();
+        let orig = note.clone();
+
+        let attrs: Vec<(&str, &str)> = note.attributes_sorted().into_iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+
+        assert_eq!(attrs, [("a", "1"), ("b", "2"), ("c", "3")]);
+        assert_eq!(note, orig);
+    }
+
+    #[test]
+    fn note_sorted_attribute_keys() {
+        let text = "[b: 2]\n[Z: 4]\n[c: 3]\n[a: 1]\n";
+        let note = Note::from_str(text).unwrap();
+
+        assert_eq!(note.attribute_keys_sorted(), ["Z", "a", "b", "c"]);
+        assert!(Note::default().attribute_keys_sorted().is_empty());
+    }
+
+    #[test]
+    fn get_attribute_as_str() {
+        let note = Note::from_str("[Key: Value]\n").unwrap();
+
+        let val: Option<&str> = note.get_str("Key");
+        assert_eq!(val, Some("Value"));
+        assert!(std::ptr::eq(val.unwrap(), note["Key"].as_str()));
+        assert_eq!(note.get_str("Nothing"), None);
+    }
+
+    #[test]
+    fn try_from_file_distinguishes_errors() {
+        use std::{env, fs};
+
+        let dir = env::temp_dir();
+
+        let missing = dir.join("upim_note_try_missing.txt");
+        match Note::try_from_file(&missing) {
+            Err(FileError::IO((file, _))) => assert_eq!(file, missing),
+            res => panic!("Expected an IO error; got {:?}", res),
+        }
+
+        assert!(matches!(Note::try_from_file(&dir), Err(FileError::IO(_))));
+
+        let text = dir.join("upim_note_try_text.txt");
+        fs::write(&text, "Just some text\nwithout a header\n").unwrap();
+        let binary = dir.join("upim_note_try_binary.txt");
+        fs::write(&binary, b"@tag\n\xff\xfe\n").unwrap();
+
+        let text_res = Note::try_from_file(&text);
+        let binary_res = Note::try_from_file(&binary);
+        fs::remove_file(&text).unwrap();
+        fs::remove_file(&binary).unwrap();
+
+        assert!(matches!(text_res, Err(FileError::Parse { .. })));
+        assert!(matches!(binary_res, Err(FileError::Parse { .. })));
+    }
+
+    #[test]
+    fn note_with_attributes_from_pairs() {
+        let pairs = vec![
+            ("Key".to_string(), "Value".to_string()),
+            ("Other".to_string(), "Thing".to_string()),
+        ];
+        let note = Note::with_attributes(pairs);
+
+        assert_eq!(note.attributes_sorted(), [
+            (&"Key".to_string(), &"Value".to_string()),
+            (&"Other".to_string(), &"Thing".to_string()),
+        ]);
+        assert!(note.tags().is_empty());
+        assert!(note.content().is_empty());
+
+        let mut map = HashMap::new();
+        map.insert("Key".to_string(), "Value".to_string());
+        let note = Note::new(&["@tag".to_string()], map, "Text");
+        assert_eq!(note["Key"], "Value");
+    }
+
+    #[test]
+    fn merge_attributes_from_defaults() {
+        let mut note = Note::from_str("\
+            @contact\n\
+            [Name: Favorite Person]\n\
+            [City: Springfield]\n\
+            \n\
+            Some text.\n\
+        ").unwrap();
+        let defaults = Note::from_str("\
+            @contact @unsorted\n\
+            [City: Unknown]\n\
+            [Country: Nowhere]\n\
+            [Phone: 555-0100]\n\
+            [Phone: 555-0199]\n\
+            \n\
+            Template text.\n\
+        ").unwrap();
+
+        note.merge_attributes_from(&defaults);
+
+        assert_eq!(note.tags(), ["@contact", "@unsorted"]);
+        assert_eq!(note["Name"], "Favorite Person");
+        assert_eq!(note["City"], "Springfield");
+        assert_eq!(note["Country"], "Nowhere");
+        assert_eq!(note.get_attribute_all("Phone"), ["555-0100", "555-0199"]);
+        assert_eq!(note.content(), "Some text.\n");
+
+        let plus = Note::from_str_with_prefix("+todo\n", '+').unwrap();
+        note.merge_attributes_from(&plus);
+        assert!(note.contains_tag("@todo"));
+    }
+
+    #[test]
+    fn set_content_from_utf8_bytes() {
+        let mut note = Note::from_str("@tag\n\nOld content.\n").unwrap();
+
+        note.set_content_from_bytes("New — content.\n".as_bytes()).unwrap();
+        assert_eq!(note.content(), "New — content.\n");
+
+        assert_eq!(
+            note.set_content_from_bytes(b"Bad \xe2\x80 bytes"),
+            Err(NoteError::InvalidUtf8 { offset: 4 })
+        );
+        assert_eq!(note.content(), "New — content.\n");
+    }
+
+    #[test]
+    fn borrowed_tag_set_and_attribute_map() {
+        let note = Note::from_str("\
+            @a @b\n\
+            @a @c\n\
+            [Key: Value]\n\
+            [Other: Thing]\n\
+        ").unwrap();
+
+        let tags = note.tags_set();
+        assert_eq!(tags.len(), 3);
+        assert!(["@a", "@b", "@c"].iter().all(|t| tags.contains(t)));
+        assert!(! tags.contains("@d"));
+        assert!(! tags.contains("a"));
+
+        let attrs = note.attributes_map();
+        assert_eq!(attrs.len(), 2);
+        assert_eq!(attrs["Key"], "Value");
+        assert_eq!(attrs.get("Other").map(String::as_str), Some("Thing"));
+        assert!(! attrs.contains_key("Nothing"));
+    }
+
+    #[test]
+    fn get_typed_attribute() {
+        let note = Note::from_str("[Count: 42]\n[Bad: four]\n").unwrap();
+
+        assert_eq!(note.get_attribute_as::<u32>("Count"), Some(Ok(42)));
+        assert_eq!(note.get_attribute_as::<u32>("Missing"), None);
+
+        let err = "four".parse::<u32>().unwrap_err();
+        assert_eq!(note.get_attribute_as::<u32>("Bad"), Some(Err(err)));
+    }
+
+    #[test]
+    fn split_tag_segments() {
+        assert_eq!(
+            Note::tag_segments("@project/alpha/frontend"),
+            ["@project", "alpha", "frontend"]
+        );
+        assert_eq!(Note::tag_segments("@project"), ["@project"]);
+        assert_eq!(Note::tag_segments("@a//b/"), ["@a", "b"]);
+    }
+
+    #[test]
+    fn tags_under_prefix() {
+        let note = Note::from_str(
+            "@project @project/alpha @project/alpha/frontend @projectx @other\n"
+        ).unwrap();
+
+        assert_eq!(
+            note.tags_under("@project"),
+            ["@project", "@project/alpha", "@project/alpha/frontend"]
+        );
+        assert_eq!(
+            note.tags_under("project/alpha"),
+            ["@project/alpha", "@project/alpha/frontend"]
+        );
+        assert_eq!(note.tags_under("@projectx"), ["@projectx"]);
+        assert!(note.tags_under("@proj").is_empty());
+        assert!(note.tags_under("@project/beta").is_empty());
+    }
+
+    #[test]
+    fn validate_attribute_keys() {
+        assert!(Note::is_valid_attribute_key("Key"));
+        assert!(Note::is_valid_attribute_key("Work phone"));
+        assert!(Note::is_valid_attribute_key("URL (home)"));
+
+        assert!(! Note::is_valid_attribute_key(""));
+        assert!(! Note::is_valid_attribute_key(" Key"));
+        assert!(! Note::is_valid_attribute_key("Key\t"));
+        assert!(! Note::is_valid_attribute_key("Key: 1"));
+        assert!(! Note::is_valid_attribute_key("[Key"));
+        assert!(! Note::is_valid_attribute_key("Key]"));
+        assert!(! Note::is_valid_attribute_key("Two\nlines"));
+    }
+
+    #[test]
+    fn validate_attribute_values() {
+        assert!(Note::is_valid_attribute_value("Value"));
+        assert!(Note::is_valid_attribute_value(""));
+        assert!(Note::is_valid_attribute_value("10:30 (UTC)"));
+
+        assert!(! Note::is_valid_attribute_value("[1]"));
+        assert!(! Note::is_valid_attribute_value("a ] b"));
+        assert!(! Note::is_valid_attribute_value("Two\nlines"));
+        assert!(! Note::is_valid_attribute_value("Two\r\nlines"));
+    }
+
+    #[test]
+    fn valid_attributes_round_trip() {
+        use std::{env, fs};
+
+        let mut note = Note::default();
+        let (key, value) = ("Meeting time", "10:30 (UTC)");
+        assert!(Note::is_valid_attribute_key(key));
+        assert!(Note::is_valid_attribute_value(value));
+
+        note.set_attribute(key, value);
+
+        let path = env::temp_dir().join("upim_note_valid_attribute.txt");
+        note.write_to_file(&path).unwrap();
+        let note = Note::read_from_file(&path);
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(note.unwrap()[key], value);
+    }
+
+    #[test]
+    fn sanitize_note_for_round_trip() {
+        let mut note = Note::default();
+        note.insert_tag("two words");
+        note.set_attribute("Key: [1]", "Value [one]\nand two");
+        note.set_attribute("Other", " ]padded[ ");
+        note.set_attribute("Good", "Value");
+
+        assert_eq!(note.sanitize(), 11);
+
+        assert_eq!(note.tags(), ["@two-words"]);
+        assert_eq!(note["Key (1)"], "Value (one) and two");
+        assert_eq!(note["Other"], ")padded(");
+        assert_eq!(note["Good"], "Value");
+
+        let path = std::env::temp_dir().join("upim_note_sanitize.txt");
+        note.write_to_file(&path).unwrap();
+        let read = Note::read_from_file(&path);
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(read.unwrap(), note);
+        assert_eq!(note.sanitize(), 0);
+    }
+
+    #[test]
+    fn match_attribute_values() {
+        let note = Note::from_str("\
+            [Email: me@example.com]\n\
+            [Email: other@example.org]\n\
+            [Work email: work@example.com]\n\
+            [Phone: 555-0100]\n\
+            \n\
+            Content: me@example.com\n\
+        ").unwrap();
+
+        let re = Regex::new(r"@example\.com$").unwrap();
+        assert_eq!(note.attributes_matching(None, &re), [
+            (&"Email".to_string(), &"me@example.com".to_string()),
+            (&"Work email".to_string(), &"work@example.com".to_string()),
+        ]);
+
+        let re = Regex::new("^$").unwrap();
+        assert!(note.attributes_matching(None, &re).is_empty());
+    }
+
+    #[test]
+    fn match_attribute_keys_and_values() {
+        let note = Note::from_str("\
+            [Email: me@example.com]\n\
+            [Email: other@example.org]\n\
+            [Work email: work@example.com]\n\
+            [Phone: 555-0100]\n\
+        ").unwrap();
+
+        let key = Regex::new("^Email$").unwrap();
+        let any = Regex::new("").unwrap();
+        let matched = note.attributes_matching(Some(&key), &any).into_iter()
+            .map(|(_, v)| v.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(matched, ["me@example.com", "other@example.org"]);
+
+        let key = Regex::new("(?i)email").unwrap();
+        let val = Regex::new(r"\.com$").unwrap();
+        let matched = note.attributes_matching(Some(&key), &val).into_iter()
+            .map(|(k, _)| k.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(matched, ["Email", "Work email"]);
+
+        let key = Regex::new("Phone").unwrap();
+        assert!(note.attributes_matching(Some(&key), &val).is_empty());
+    }
+
+    #[test]
+    fn split_content_sections() {
+        let note = Note::content_only("\
+            Some intro.\n\
+            \n\
+            # First\n\
+            Text one.\n\
+            #Second\r\n\
+            ## Nested #\n\
+            Text two.\n\
+            More text.\n\
+            Not # a heading\n\
+        ");
+
+        assert_eq!(note.content_sections(), [
+            (None, "Some intro.\n\n"),
+            (Some("First"), "Text one.\n"),
+            (Some("Second"), ""),
+            (Some("Nested #"), "Text two.\nMore text.\nNot # a heading\n"),
+        ]);
+
+        let note = Note::content_only("# Only heading");
+        assert_eq!(note.content_sections(), [(Some("Only heading"), "")]);
+    }
+
+    #[test]
+    fn split_content_without_headings() {
+        let note = Note::content_only("Line one.\nLine two.\n");
+        assert_eq!(note.content_sections(), [(None, note.content())]);
+
+        assert!(Note::default().content_sections().is_empty());
+    }
+
+    #[test]
+    fn preview_content() {
+        let note = Note::content_only("Café au lait\r\nSecond line\n");
+
+        assert_eq!(note.content_preview(0), "");
+        assert_eq!(note.content_preview(3), "Caf");
+        assert_eq!(note.content_preview(4), "Café");
+        assert_eq!(note.content_preview(5), "Café ");
+        assert_eq!(note.content_preview(1000), note.content());
+        assert_eq!(note.first_line(), "Café au lait");
+
+        let note = Note::content_only("日本語");
+        assert_eq!(note.content_preview(2), "日本");
+        assert_eq!(note.content_preview(3), "日本語");
+        assert_eq!(note.first_line(), "日本語");
+
+        assert_eq!(Note::default().first_line(), "");
+        assert_eq!(Note::default().content_preview(10), "");
+    }
+
+    #[test]
+    fn empty_note_round_trip() {
+        use std::{env, fs};
+
+        let path = env::temp_dir().join("upim_note_empty.txt");
+        Note::default().write_to_file(&path).unwrap();
+        let text = fs::read_to_string(&path);
+        let note = Note::read_from_file(&path);
+        let _ = fs::remove_file(&path);
+
+        let text = text.unwrap();
+        assert_eq!(text, "\n");
+        assert_eq!(note.unwrap(), Note::default());
+        assert_eq!(Note::from_str(&text).unwrap(), Note::default());
+        assert_eq!(Note::from_str("").unwrap(), Note::default());
+        assert_eq!(
+            Note::from_str(&Note::default().to_string()).unwrap(),
+            Note::default()
+        );
+
+        assert!(Note::default().is_empty());
+        assert!(! Note::content_only("\n").is_empty());
+        assert!(! Note::from_str("@tag\n").unwrap().is_empty());
+        assert!(! Note::from_str("[a: ]\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn search_content_with_context() {
+        let note = Note::content_only(
+            "First apple\nsecond\nthird Apple\nfourth\nfifth\nlast apple"
+        );
+
+        let matches = note.search_with_context("apple", 2, 1, false);
+        assert_eq!(matches.len(), 2);
+
+        // The window is cut short at the start of the content.
+        assert_eq!(matches[0].line, 1);
+        assert_eq!(matches[0].text, "First apple");
+        assert!(matches[0].before.is_empty());
+        assert_eq!(matches[0].after, ["second"]);
+
+        // ...and at the end.
+        assert_eq!(matches[1].line, 6);
+        assert_eq!(matches[1].before, ["fourth", "fifth"]);
+        assert!(matches[1].after.is_empty());
+
+        let matches = note.search_with_context("APPLE", 0, 3, true);
+        assert_eq!(
+            matches.iter().map(|m| m.line).collect::<Vec<_>>(),
+            [1, 3, 6]
+        );
+        assert!(matches[1].before.is_empty());
+        assert_eq!(matches[1].after, ["fourth", "fifth", "last apple"]);
+
+        assert!(note.search_with_context("pear", 1, 1, true).is_empty());
+        assert!(Note::default().search_with_context("", 1, 1, false)
+            .is_empty());
+    }
+
+    #[test]
+    fn grouped_tags_round_trip() {
+        use std::{env, fs};
+
+        let text = "@a @b\n@c\n[Key: Value]\n\nSome content.\n";
+        let path = env::temp_dir().join("upim_note_grouped_tags.txt");
+        fs::write(&path, text).unwrap();
+
+        let note = Note::read_from_file(&path).unwrap();
+        note.write_to_file(&path).unwrap();
+        let written = fs::read_to_string(&path);
+
+        let mut note = note;
+        note.remove_tag("@a");
+        note.insert_tag("d");
+        note.write_to_file(&path).unwrap();
+        let changed = fs::read_to_string(&path);
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(written.unwrap(), text);
+        assert_eq!(
+            changed.unwrap(),
+            "@b\n@c\n@d\n[Key: Value]\n\nSome content.\n"
+        );
+
+        // Tag lines are formatting; they do not affect equality.
+        assert_eq!(
+            Note::from_str("@a @b\n").unwrap(),
+            Note::from_str("@a\n@b\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn display_note_round_trip() {
+        let text = "@a @b\n@c\n[Key: Value]\n\n\
+            First paragraph.\n\nSecond paragraph,\nwith two lines.\n";
+
+        let note = Note::from_str(text).unwrap();
+        assert_eq!(note.to_string(), text);
+        assert_eq!(Note::from_str(&note.to_string()).unwrap(), note);
+
+        let mut note = Note::new(
+            &["@x".into(), "@y".into()],
+            vec![("K".into(), "V".into())],
+            "Some text.\n\nMore text.\n"
+        );
+        note.add_attribute("K", "W");
+
+        assert_eq!(
+            note.to_string(),
+            "@x\n@y\n[K: V]\n[K: W]\n\nSome text.\n\nMore text.\n"
+        );
+        assert_eq!(Note::from_str(&note.to_string()).unwrap(), note);
+    }
+
+    #[test]
+    fn multi_valued_attributes() {
+        let mut note = Note::from_str("\
+            [Phone: 555-0100]\n\
+            [Name: Someone]\n\
+            [Phone: 555-0199]\n\
+        ").unwrap();
+
+        assert_eq!(note.get_attribute_all("Phone"), ["555-0100", "555-0199"]);
+        assert_eq!(note.get_attribute_all("Name"), ["Someone"]);
+        assert!(note.get_attribute_all("Email").is_empty());
+
+        note.add_attribute("Phone", "555-0142");
+        note.set_attribute("Phone", "555-0000");
+        assert_eq!(note["Phone"], "555-0000");
+        assert_eq!(note.get_attribute("Phone").unwrap(), "555-0000");
+        assert_eq!(
+            note.get_attribute_all("Phone"),
+            ["555-0000", "555-0199", "555-0142"]
+        );
+
+        let note = Note::from_str(&note.to_string()).unwrap();
+        assert_eq!(note.get_attribute_all("Phone").len(), 3);
+    }
+
+    #[test]
+    fn reader_and_writer_round_trip() {
+        use std::io::Cursor;
+
+        let note = Note::from_str("\
+            @a @b\n\
+            [Key: Value]\n\
+            [Key: Other]\n\
+            \n\
+            Some text.\n\
+            \n\
+            More text.\n\
+        ").unwrap();
+
+        let mut buf = Cursor::new(vec![]);
+        note.to_writer(&mut buf).unwrap();
+        assert_eq!(buf.get_ref().as_slice(), note.to_string().as_bytes());
+
+        buf.set_position(0);
+        assert_eq!(Note::from_reader(&mut buf).unwrap(), note);
+
+        let err = Note::from_reader(Cursor::new(b"No header\n")).unwrap_err();
+        assert!(matches!(err, FileError::Parse { line: 1, .. }));
+
+        let err = Note::from_reader(Cursor::new(b"\n\xff\n")).unwrap_err();
+        assert!(matches!(err, FileError::IO(_)));
+    }
+
+    #[test]
+    fn errors_name_the_file() {
+        use std::{env, fs};
+
+        let path = env::temp_dir().join("upim_note_error_path.txt");
+        fs::write(&path, "@tag\nnot metadata\n\nText\n").unwrap();
+        let invalid = Note::validate_header(&path);
+
+        fs::write(&path, b"@tag\n\n\xff\n").unwrap();
+        let not_utf8 = Note::read_from_file(&path);
+        let _ = fs::remove_file(&path);
+
+        let missing = Note::read_header(&path);
+
+        match invalid {
+            Err(ref e @ FileError::Parse { ref file, .. }) => {
+                assert_eq!(file, &path);
+                assert!(e.to_string().contains(&*path.to_string_lossy()));
+            },
+            res => panic!("Expected a parse error: {:?}", res),
+        }
+        for res in [not_utf8, missing] {
+            assert!(matches!(res, Err(FileError::IO((f, _))) if f == path));
+        }
+
+        // There is no file to name.
+        match Note::from_str("not metadata\n") {
+            Err(FileError::Parse { file, .. }) => assert!(file.as_os_str()
+                .is_empty()),
+            res => panic!("Expected a parse error: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn failed_write_keeps_original() {
+        use std::{env, fs, io};
+
+        let dir = env::temp_dir().join("upim_note_atomic_write");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("note.txt");
+        fs::write(&path, "@original\n\nText.\n").unwrap();
+
+        // Fail after writing part of a large note.
+        let content = "x".repeat(1 << 20);
+        let res = write_atomically(&path, |w| {
+            w.write_all(content.as_bytes())?;
+            Err(io::ErrorKind::WriteZero.into())
+        });
+
+        let kept = fs::read_to_string(&path);
+        let files = fs::read_dir(&dir).unwrap().count();
+
+        let note = Note::from_str("@new\n\nNew text.\n").unwrap();
+        let written = note.write_to_file(&path);
+        let read = Note::read_from_file(&path);
+        let files_after = fs::read_dir(&dir).unwrap().count();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(res.is_err());
+        assert_eq!(kept.unwrap(), "@original\n\nText.\n");
+        assert_eq!(files, 1);
+
+        written.unwrap();
+        assert_eq!(read.unwrap(), note);
+        assert_eq!(files_after, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_through_symlink() {
+        use std::{env, fs, os::unix::fs::symlink};
+
+        let dir = env::temp_dir().join("upim_note_symlink_write");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("real")).unwrap();
+
+        let real = dir.join("real/note.txt");
+        let link = dir.join("link.txt");
+        fs::write(&real, "@original\n\nText.\n").unwrap();
+        symlink(&real, &link).unwrap();
+
+        let note = Note::from_str("@new\n\nNew text.\n").unwrap();
+        let written = note.write_to_file(&link);
+
+        let is_link = fs::symlink_metadata(&link)
+            .map(|m| m.file_type().is_symlink());
+        let read = Note::read_from_file(&real);
+        let files = fs::read_dir(dir.join("real")).unwrap().count();
+        let _ = fs::remove_dir_all(&dir);
+
+        written.unwrap();
+        assert!(is_link.unwrap());
+        assert_eq!(read.unwrap(), note);
+        assert_eq!(files, 1);
+    }
+
+    #[test]
+    fn attribute_order_round_trip() {
+        use std::{env, fs};
+
+        let text = "[Zeta: 1]\n[Alpha: 2]\n[Mid: 3]\n[Alpha: 4]\n[Beta: 5]\n\n";
+        let path = env::temp_dir().join("upim_note_attribute_order.txt");
+        fs::write(&path, text).unwrap();
+
+        let note = Note::read_from_file(&path).unwrap();
+        note.write_to_file(&path).unwrap();
+        let read = Note::read_from_file(&path);
+        let _ = fs::remove_file(&path);
+
+        let keys = |n: &Note| n.attributes()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>();
+
+        let read = read.unwrap();
+        assert_eq!(keys(&read), keys(&note));
+        assert_eq!(keys(&read), ["Zeta=1", "Alpha=2", "Alpha=4", "Mid=3",
+            "Beta=5"]);
+
+        let mut note = read;
+        note.remove_attribute("Zeta");
+        note.set_attribute("Gamma", "6");
+        note.set_attribute("Mid", "7");
+        assert_eq!(
+            note.attribute_keys().collect::<Vec<_>>(),
+            ["Alpha", "Mid", "Beta", "Gamma"]
+        );
+    }
+
+    #[test]
+    fn header_only_note_round_trip() {
+        use std::{env, fs};
+
+        let path = env::temp_dir().join("upim_note_header_only.txt");
+        let note = Note::from_str("@tag\n[Key: Value]").unwrap();
+        note.write_to_file(&path).unwrap();
+        let read = Note::read_from_file(&path);
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(read.unwrap(), note);
+
+        let note = Note::content_only("\nLeading blank line\n");
+        note.write_to_file(&path).unwrap();
+        let read = Note::read_from_file(&path);
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(read.unwrap(), note);
+    }
+
+    #[test]
+    fn build_note() {
+        let note = NoteBuilder::new()
+            .tag("a")
+            .tag("@b")
+            .tag("a")
+            .attribute("Phone", "555-0100")
+            .attribute("Phone", "555-0199")
+            .content("Old")
+            .content("Text\n")
+            .build()
+            .unwrap();
+
+        assert_eq!(note.tags(), ["@a", "@b"]);
+        assert_eq!(note.get_attribute_all("Phone"), ["555-0100", "555-0199"]);
+        assert_eq!(note.content(), "Text\n");
+        assert_eq!(NoteBuilder::new().build().unwrap(), Note::default());
+
+        let res = NoteBuilder::new()
+            .attribute("Time: 10", "AM")
+            .attribute("Key", "[Value]")
+            .build();
+        assert_eq!(res, Err(NoteError::InvalidAttribute {
+            key: "Time: 10".into(),
+            value: "AM".into(),
+        }));
+
+        assert!(NoteBuilder::new().attribute("", "x").build().is_err());
+        assert!(NoteBuilder::new().attribute("Key", "[x]").build().is_err());
+    }
+
+    #[test]
+    fn rename_tags() {
+        let mut note = Note::from_str("@a @b\n@c\n").unwrap();
+
+        assert!(note.rename_tag("b", "@x"));
+        assert_eq!(note.tags(), ["@a", "@x", "@c"]);
+        assert_eq!(note.to_string(), "@a @x\n@c\n\n");
+
+        assert!(! note.rename_tag("@missing", "y"));
+        assert!(note.rename_tag("a", "a"));
+        assert_eq!(note.tags(), ["@a", "@x", "@c"]);
+
+        // The new tag is already present.
+        assert!(note.rename_tag("@a", "@c"));
+        assert_eq!(note.tags(), ["@x", "@c"]);
+    }
+
+    #[test]
+    fn rename_attribute_keys() {
+        let mut note = Note::from_str("\
+            [Phone: 555-0100]\n\
+            [Name: Someone]\n\
+            [Phone: 555-0199]\n\
+            [Email: a@example.com]\n\
+        ").unwrap();
+
+        assert!(note.rename_attribute_key("Phone", "Work Phone"));
+        assert!(! note.contains_attribute("Phone"));
+        assert_eq!(
+            note.attribute_keys().collect::<Vec<_>>(),
+            ["Work Phone", "Name", "Email"]
+        );
+        assert_eq!(
+            note.get_attribute_all("Work Phone"),
+            ["555-0100", "555-0199"]
+        );
+
+        assert!(! note.rename_attribute_key("Missing", "Other"));
+        assert!(! note.contains_attribute("Other"));
+
+        // The existing key is overwritten.
+        assert!(note.rename_attribute_key("Email", "Name"));
+        assert_eq!(note.get_attribute_all("Name"), ["a@example.com"]);
+        assert_eq!(
+            note.attribute_keys().collect::<Vec<_>>(),
+            ["Work Phone", "Name"]
+        );
+        assert!(note.rename_attribute_key("Name", "Name"));
+        assert_eq!(note["Name"], "a@example.com");
+    }
+
+    #[test]
+    fn set_and_push_content() {
+        let mut note = Note::from_str("@tag\n\nOld text.\n").unwrap();
+
+        note.set_content("New text.");
+        assert_eq!(note.content(), "New text.");
+
+        note.push_content(" More.");
+        note.append_content("Last line.\n");
+        assert_eq!(note.content(), "New text. More.\nLast line.\n");
+
+        note.content_mut().truncate(9);
+        assert_eq!(note.content(), "New text.");
+        assert_eq!(note.tags(), ["@tag"]);
+
+        note.clear_content();
+        note.push_content("Text");
+        assert_eq!(note.content(), "Text");
+    }
+
+    #[test]
+    fn map_note_content() {
+        let mut note = Note::from_str("@tag\n\nSome content.\n").unwrap();
+        note.map_content(str::to_uppercase);
+
+        assert_eq!(note.content(), "SOME CONTENT.\n");
+        assert!(note.contains_tag("@tag"));
+    }
+
+    #[test]
+    fn map_note_attributes() {
+        let mut note = Note::from_str("\
+            [Phone: 555-0100]\n\
+            [Phone: 555-0199]\n\
+            [Email: me@example.com]\n\
+            [Fax: 555-0142]\n\
+            [Drop: first]\n\
+            [Drop: keep]\n\
+        ").unwrap();
+
+        note.map_attributes(|k, v| match k {
+            "Fax" => None,
+            "Drop" if v == "first" => None,
+            "Phone" => Some(v.replace("555-", "+1 555-")),
+            _ => Some(v.to_uppercase()),
+        });
+
+        assert_eq!(note.attributes_sorted(), [
+            (&"Drop".to_string(), &"KEEP".to_string()),
+            (&"Email".to_string(), &"ME@EXAMPLE.COM".to_string()),
+            (&"Phone".to_string(), &"+1 555-0100".to_string()),
+            (&"Phone".to_string(), &"+1 555-0199".to_string()),
+        ]);
+        assert_eq!(note["Drop"], "KEEP");
+        assert_eq!(note["Phone"], "+1 555-0100");
+        assert!(note.get_attribute("Fax").is_none());
+    }
+
+    #[test]
+    fn append_content() {
+        let mut note = Note::from_str("@tag\n\nLine one.").unwrap();
+
+        note.append_content("Line two.\n");
+        assert_eq!(note.content(), "Line one.\nLine two.\n");
+
+        note.append_content("Line three.\n");
+        assert_eq!(note.content(), "Line one.\nLine two.\nLine three.\n");
+
+        let mut note = Note::default();
+        note.append_content("Text");
+        assert_eq!(note.content(), "Text");
+    }
+
+    #[test]
+    fn count_tags_in_collection() {
+        use std::{env, fs};
+
+        let dir = env::temp_dir().join("upim_note_tag_counts");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+
+        fs::write(dir.join("a.txt"), "@work @urgent\n\nText @home\n")
+            .unwrap();
+        fs::write(dir.join("b.txt"), "@work\n@work @home\n").unwrap();
+        fs::write(dir.join("sub/c.txt"), "@home\n[key: value]\n").unwrap();
+        fs::write(dir.join("sub/d.txt"), "\nNo tags.\n").unwrap();
+        fs::write(dir.join("e.txt"), "Not a note\n").unwrap();
+        fs::write(dir.join(".e.txt.lock"), "").unwrap();
+
+        let counts = tag_counts(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let (counts, skipped) = counts.unwrap();
+
+        assert_eq!(skipped.len(), 1);
+        assert!(matches!(&skipped[0],
+            FileError::Parse { file, .. } if *file == dir.join("e.txt")
+        ));
+
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts["@work"], 2);
+        assert_eq!(counts["@home"], 2);
+        assert_eq!(counts["@urgent"], 1);
+
+        assert!(tag_counts(Path::new("/nonexistent/upim")).is_err());
+    }
+
+    #[test]
+    fn rename_tag_across_collection() {
+        use std::{env, fs};
+
+        let dir = env::temp_dir().join("upim_note_rename_tag");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+
+        fs::write(dir.join("a.txt"), "@todo @work\n\nText @todo\n").unwrap();
+        fs::write(dir.join("b.txt"), "@task @todo\n").unwrap();
+        fs::write(dir.join("sub/c.txt"), "@home\n[key: value]\n").unwrap();
+
+        let count = rename_tag_in_collection(&dir, "@todo", "task");
+        let a = Note::read_from_file(&dir.join("a.txt")).unwrap();
+        let b = Note::read_from_file(&dir.join("b.txt")).unwrap();
+        let c = fs::read_to_string(dir.join("sub/c.txt")).unwrap();
+        let files = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(count.unwrap(), 2);
+        assert_eq!(a.tags(), ["@task", "@work"]);
+        assert_eq!(a.content(), "Text @todo\n");
+        assert_eq!(b.tags(), ["@task"]);
+        assert_eq!(c, "@home\n[key: value]\n");
+        assert_eq!(files, 3);
+
+        assert!(
+            rename_tag_in_collection(Path::new("/nonexistent/upim"), "a", "b")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rename_tag_with_invalid_file_changes_nothing() {
+        use std::{env, fs};
+
+        let dir = env::temp_dir().join("upim_note_rename_tag_invalid");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        fs::write(dir.join("a.txt"), "@todo\n\nText.\n").unwrap();
+        fs::write(dir.join("b.txt"), "Not a note\n").unwrap();
+        fs::write(dir.join("c.txt"), "@todo @work\n").unwrap();
+
+        let res = rename_tag_in_collection(&dir, "todo", "task");
+        let a = fs::read_to_string(dir.join("a.txt")).unwrap();
+        let c = fs::read_to_string(dir.join("c.txt")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        match res {
+            Err(FileError::Parse { file, .. }) =>
+                assert_eq!(file, dir.join("b.txt")),
+            res => panic!("Expected a parse error: {:?}", res),
+        }
+        assert_eq!(a, "@todo\n\nText.\n");
+        assert_eq!(c, "@todo @work\n");
+    }
+
+    #[test]
+    fn diff_notes() {
+        let old = Note::from_str("\
+            @a @b\n\
+            [Key: Value]\n\
+            [Old: Thing]\n\
+            [Same: Value]\n\
+            \n\
+            First line.\n\
+            Second line.\n\
+            Last line.\n\
+        ").unwrap();
+        let new = Note::from_str("\
+            @b @c\n\
+            [Key: New Value]\n\
+            [New: Thing]\n\
+            [Same: Value]\n\
+            \n\
+            First line.\n\
+            Changed line.\n\
+            Added line.\n\
+            Last line.\n\
+        ").unwrap();
+
+        let changes = old.diff(&new);
+        assert_eq!(changes, [
+            NoteChange::TagRemoved("@a".into()),
+            NoteChange::TagAdded("@c".into()),
+            NoteChange::AttributeChanged(
+                "Key".into(), "Value".into(), "New Value".into()
+            ),
+            NoteChange::AttributeAdded("New".into(), "Thing".into()),
+            NoteChange::AttributeRemoved("Old".into(), "Thing".into()),
+            NoteChange::ContentChanged {
+                removed: vec!["Second line.".into()],
+                added: vec!["Changed line.".into(), "Added line.".into()],
+            },
+        ]);
+
+        let text = changes.iter().map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(text, "\
+            -@a\n\
+            +@c\n\
+            -[Key: Value]\n\
+            +[Key: New Value]\n\
+            +[New: Thing]\n\
+            -[Old: Thing]\n\
+            -Second line.\n\
+            +Changed line.\n\
+            +Added line.\
+        ");
+
+        assert!(old.diff(&old.clone()).is_empty());
+    }
+
+    #[test]
+    fn read_header_and_content_offset() {
+        use std::{env, fs};
+
+        let path = env::temp_dir().join("upim_note_header_offset.txt");
+        fs::write(&path, "@tag\n[key: value]\n\nContent.\n").unwrap();
+
+        let res = Note::read_header_with_offset(&path);
+        fs::remove_file(&path).unwrap();
+
+        let (note, offset) = res.unwrap();
+        assert_eq!(offset, 19);
+        assert_eq!(note.tags(), ["@tag"]);
+        assert!(note.content().is_empty());
+    }
+
+    #[test]
+    fn read_content_window() {
+        use std::{env, fs, io::ErrorKind};
+
+        let path = env::temp_dir().join("upim_note_content_range.txt");
+        fs::write(&path, "@tag\n\nSome café content.\n").unwrap();
+
+        let middle = Note::read_content_range(&path, 5, 5);
+        let past_end = Note::read_content_range(&path, 13, 100);
+        let at_end = Note::read_content_range(&path, 20, 10);
+        let split_start = Note::read_content_range(&path, 9, 6);
+        let split_end = Note::read_content_range(&path, 5, 4);
+        let out_of_range = Note::read_content_range(&path, 21, 1);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(middle.unwrap(), "café");
+        assert_eq!(past_end.unwrap(), "ntent.\n");
+        assert_eq!(at_end.unwrap(), "");
+        assert_eq!(split_start.unwrap(), " cont");
+        assert_eq!(split_end.unwrap(), "caf");
+
+        match out_of_range {
+            Err(FileError::IO((p, kind))) => {
+                assert_eq!(p, path);
+                assert_eq!(kind, ErrorKind::InvalidInput);
+            },
+            _ => panic!("Expected an out-of-range error"),
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn read_content_via_mmap() {
+        use std::{env, fs};
+
+        let path = env::temp_dir().join("upim_note_mmap_content.txt");
+        let mut text = String::from("@tag\n[key: value]\n\n");
+        for i in 0..100_000 {
+            text.push_str(&format!("Line {} of the content — ü\n", i));
+        }
+        fs::write(&path, &text).unwrap();
+
+        let empty = env::temp_dir().join("upim_note_mmap_empty.txt");
+        fs::write(&empty, "@tag\n\n").unwrap();
+
+        let mapped = Note::open_content_mmap(&path);
+        let read = Note::read_from_file(&path);
+        let empty_res = Note::open_content_mmap(&empty);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&empty).unwrap();
+
+        let (note, content) = mapped.unwrap();
+        let read = read.unwrap();
+
+        assert!(content.is_mapped());
+        assert_eq!(&*content, read.content());
+        assert_eq!(note.tags(), read.tags());
+        assert_eq!(note["key"], "value");
+
+        let (_, content) = empty_res.unwrap();
+        assert!(! content.is_mapped());
+        assert_eq!(content.as_str(), "");
+    }
+
+    #[test]
+    fn note_word_frequencies() {
+        let text = "\
+        @tag\n\
+        [key: value]\n\
+        \n\
+        The cat saw the other cat.\n\
+        THE END -- 'the' end, isn't it?\n\
+        ";
+        let note = Note::from_str(text).unwrap();
+        let words = note.word_frequencies();
+
+        assert_eq!(words["the"], 4);
+        assert_eq!(words["cat"], 2);
+        assert_eq!(words["end"], 2);
+        assert_eq!(words["isn't"], 1);
+        assert_eq!(words.len(), 7);
+        assert!(! words.contains_key("tag"));
+        assert!(Note::default().word_frequencies().is_empty());
+    }
+
+    #[test]
+    fn note_stats() {
+        let text = "\
+        @tag1 @tag2\n\
+        @tag3\n\
+        [a: 1]\n\
+        [b: 2]\n\
+        \n\
+        Line one.\n\
+        Line two.\n\
+        ";
+        let note = Note::from_str(text).unwrap();
+
+        assert_eq!(note.stats(), NoteStats {
+            tags: 3,
+            attributes: 2,
+            content_bytes: 20,
+            content_lines: 2,
+        });
+        assert_eq!(Note::default().stats(), NoteStats::default());
+    }
+
+    #[test]
+    fn repeated_tags_strict_and_lenient() {
+        let text = "@a @b @a\n[Key: Value]\n@c @b\n\nText\n";
+
+        match Note::from_str_strict(text) {
+            Err(FileError::Parse { kind, msg, data, line, .. }) => {
+                assert_eq!(kind, ParseKind::DuplicateTag);
+                assert_eq!(msg, "Duplicate tags: @a, @b");
+                assert_eq!(data, "@a @b");
+                assert_eq!(line, 1);
+            },
+            res => panic!("Expected a duplicate tag error: {:?}", res),
+        }
+
+        let note = Note::from_str(text).unwrap();
+        assert_eq!(note.tags(), ["@a", "@b", "@c"]);
+        assert_eq!(note["Key"], "Value");
+        assert_eq!(note.content(), "Text\n");
+
+        let strict = Note::from_str_strict("@a @b\n@c\n\nText\n").unwrap();
+        assert_eq!(strict.tags(), ["@a", "@b", "@c"]);
+    }
+
+    #[test]
+    fn parse_named_string() {
+        let name = Path::new("fetched/note.txt");
+        let text = "@tag\n[a: 1]\nnot metadata\n\nText\n";
+
+        match Note::from_str_named(text, name) {
+            Err(FileError::Parse { file, line, .. }) => {
+                assert_eq!(file, name);
+                assert_eq!(line, 3);
+            },
+            res => panic!("Expected a parse error: {:?}", res),
+        }
+
+        match Note::from_str(text) {
+            Err(FileError::Parse { file, .. }) => {
+                assert_eq!(file, Path::new(""));
+            },
+            res => panic!("Expected a parse error: {:?}", res),
+        }
+
+        let err = Note::from_str_named(text, name).unwrap_err();
+        assert!(err.to_string().contains("fetched/note.txt"));
+
+        let note = Note::from_str_named("@tag\n\nText\n", name).unwrap();
+        assert!(note.contains_tag("@tag"));
+        assert_eq!(note.content(), "Text\n");
+    }
+
+    #[test]
+    fn parse_errors_report_file_line() {
+        use std::{env, fs};
+
+        let error_line = |res: Result<Note, FileError>| match res {
+            Err(FileError::Parse { line, .. }) => line,
+            res => panic!("Expected a parse error: {:?}", res),
+        };
+
+        let text = "@tag\n[a: 1]\n@other\nnot metadata\n\nText\n";
+        let path = env::temp_dir().join("upim_note_error_line.txt");
+        fs::write(&path, text).unwrap();
+
+        let from_str = Note::from_str(text);
+        let from_file = Note::read_from_file(&path);
+        let header = Note::read_header(&path);
+        let validated = Note::validate_header(&path);
+
+        // A final one-character line used to end the header silently.
+        let short = "@tag\n[a: 1]\n@b\nx";
+        fs::write(&path, short).unwrap();
+        let short_from_file = Note::read_from_file(&path);
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(error_line(from_str), 4);
+        assert_eq!(error_line(from_file), 4);
+        assert_eq!(error_line(header), 4);
+        assert!(matches!(validated, Err(FileError::Parse { line: 4, .. })));
+
+        assert_eq!(error_line(Note::from_str(short)), 4);
+        assert_eq!(error_line(short_from_file), 4);
+
+        let note = Note::from_str("@tag\n[a: 1]").unwrap();
+        assert_eq!(note["a"], "1");
+        assert!(note.content().is_empty());
+    }
+
+    #[test]
+    fn collect_all_header_errors() {
+        use std::{env, fs};
+
+        let path = env::temp_dir().join("upim_note_header_errors.txt");
+        fs::write(&path, "@tag\nbad\n[a: 1]\n[a: 2]\n@x tag\n\nbad\n")
+            .unwrap();
+
+        let lenient = Note::header_errors(&path, false);
+        let strict = Note::header_errors(&path, true);
+        let _ = fs::remove_file(&path);
+
+        let lines = |errs: Vec<FileError>| errs.iter()
+            .map(|e| match e {
+                FileError::Parse { line, .. } => *line,
+                _ => panic!("Expected a parse error"),
+            })
+            .collect::<Vec<u32>>();
+
+        assert_eq!(lines(lenient.unwrap()), [2, 5]);
+        assert_eq!(lines(strict.unwrap()), [2, 4, 5]);
+        assert!(Note::header_errors(Path::new("/nonexistent"), false).is_err());
+    }
+
+    #[test]
+    fn validate_duplicate_attribute_keys() {
+        use std::{env, fs};
+
+        let path = env::temp_dir().join("upim_note_duplicate_keys.txt");
+        fs::write(&path, "@tag\n[Key: 1]\n[Other: 2]\n[Key: 3]\n\nText\n")
+            .unwrap();
+
+        let lenient = Note::validate_header(&path);
+        let strict = Note::validate_header_with(&path, true);
+        let note = Note::read_from_file(&path);
+        let _ = fs::remove_file(&path);
+
+        assert!(lenient.is_ok());
+        match strict {
+            Err(FileError::Parse { kind, msg, data, line, .. }) => {
+                assert_eq!(kind, ParseKind::DuplicateKey);
+                assert!(msg.contains("'Key'"));
+                assert!(msg.contains("line 2"));
+                assert_eq!(data, "[Key: 3]");
+                assert_eq!(line, 4);
+            },
+            _ => panic!("Expected a duplicate key error"),
+        }
+
+        let note = note.unwrap();
+        assert_eq!(note.duplicate_attribute_keys(), vec!["Key".to_string()]);
+        assert_eq!(note["Key"], "1");
+    }
+
+    #[test]
+    fn lint_clean_note() {
+        let text = "@tag @other\n[A: 1]\n[b: 2]\n[C: 3]\n\nSome content\n";
+        assert!(Note::lint(text).is_empty());
+        assert!(Note::lint("@tag\n").is_empty());
+        assert!(Note::lint("").is_empty());
+    }
+
+    #[test]
+    fn lint_ungrouped_tags() {
+        let warnings = Note::lint("@tag\n[a: 1]\n@other\n@third\n\nText\n");
+
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].kind, LintKind::UngroupedTags);
+        assert_eq!(warnings[0].line, 3);
+        assert_eq!(warnings[1].line, 4);
+        assert!(warnings[0].message.contains("line 1"));
+    }
+
+    #[test]
+    fn lint_unsorted_attributes() {
+        let warnings = Note::lint("[b: 1]\n[A: 2]\n[c: 3]\n\nText\n");
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, LintKind::UnsortedAttributes);
+        assert_eq!(warnings[0].line, 2);
+        assert!(warnings[0].message.contains("'A'"));
+    }
+
+    #[test]
+    fn lint_trailing_whitespace() {
+        let warnings = Note::lint("[a: 1 ]\n[b: 2]\n[c: 3\t]\n\nText\n");
+
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].kind, LintKind::TrailingWhitespace);
+        assert_eq!(warnings[0].line, 1);
+        assert_eq!(warnings[1].line, 3);
+    }
+
+    #[test]
+    fn lint_extra_blank_lines() {
+        let warnings = Note::lint("@tag\n[a: 1]\n\n\n\nText\n");
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, LintKind::ExtraBlankLines);
+        assert_eq!(warnings[0].line, 4);
+        assert_eq!(
+            warnings[0].to_string(),
+            "Only one blank line should separate the header and content \
+                at line 4"
+        );
+    }
+
+    #[test]
+    fn strict_validation_accepts_unique_keys() {
+        use std::{env, fs};
+
+        let path = env::temp_dir().join("upim_note_unique_keys.txt");
+        fs::write(&path, "[Key: 1]\n[Other: 2]\n\n[Key: 3]\n").unwrap();
+
+        let strict = Note::validate_header_with(&path, true);
+        let _ = fs::remove_file(&path);
+
+        assert!(strict.is_ok());
+    }
 }